@@ -1,6 +1,18 @@
-use std::path::PathBuf;
+use std::{io::{Error, ErrorKind}, num::NonZeroUsize, path::{Path, PathBuf}};
 
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::{
+    bank::Bank,
+    diagnostic::{DiagnosticCode, WarningLevel, WarningLevels},
+    export::{
+        AssemblyExporter, CExporter, Exporter, IntelHexExporter, JsonExporter, MidiExporter,
+        PythonExporter, RawExporter, RustExporter, SRecordExporter, C_BYTES_PER_LINE
+    },
+    formatter::DEFAULT_WIDTH,
+    player::DEFAULT_SAMPLE_RATE,
+    target::Target
+};
 
 #[derive(Debug, Clone, Copy, Default, ValueEnum, PartialEq, Eq)]
 pub enum ExportType {
@@ -8,50 +20,393 @@ pub enum ExportType {
     #[default]
     Code,
     /// Raw binary
-    Raw
+    Raw,
+    /// Standard MIDI File, with loops and macros expanded
+    Midi,
+    /// Rust static array
+    Rust,
+    /// GNU assembler `.byte` listing
+    Asm,
+    /// Python bytes constant
+    Python,
+    /// JSON document with the bytes and the header offsets
+    Json,
+    /// Intel HEX
+    Ihex,
+    /// Motorola S-record
+    Srec
 }
 
 /// A Compiler to convert MMML files to C source data files.
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub compile: Option<CompilerArgs>
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Convert compiled µMML data back into µMML source code
+    Disassemble(DisassembleArgs),
+    /// Render a song to a WAV file with the built-in 1-bit synthesizer
+    Render(RenderArgs),
+    /// Convert a file from another format to µMML source code
+    Import(ImportArgs),
+    /// Check a song for errors without writing any output
+    Check(CheckArgs),
+    /// Reformat source code, keeping its comments
+    Fmt(FmtArgs),
+    /// Check a song for useless commands and likely mistakes
+    Lint(LintArgs),
+    /// Compile several songs into one bank with a song table
+    Bank(BankArgs)
+}
+
+#[derive(Args, Debug)]
 pub struct CompilerArgs {
     /// Input source code.
+    // clap leaves the group of a struct with flattened fields empty, the input path tells when the options are given.
+    #[arg(group = "CompilerArgs")]
     pub input_path: PathBuf,
     /// Output file
     #[arg(short, long)]
     output_path: Option<PathBuf>,
-    /// Export type (C code or raw binary data)
+    /// Export type
     #[arg(short, long)]
     pub export_type: ExportType,
+    /// Address the data is loaded at, for Intel HEX and S-record exports
+    #[arg(long, value_parser = parse_address, default_value = "0")]
+    pub base_address: u32,
+    #[command(flatten)]
+    c_array: CArrayArgs,
+    /// Define NAME_SIZE in the header
+    #[arg(long, action, help_heading = "C output")]
+    size_define: bool,
+    /// Define the offset of each channel and macro in the header
+    #[arg(long, action, help_heading = "C output")]
+    offset_defines: bool,
     /// Music name in the output file
     #[arg(short, long)]
     music_name: Option<String>,
+    #[command(flatten)]
+    pub song: SongArgs,
+    /// Stop the compilation after this number of errors
+    #[arg(long)]
+    pub max_errors: Option<NonZeroUsize>,
+    /// Remove octave and volume commands that change nothing, merge adjacent rests and drop empty loops
+    #[arg(short = 'O', long, action)]
+    pub optimize: bool,
+    /// Factor repeated phrases into loops and new macros to shrink the output
+    #[arg(short = 'z', long, action)]
+    pub compress: bool,
     /// Output more info (Debug purpuses only)
     #[clap(short, long, action)]
     pub verbose: bool
 }
 
 impl CompilerArgs {
-    pub fn get_output_path(&self) -> PathBuf {
-        self.output_path.clone().unwrap_or(self.input_path.with_extension(
-            match self.export_type {
-                ExportType::Code => "c",
-                ExportType::Raw => "mbf" // µMML Binary File
-            }
-        ))
+    /// Output file, named after the input file with the extension of the exporter when not set.
+    pub fn get_output_path(&self, exporter: &dyn Exporter) -> PathBuf {
+        self.output_path.clone().unwrap_or(self.input_path.with_extension(exporter.extension()))
+    }
+
+    /// Exporter of the export type, the target giving the number of channels.
+    pub fn get_exporter(&self, target: &Target) -> Box<dyn Exporter> {
+        let mut c_exporter: CExporter = CExporter::new();
+        self.c_array.configure(&mut c_exporter);
+        c_exporter.set_size_define(self.size_define);
+        c_exporter.set_offset_defines(self.offset_defines);
+        c_exporter.set_channels(target.channels);
+        exporter(self.export_type, self.base_address, target, c_exporter)
     }
 
-    pub fn get_music_name(&self) -> String {
+    pub fn get_music_name(&self, exporter: &dyn Exporter) -> String {
         if let Some(name) = &self.music_name {
             return name.clone();
         }
-        if let Some(file_name) = self.get_output_path().to_str() {
+        if let Some(file_name) = self.get_output_path(exporter).to_str() {
             let file: Vec<&str> = file_name.split('.').collect();
             return file[0]
                 .to_string()
                 .to_uppercase()
-                .replace(&['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'], "");
+                .replace(['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'], "");
         }
         String::from("Music")
     }
 }
+
+/// Options of the C array, shared by the songs and the banks.
+#[derive(Args, Debug)]
+pub struct CArrayArgs {
+    /// Store the C array in the AVR program memory with PROGMEM
+    #[arg(long, action, help_heading = "C output")]
+    progmem: bool,
+    /// Place the C array in a linker section
+    #[arg(long, value_name = "NAME", help_heading = "C output")]
+    section: Option<String>,
+    /// Use uint8_t from <stdint.h> instead of unsigned char
+    #[arg(long, action, help_heading = "C output")]
+    stdint: bool,
+    /// Make the C array static, the header no longer declaring it
+    #[arg(long = "static", action, help_heading = "C output")]
+    is_static: bool,
+    /// Number of bytes per line of the C array
+    #[arg(long, default_value_t = C_BYTES_PER_LINE, help_heading = "C output")]
+    bytes_per_line: usize
+}
+
+impl CArrayArgs {
+    fn configure(&self, c_exporter: &mut CExporter) {
+        c_exporter.set_progmem(self.progmem);
+        c_exporter.set_section(self.section.clone());
+        c_exporter.set_stdint(self.stdint);
+        c_exporter.set_static(self.is_static);
+        c_exporter.set_bytes_per_line(self.bytes_per_line);
+    }
+}
+
+/// Exporter of an export type, C code being exported by the given C exporter and MIDI files having the channels of the target.
+fn exporter(export_type: ExportType, base_address: u32, target: &Target, c_exporter: CExporter) -> Box<dyn Exporter> {
+    match export_type {
+        ExportType::Code => Box::new(c_exporter),
+        ExportType::Raw => Box::new(RawExporter),
+        ExportType::Midi => Box::new(MidiExporter { channels: target.channels }),
+        ExportType::Rust => Box::new(RustExporter),
+        ExportType::Asm => Box::new(AssemblyExporter),
+        ExportType::Python => Box::new(PythonExporter),
+        ExportType::Json => Box::new(JsonExporter),
+        ExportType::Ihex => Box::new(IntelHexExporter { base_address }),
+        ExportType::Srec => Box::new(SRecordExporter { base_address })
+    }
+}
+
+/// Parses a decimal address or a hexadecimal one starting with `0x`.
+fn parse_address(address: &str) -> Result<u32, String> {
+    match address.strip_prefix("0x").or(address.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => address.parse::<u32>()
+    }.map_err(|_| format!("Invalid address \"{}\".", address))
+}
+
+fn warning_levels(allow: &[String], deny: &[String]) -> Result<WarningLevels, Error> {
+    let mut warning_levels: WarningLevels = WarningLevels::default();
+    for (names, level) in [(allow, WarningLevel::Allow), (deny, WarningLevel::Deny)] {
+        for name in names {
+            if name == "warnings" {
+                warning_levels.set_default_level(level);
+            } else if let Some(code) = DiagnosticCode::from_warning_name(name) {
+                warning_levels.set_level(code, level);
+            } else {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unknown warning \"{}\".", name)
+                ));
+            }
+        }
+    }
+    Ok(warning_levels)
+}
+
+/// Finds a target by its name or loads it from a TOML file, the generic target being the default.
+fn load_target(target: &Option<String>) -> Result<Target, Error> {
+    match target {
+        Some(name) => match Target::from_name(name) {
+            Some(target) => Ok(target),
+            None if Path::new(name).is_file() => Target::load(Path::new(name)),
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown target \"{}\". Expected generic, protodome-avr, extended or a TOML file.", name)
+            ))
+        },
+        None => Ok(Target::default())
+    }
+}
+
+/// Options of the subcommands compiling songs.
+#[derive(Args, Debug)]
+pub struct SongArgs {
+    /// Turn a warning or a lint into an error ("warnings" for all of them)
+    #[arg(short = 'D', long = "deny", value_name = "WARNING")]
+    deny: Vec<String>,
+    /// Silence a warning or a lint ("warnings" for all of them)
+    #[arg(short = 'A', long = "allow", value_name = "WARNING")]
+    allow: Vec<String>,
+    /// Directory searched for included files, after the directory of the including file
+    #[arg(short = 'I', long = "include-path", value_name = "DIR")]
+    pub include_paths: Vec<PathBuf>,
+    /// Driver profile to compile for (generic, protodome-avr, extended or a TOML file)
+    #[arg(short, long)]
+    target: Option<String>
+}
+
+impl SongArgs {
+    /// Warning levels from the deny and allow lists, specific warnings taking precedence over "warnings".
+    pub fn get_warning_levels(&self) -> Result<WarningLevels, Error> {
+        warning_levels(&self.allow, &self.deny)
+    }
+
+    pub fn get_target(&self) -> Result<Target, Error> {
+        load_target(&self.target)
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    /// Input source code.
+    pub input_path: PathBuf,
+    /// Report the length of each channel and warn when they don't end together
+    #[arg(long, action)]
+    pub timing: bool,
+    #[command(flatten)]
+    pub song: SongArgs
+}
+
+#[derive(Args, Debug)]
+pub struct LintArgs {
+    /// Input source code.
+    pub input_path: PathBuf,
+    #[command(flatten)]
+    pub song: SongArgs
+}
+
+#[derive(Args, Debug)]
+pub struct BankArgs {
+    /// Input source code files, their names in uppercase naming the songs.
+    #[arg(required_unless_present = "manifest")]
+    pub input_paths: Vec<PathBuf>,
+    /// TOML manifest listing the songs, added after the input files
+    #[arg(long, value_name = "FILE")]
+    pub manifest: Option<PathBuf>,
+    /// Output file
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+    /// Export type (every type but midi)
+    #[arg(short, long, default_value = "code")]
+    pub export_type: ExportType,
+    /// Bank name in the output file
+    #[arg(short = 'm', long)]
+    pub bank_name: Option<String>,
+    /// Address the data is loaded at, for Intel HEX and S-record exports
+    #[arg(long, value_parser = parse_address, default_value = "0")]
+    pub base_address: u32,
+    #[command(flatten)]
+    c_array: CArrayArgs,
+    #[command(flatten)]
+    pub song: SongArgs,
+    /// Remove octave and volume commands that change nothing, merge adjacent rests and drop empty loops
+    #[arg(short = 'O', long, action)]
+    pub optimize: bool
+}
+
+impl BankArgs {
+    /// Output file, named after the manifest or "bank" with the extension of the exporter when not set.
+    pub fn get_output_path(&self, exporter: &dyn Exporter) -> PathBuf {
+        let extension: &str = exporter.extension();
+        self.output_path.clone().unwrap_or(match &self.manifest {
+            Some(manifest) => manifest.with_extension(extension),
+            None => PathBuf::from("bank").with_extension(extension)
+        })
+    }
+
+    /// Exporter of the bank, the C header defining the offset and length of each song.
+    pub fn get_exporter(&self, bank: &Bank, bank_name: &str, target: &Target) -> Result<Box<dyn Exporter>, Error> {
+        if self.export_type == ExportType::Midi {
+            return Err(Error::new(ErrorKind::InvalidInput, "A bank can't be exported as a MIDI file."));
+        }
+        let mut c_exporter: CExporter = CExporter::new();
+        self.c_array.configure(&mut c_exporter);
+        c_exporter.set_size_define(true);
+        c_exporter.add_define(format!("{}_SONGS", bank_name), bank.songs.len().to_string());
+        for song in &bank.songs {
+            c_exporter.add_define(format!("{}_{}_OFFSET", bank_name, song.name), format!("0x{:04X}", song.offset));
+            c_exporter.add_define(format!("{}_{}_LENGTH", bank_name, song.name), song.length.to_string());
+        }
+        Ok(exporter(self.export_type, self.base_address, target, c_exporter))
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct FmtArgs {
+    /// Input source code files.
+    #[arg(required = true)]
+    pub input_paths: Vec<PathBuf>,
+    /// Don't write the files, fail if one of them is not formatted
+    #[arg(long, action)]
+    pub check: bool,
+    /// Maximum line length, longer lines being wrapped
+    #[arg(short, long, default_value_t = DEFAULT_WIDTH)]
+    pub width: usize,
+    /// Align the bar lines of the channels written on parallel lines
+    #[arg(short, long, action)]
+    pub align: bool,
+    /// Driver profile giving the number of channels (generic, protodome-avr, extended or a TOML file)
+    #[arg(short, long)]
+    target: Option<String>
+}
+
+impl FmtArgs {
+    pub fn get_target(&self) -> Result<Target, Error> {
+        load_target(&self.target)
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct DisassembleArgs {
+    /// Input compiled data (C code, raw binary or source code, guessed from the extension).
+    pub input_path: PathBuf,
+    /// Output file (prints to the standard output if not set)
+    #[arg(short, long)]
+    pub output_path: Option<PathBuf>
+}
+
+#[derive(Args, Debug)]
+pub struct RenderArgs {
+    /// Input source code or compiled data (guessed from the extension).
+    pub input_path: PathBuf,
+    /// Output file
+    #[arg(short, long)]
+    output_path: Option<PathBuf>,
+    /// Sample rate of the output file
+    #[arg(short, long, default_value_t = DEFAULT_SAMPLE_RATE)]
+    pub sample_rate: u32,
+    /// Maximum length of the output in seconds
+    #[arg(short = 'l', long)]
+    pub max_seconds: Option<u32>
+}
+
+impl RenderArgs {
+    pub fn get_output_path(&self) -> PathBuf {
+        self.output_path.clone().unwrap_or(self.input_path.with_extension("wav"))
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    #[command(subcommand)]
+    pub format: ImportFormat
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImportFormat {
+    /// Import a Standard MIDI File
+    Midi(MidiImportArgs)
+}
+
+#[derive(Args, Debug)]
+pub struct MidiImportArgs {
+    /// Input MIDI file
+    pub input_path: PathBuf,
+    /// Output file
+    #[arg(short, long)]
+    output_path: Option<PathBuf>
+}
+
+impl MidiImportArgs {
+    pub fn get_output_path(&self) -> PathBuf {
+        self.output_path.clone().unwrap_or(self.input_path.with_extension("mmml"))
+    }
+}
@@ -0,0 +1,265 @@
+use crate::diagnostic::Span;
+
+const LENGTHS: [u8; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pitch {
+    C,
+    CSharp,
+    D,
+    DSharp,
+    E,
+    F,
+    FSharp,
+    G,
+    GSharp,
+    A,
+    ASharp,
+    B
+}
+
+pub const PITCHES: [Pitch; 12] = [
+    Pitch::C, Pitch::CSharp, Pitch::D, Pitch::DSharp, Pitch::E, Pitch::F,
+    Pitch::FSharp, Pitch::G, Pitch::GSharp, Pitch::A, Pitch::ASharp, Pitch::B
+];
+
+impl Pitch {
+    /// Semitones from C (0-11).
+    pub fn semitone(&self) -> u8 {
+        PITCHES.iter().position(|pitch| pitch == self).unwrap_or(0) as u8
+    }
+
+    pub fn from_semitone(semitone: u8) -> Self {
+        PITCHES[(semitone % 12) as usize]
+    }
+
+    /// Command name as written in µMML, `E#` being written `F`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "C" => Some(Self::C),
+            "C#" => Some(Self::CSharp),
+            "D" => Some(Self::D),
+            "D#" => Some(Self::DSharp),
+            "E" => Some(Self::E),
+            "E#" | "F" => Some(Self::F),
+            "F#" => Some(Self::FSharp),
+            "G" => Some(Self::G),
+            "G#" => Some(Self::GSharp),
+            "A" => Some(Self::A),
+            "A#" => Some(Self::ASharp),
+            "B" => Some(Self::B),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        ["c", "c#", "d", "d#", "e", "f", "f#", "g", "g#", "a", "a#", "b"][self.semitone() as usize]
+    }
+}
+
+/// Note length: 1 for a whole note up to 128, optionally dotted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Duration {
+    pub length: u8,
+    pub dotted: bool
+}
+
+impl Duration {
+    pub const WHOLE: Duration = Duration { length: 1, dotted: false };
+    pub const HALF: Duration = Duration { length: 2, dotted: false };
+    pub const QUARTER: Duration = Duration { length: 4, dotted: false };
+    pub const EIGHTH: Duration = Duration { length: 8, dotted: false };
+    pub const SIXTEENTH: Duration = Duration { length: 16, dotted: false };
+    pub const THIRTY_SECOND: Duration = Duration { length: 32, dotted: false };
+    pub const SIXTY_FOURTH: Duration = Duration { length: 64, dotted: false };
+    pub const HUNDRED_TWENTY_EIGHTH: Duration = Duration { length: 128, dotted: false };
+
+    /// Returns `None` if the driver can't play this duration.
+    pub fn new(length: u8, dotted: bool) -> Option<Self> {
+        let duration: Duration = Self { length, dotted };
+        duration.encode().map(|_| duration)
+    }
+
+    pub fn dotted(self) -> Self {
+        Self {
+            dotted: true,
+            ..self
+        }
+    }
+
+    /// Low nibble of the note byte.
+    pub fn encode(&self) -> Option<u8> {
+        let index: usize = LENGTHS.iter().position(|&length| length == self.length)?;
+        match (self.dotted, index) {
+            (false, _) => Some(index as u8),
+            (true, 0) => None,
+            (true, _) => Some(0x8 | (index - 1) as u8)
+        }
+    }
+
+    pub fn decode(nibble: u8) -> Option<Self> {
+        match nibble {
+            0x0..=0x7 => Some(Self { length: LENGTHS[nibble as usize], dotted: false }),
+            0x8..=0xE => Some(Self { length: LENGTHS[(nibble & 0x7) as usize + 1], dotted: true }),
+            _ => None
+        }
+    }
+
+    /// Number of driver ticks, a whole note lasting 128 ticks.
+    pub fn ticks(&self) -> u16 {
+        Self::nibble_ticks(self.encode().unwrap_or(0))
+    }
+
+    /// Number of driver ticks of an encoded duration.
+    pub fn nibble_ticks(nibble: u8) -> u16 {
+        if nibble < 8 {
+            (0x7F >> nibble) + 1
+        } else {
+            (95 >> (nibble & 0x7)) + 1
+        }
+    }
+}
+
+impl std::fmt::Display for Duration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.length, if self.dotted { "." } else { "" })
+    }
+}
+
+/// A value with the location of the source code it comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span
+}
+
+impl<T> Spanned<T> {
+    pub fn new(value: T, span: Span) -> Self {
+        Self { value, span }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    /// A note, using the previous duration if none is given.
+    Note { pitch: Pitch, duration: Option<Duration>, span: Span },
+    Rest { duration: Option<Duration>, span: Span },
+    Octave { octave: Spanned<u8>, span: Span },
+    OctaveUp { span: Span },
+    OctaveDown { span: Span },
+    Volume { volume: Spanned<u8>, span: Span },
+    Loop { times: u8, items: Vec<Item>, span: Span },
+    /// Items whose notes are shifted by a number of semitones when compiling.
+    Transposition { semitones: Spanned<i8>, items: Vec<Item>, span: Span },
+    /// Call of a macro by its number, starting at 1.
+    MacroCall { number: Spanned<u8>, span: Span },
+    /// Call of a macro by the name of its section.
+    NamedMacroCall { name: Spanned<String>, span: Span },
+    Tempo { tempo: u8, span: Span },
+    Transpose { value: u8, span: Span },
+    Instrument { value: u8, span: Span },
+    Tie { span: Span },
+    Panning { value: u8, span: Span },
+    Stop { span: Span },
+    /// `|`, checked against the time signature but compiled to nothing.
+    BarLine { span: Span },
+    /// Number of beats per bar and note length of a beat, like 3 and 4 for 3/4.
+    TimeSignature { beats: u8, unit: u8, span: Span }
+}
+
+impl Item {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Note { span, .. } | Self::Rest { span, .. } | Self::Octave { span, .. } |
+            Self::OctaveUp { span } | Self::OctaveDown { span } | Self::Volume { span, .. } |
+            Self::Loop { span, .. } | Self::Transposition { span, .. } | Self::MacroCall { span, .. } |
+            Self::NamedMacroCall { span, .. } | Self::Tempo { span, .. } | Self::Transpose { span, .. } |
+            Self::Instrument { span, .. } | Self::Tie { span } | Self::Panning { span, .. } | Self::Stop { span } |
+            Self::BarLine { span } | Self::TimeSignature { span, .. } => *span
+        }
+    }
+}
+
+/// Content of an `@` section.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Section {
+    /// Name given by an `@name:` label.
+    pub name: Option<Spanned<String>>,
+    pub items: Vec<Item>,
+    /// Location of the `@`.
+    pub span: Span
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Song {
+    pub channels: Vec<Section>,
+    pub macros: Vec<Section>
+}
+
+impl Song {
+    /// Channels then macros, in the order of the header table.
+    pub fn sections(&self) -> impl Iterator<Item = &Section> {
+        self.channels.iter().chain(self.macros.iter())
+    }
+}
+
+impl std::fmt::Display for Item {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let duration = |duration: &Option<Duration>| duration.map(|duration| duration.to_string()).unwrap_or_default();
+        match self {
+            Self::Note { pitch, duration: note_duration, .. } => write!(f, "{}{}", pitch.name(), duration(note_duration)),
+            Self::Rest { duration: rest_duration, .. } => write!(f, "r{}", duration(rest_duration)),
+            Self::Octave { octave, .. } => write!(f, "o{}", octave.value),
+            Self::OctaveUp { .. } => write!(f, ">"),
+            Self::OctaveDown { .. } => write!(f, "<"),
+            Self::Volume { volume, .. } => write!(f, "v{}", volume.value),
+            Self::Loop { times, items, .. } => {
+                write!(f, "[{}", times)?;
+                for item in items {
+                    write!(f, " {}", item)?;
+                }
+                write!(f, " ]")
+            },
+            Self::Transposition { semitones, items, .. } => {
+                write!(f, "{{{:+}", semitones.value)?;
+                for item in items {
+                    write!(f, " {}", item)?;
+                }
+                write!(f, " }}")
+            },
+            Self::MacroCall { number, .. } => write!(f, "m{}", number.value),
+            Self::NamedMacroCall { name, .. } => write!(f, "m({})", name.value),
+            Self::Tempo { tempo, .. } => write!(f, "t{}", tempo),
+            Self::Transpose { value, .. } => write!(f, "k{}", value),
+            Self::Instrument { value, .. } => write!(f, "i{}", value),
+            Self::Tie { .. } => write!(f, "&"),
+            Self::Panning { value, .. } => write!(f, "p{}", value),
+            Self::Stop { .. } => write!(f, "s"),
+            Self::BarLine { .. } => write!(f, "|"),
+            Self::TimeSignature { beats, unit, .. } => write!(f, "#time {}/{}", beats, unit)
+        }
+    }
+}
+
+impl std::fmt::Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "@")?;
+        if let Some(name) = &self.name {
+            write!(f, "{}:", name.value)?;
+        }
+        for item in &self.items {
+            write!(f, " {}", item)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the song as µMML source code, one section per line.
+impl std::fmt::Display for Song {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for section in self.sections() {
+            writeln!(f, "{}", section)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use std::{collections::HashMap, fmt::{Display, Formatter}, io::{Error, ErrorKind}, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+
+use crate::optimizer::{read_sections, Command};
+
+/// Songs to compile into a bank, read from a TOML file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Name of the bank in the output file.
+    pub name: Option<String>,
+    pub songs: Vec<ManifestSong>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestSong {
+    /// Name in the song table, taken from the file name when missing.
+    pub name: Option<String>,
+    /// Source code of the song, relative to the manifest.
+    pub path: PathBuf
+}
+
+impl Manifest {
+    pub fn from_toml(source: &str) -> Result<Self, Error> {
+        toml::from_str(source).map_err(|err| Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid bank manifest: {}", err.message())
+        ))
+    }
+
+    /// Loads a manifest, the paths of its songs becoming relative to the current directory.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut manifest: Manifest = Self::from_toml(&std::fs::read_to_string(path)?)?;
+        let directory: &Path = path.parent().unwrap_or(Path::new(""));
+        for song in &mut manifest.songs {
+            song.path = directory.join(&song.path);
+        }
+        Ok(manifest)
+    }
+}
+
+/// Name usable in C code, in uppercase with `_` instead of the characters not allowed in C names.
+pub fn identifier(name: &str) -> String {
+    let identifier: String = name.chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch.to_ascii_uppercase() } else { '_' })
+        .collect();
+    match identifier.is_empty() || identifier.starts_with(|ch: char| ch.is_ascii_digit()) {
+        true => format!("_{}", identifier),
+        false => identifier
+    }
+}
+
+/// Name of a song from its file name, as an [`identifier`].
+pub fn song_name(path: &Path) -> String {
+    identifier(&path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BankSong {
+    pub name: String,
+    /// Offset of the header table of the song from the start of the bank.
+    pub offset: usize,
+    /// Bytes from the offset to the end of the song, the macros it shares with other songs being counted in [`Bank::shared_bytes`] instead.
+    pub length: usize
+}
+
+/// Songs compiled into one data blob.
+/// The blob starts with a song table: the number of songs, then the offset and length of each song as 16-bit big-endian numbers,
+/// then the names of the songs ending with a 0.
+/// Each song keeps its own header table, macros found in several songs being stored once after the last song.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bank {
+    pub data: Vec<u8>,
+    pub songs: Vec<BankSong>,
+    pub shared_macros: usize,
+    /// Bytes of the shared macros, the data being the song table, then the songs, then the shared macros.
+    pub shared_bytes: usize,
+    pub bytes_saved: usize
+}
+
+impl Display for Bank {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Bank of {} songs is {} bytes, sharing {} macros saved {} bytes.",
+            self.songs.len(), self.data.len(), self.shared_macros, self.bytes_saved
+        )
+    }
+}
+
+/// A song split into sections, before its place in the bank is known.
+struct SongSections {
+    name: String,
+    sections: Vec<Vec<Command>>
+}
+
+fn too_large(what: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("The {} is too large for 16-bit offsets.", what))
+}
+
+fn section_bytes(commands: &[Command]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = commands.concat();
+    bytes.push(0xFF);
+    bytes
+}
+
+/// Combines compiled songs, given with their names, into a bank.
+/// The names become [`identifier`]s, two songs can't have the same one.
+pub fn build_bank(songs: &[(String, Vec<u8>)], channels: u8) -> Result<Bank, Error> {
+    if songs.is_empty() || songs.len() > u8::MAX as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("A bank holds 1 to {} songs. Found {}.", u8::MAX, songs.len())
+        ));
+    }
+    let names: Vec<String> = songs.iter().map(|(name, _)| identifier(name)).collect();
+    if let Some((index, name)) = names.iter().enumerate().find(|(index, name)| names[..*index].contains(name)) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Song {} is named \"{}\" like a previous song.", index + 1, name)
+        ));
+    }
+    let songs: Vec<SongSections> = songs.iter().zip(names)
+        .map(|((_, data), name)| Ok(SongSections { name, sections: read_sections(data, channels as usize)? }))
+        .collect::<Result<Vec<SongSections>, Error>>()?;
+
+    // Songs using each macro, a macro used by several songs being shared.
+    let mut macro_songs: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+    let mut macro_order: Vec<Vec<u8>> = Vec::new();
+    for (song_index, song) in songs.iter().enumerate() {
+        for commands in &song.sections[channels as usize..] {
+            let bytes: Vec<u8> = section_bytes(commands);
+            let song_indices: &mut Vec<usize> = macro_songs.entry(bytes.clone()).or_default();
+            if song_indices.is_empty() {
+                macro_order.push(bytes);
+            }
+            if song_indices.last() != Some(&song_index) {
+                song_indices.push(song_index);
+            }
+        }
+    }
+    let shared: Vec<Vec<u8>> = macro_order.into_iter().filter(|bytes| macro_songs[bytes].len() > 1).collect();
+
+    let mut data: Vec<u8> = vec![songs.len() as u8];
+    data.resize(1 + songs.len() * 4, 0);
+    for song in &songs {
+        data.extend(song.name.as_bytes());
+        data.push(0);
+    }
+
+    // Songs are written with a placeholder for the offsets of their shared macros, known once every song is placed.
+    let mut bank_songs: Vec<BankSong> = Vec::new();
+    let mut shared_headers: Vec<(usize, usize)> = Vec::new();
+    for song in &songs {
+        let start: usize = data.len();
+        data.resize(start + song.sections.len() * 2, 0);
+        for (index, commands) in song.sections.iter().enumerate() {
+            let bytes: Vec<u8> = section_bytes(commands);
+            if index >= channels as usize {
+                if let Some(shared_index) = shared.iter().position(|shared_bytes| *shared_bytes == bytes) {
+                    shared_headers.push((start + index * 2, shared_index));
+                    continue;
+                }
+            }
+            let offset: u16 = u16::try_from(data.len() - start).map_err(|_| too_large(&format!("song \"{}\"", song.name)))?;
+            data[start + index * 2..start + index * 2 + 2].copy_from_slice(&offset.to_be_bytes());
+            data.extend(bytes);
+        }
+        data.push(0x00);
+        bank_songs.push(BankSong { name: song.name.clone(), offset: start, length: data.len() - start });
+    }
+
+    let mut shared_offsets: Vec<usize> = Vec::new();
+    for bytes in &shared {
+        shared_offsets.push(data.len());
+        data.extend(bytes);
+    }
+    for (song, bank_song) in bank_songs.iter().enumerate() {
+        let entry: usize = 1 + song * 4;
+        let offset: u16 = u16::try_from(bank_song.offset).map_err(|_| too_large("bank"))?;
+        data[entry..entry + 2].copy_from_slice(&offset.to_be_bytes());
+        let length: u16 = u16::try_from(bank_song.length).map_err(|_| too_large("bank"))?;
+        data[entry + 2..entry + 4].copy_from_slice(&length.to_be_bytes());
+    }
+    for (header, shared_index) in shared_headers {
+        let song_start: usize = bank_songs.iter().rev().find(|song| song.offset <= header).map_or(0, |song| song.offset);
+        let offset: u16 = u16::try_from(shared_offsets[shared_index] - song_start).map_err(|_| too_large("bank"))?;
+        data[header..header + 2].copy_from_slice(&offset.to_be_bytes());
+    }
+
+    let bytes_saved: usize = shared.iter().map(|bytes| (macro_songs[bytes].len() - 1) * bytes.len()).sum();
+    Ok(Bank {
+        data,
+        songs: bank_songs,
+        shared_macros: shared.len(),
+        shared_bytes: shared.iter().map(Vec::len).sum(),
+        bytes_saved
+    })
+}
@@ -0,0 +1,14 @@
+use mmml_compiler::lsp::run;
+use std::{io::{stdin, stdout}, process::exit};
+
+/// µMML language server, speaking LSP over the standard input and output.
+fn main() {
+    match run(stdin().lock(), stdout().lock()) {
+        Ok(true) => {},
+        Ok(false) => exit(1),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            exit(1);
+        }
+    }
+}
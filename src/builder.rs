@@ -0,0 +1,333 @@
+use std::{fmt::{Display, Formatter}, io::{Error, ErrorKind}, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::{
+    ast::{Duration, Item, Pitch, Section, Song, Spanned},
+    compiler::{Compiler, DEFAULT_OCTAVE},
+    diagnostic::{Diagnostic, Span},
+    driver::MAX_LOOPS,
+    target::Target
+};
+
+/// Invalid songs rejected by the builders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    InvalidOctave { octave: u8, min_octave: u8, max_octave: u8 },
+    InvalidVolume(u8),
+    InvalidDuration(Duration),
+    /// A loop played 0 times, the driver would play it 256 times.
+    InvalidLoopCount,
+    /// More nested loops than the driver loop stack can hold.
+    TooManyNestedLoops,
+    TooManyChannels { channels: usize, max_channels: u8 },
+    /// A macro reference coming from another song.
+    UnknownMacro(u8),
+    /// Errors found by the compiler, like a `>` going past the maximum octave.
+    Compile(Vec<Diagnostic>)
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidOctave { octave, min_octave, max_octave } =>
+                write!(f, "Invalid octave number {}. Expected octave number {}-{}.", octave, min_octave, max_octave),
+            Self::InvalidVolume(volume) => write!(f, "Invalid volume number {}. Expected volume number 0-8.", volume),
+            Self::InvalidDuration(duration) => write!(f, "Invalid duration {}.", duration),
+            Self::InvalidLoopCount => write!(f, "Loops must be played at least once."),
+            Self::TooManyNestedLoops => write!(f, "Loops can't be nested more than {} times.", MAX_LOOPS),
+            Self::TooManyChannels { channels, max_channels } =>
+                write!(f, "Song has {} channels but the target supports {}.", channels, max_channels),
+            Self::UnknownMacro(number) => write!(f, "Macro {} doesn't belong to this song.", number),
+            Self::Compile(diagnostics) => match diagnostics.first() {
+                Some(diagnostic) => write!(f, "{}", diagnostic),
+                None => write!(f, "Could not compile the song.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<BuildError> for Error {
+    fn from(error: BuildError) -> Self {
+        Error::new(ErrorKind::InvalidData, error.to_string())
+    }
+}
+
+/// Identifier of the next [`SongBuilder`], to tell the macros of different songs apart.
+static NEXT_SONG_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Reference to a macro of a [`SongBuilder`], to call it from a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacroRef {
+    song_id: usize,
+    number: u8
+}
+
+/// Commands being built for a channel or a macro.
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    items: Vec<Item>,
+    error: Option<BuildError>,
+    loop_depth: usize,
+    /// Octave after the last command, starting at the octave of the compiler.
+    octave: u8,
+    min_octave: u8,
+    max_octave: u8
+}
+
+impl Sequence {
+    fn new(target: &Target) -> Self {
+        Self {
+            items: Vec::new(),
+            error: None,
+            loop_depth: 0,
+            octave: DEFAULT_OCTAVE.clamp(target.min_octave, target.max_octave),
+            min_octave: target.min_octave,
+            max_octave: target.max_octave
+        }
+    }
+
+    /// Adds the item, only the first error is kept.
+    fn push(&mut self, item: Result<Item, BuildError>) {
+        match item {
+            Ok(item) => self.items.push(item),
+            Err(error) => {
+                self.error.get_or_insert(error);
+            }
+        }
+    }
+
+    /// Moves the octave by `shift`, which must stay in the octaves of the target.
+    fn shift_octave(&mut self, shift: i8, item: Item) {
+        let octave: u8 = self.octave.saturating_add_signed(shift);
+        let item: Result<Item, BuildError> = match octave < self.min_octave || octave > self.max_octave {
+            true => Err(BuildError::InvalidOctave { octave, min_octave: self.min_octave, max_octave: self.max_octave }),
+            false => Ok(item)
+        };
+        if item.is_ok() {
+            self.octave = octave;
+        }
+        self.push(item);
+    }
+
+    fn finish(self) -> Result<Section, BuildError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(Section { name: None, items: self.items, span: Span::default() })
+        }
+    }
+}
+
+fn check_duration(duration: Duration) -> Result<Option<Duration>, BuildError> {
+    match duration.encode() {
+        Some(_) => Ok(Some(duration)),
+        None => Err(BuildError::InvalidDuration(duration))
+    }
+}
+
+/// Commands shared by channels and macros.
+pub trait SequenceBuilder: Sized {
+    fn sequence(&mut self) -> &mut Sequence;
+
+    fn note(&mut self, pitch: Pitch, duration: Duration) -> &mut Self {
+        let item: Result<Item, BuildError> = check_duration(duration)
+            .map(|duration| Item::Note { pitch, duration, span: Span::default() });
+        self.sequence().push(item);
+        self
+    }
+
+    fn rest(&mut self, duration: Duration) -> &mut Self {
+        let item: Result<Item, BuildError> = check_duration(duration)
+            .map(|duration| Item::Rest { duration, span: Span::default() });
+        self.sequence().push(item);
+        self
+    }
+
+    fn octave(&mut self, octave: u8) -> &mut Self {
+        let sequence: &mut Sequence = self.sequence();
+        let item: Result<Item, BuildError> = if octave >= sequence.min_octave && octave <= sequence.max_octave {
+            sequence.octave = octave;
+            Ok(Item::Octave { octave: Spanned::new(octave, Span::default()), span: Span::default() })
+        } else {
+            Err(BuildError::InvalidOctave { octave, min_octave: sequence.min_octave, max_octave: sequence.max_octave })
+        };
+        sequence.push(item);
+        self
+    }
+
+    fn octave_up(&mut self) -> &mut Self {
+        self.sequence().shift_octave(1, Item::OctaveUp { span: Span::default() });
+        self
+    }
+
+    fn octave_down(&mut self) -> &mut Self {
+        self.sequence().shift_octave(-1, Item::OctaveDown { span: Span::default() });
+        self
+    }
+
+    /// Sets the volume, from 0 (silent) to 8.
+    fn volume(&mut self, volume: u8) -> &mut Self {
+        let item: Result<Item, BuildError> = match volume {
+            0..=8 => Ok(Item::Volume { volume: Spanned::new(volume, Span::default()), span: Span::default() }),
+            _ => Err(BuildError::InvalidVolume(volume))
+        };
+        self.sequence().push(item);
+        self
+    }
+
+    fn tempo(&mut self, tempo: u8) -> &mut Self {
+        self.sequence().push(Ok(Item::Tempo { tempo, span: Span::default() }));
+        self
+    }
+
+    fn transpose(&mut self, value: u8) -> &mut Self {
+        self.sequence().push(Ok(Item::Transpose { value, span: Span::default() }));
+        self
+    }
+
+    fn instrument(&mut self, value: u8) -> &mut Self {
+        self.sequence().push(Ok(Item::Instrument { value, span: Span::default() }));
+        self
+    }
+
+    fn tie(&mut self) -> &mut Self {
+        self.sequence().push(Ok(Item::Tie { span: Span::default() }));
+        self
+    }
+
+    fn panning(&mut self, value: u8) -> &mut Self {
+        self.sequence().push(Ok(Item::Panning { value, span: Span::default() }));
+        self
+    }
+
+    fn stop(&mut self) -> &mut Self {
+        self.sequence().push(Ok(Item::Stop { span: Span::default() }));
+        self
+    }
+
+    /// Plays the commands added by `body` `times` times.
+    fn loop_n(&mut self, times: u8, body: impl FnOnce(&mut Self)) -> &mut Self {
+        let sequence: &mut Sequence = self.sequence();
+        let outer_items: Vec<Item> = std::mem::take(&mut sequence.items);
+        sequence.loop_depth += 1;
+        let loop_depth: usize = sequence.loop_depth;
+        body(self);
+        let sequence: &mut Sequence = self.sequence();
+        sequence.loop_depth -= 1;
+        let items: Vec<Item> = std::mem::replace(&mut sequence.items, outer_items);
+        let item: Result<Item, BuildError> = if times == 0 {
+            Err(BuildError::InvalidLoopCount)
+        } else if loop_depth > MAX_LOOPS {
+            Err(BuildError::TooManyNestedLoops)
+        } else {
+            Ok(Item::Loop { times, items, span: Span::default() })
+        };
+        sequence.push(item);
+        self
+    }
+}
+
+pub struct ChannelBuilder {
+    sequence: Sequence,
+    song_id: usize
+}
+
+impl ChannelBuilder {
+    pub fn call(&mut self, macro_ref: MacroRef) -> &mut Self {
+        let item: Result<Item, BuildError> = match macro_ref.song_id == self.song_id {
+            true => Ok(Item::MacroCall { number: Spanned::new(macro_ref.number, Span::default()), span: Span::default() }),
+            false => Err(BuildError::UnknownMacro(macro_ref.number))
+        };
+        self.sequence.push(item);
+        self
+    }
+}
+
+impl SequenceBuilder for ChannelBuilder {
+    fn sequence(&mut self) -> &mut Sequence {
+        &mut self.sequence
+    }
+}
+
+/// Builds a macro. Macros can't call other macros as the driver keeps a single return address.
+pub struct MacroBuilder {
+    sequence: Sequence
+}
+
+impl SequenceBuilder for MacroBuilder {
+    fn sequence(&mut self) -> &mut Sequence {
+        &mut self.sequence
+    }
+}
+
+/// Builds a song from Rust code, without writing µMML source code.
+pub struct SongBuilder {
+    id: usize,
+    target: Target,
+    channels: Vec<Result<Section, BuildError>>,
+    macros: Vec<Result<Section, BuildError>>
+}
+
+impl Default for SongBuilder {
+    fn default() -> Self {
+        Self::new(Target::default())
+    }
+}
+
+impl SongBuilder {
+    pub fn new(target: Target) -> Self {
+        Self {
+            id: NEXT_SONG_ID.fetch_add(1, Ordering::Relaxed),
+            target,
+            channels: Vec::new(),
+            macros: Vec::new()
+        }
+    }
+
+    /// Adds the next channel. Channels that are not added only play rests.
+    pub fn channel(&mut self, body: impl FnOnce(&mut ChannelBuilder)) -> &mut Self {
+        let mut channel: ChannelBuilder = ChannelBuilder {
+            sequence: Sequence::new(&self.target),
+            song_id: self.id
+        };
+        body(&mut channel);
+        self.channels.push(channel.sequence.finish());
+        self
+    }
+
+    pub fn add_macro(&mut self, body: impl FnOnce(&mut MacroBuilder)) -> MacroRef {
+        let mut macro_builder: MacroBuilder = MacroBuilder {
+            sequence: Sequence::new(&self.target)
+        };
+        body(&mut macro_builder);
+        self.macros.push(macro_builder.sequence.finish());
+        MacroRef { song_id: self.id, number: self.macros.len() as u8 }
+    }
+
+    pub fn build(&self) -> Result<Song, BuildError> {
+        if self.channels.len() > self.target.channels as usize {
+            return Err(BuildError::TooManyChannels { channels: self.channels.len(), max_channels: self.target.channels });
+        }
+        let mut channels: Vec<Section> = self.channels.iter().cloned().collect::<Result<Vec<Section>, BuildError>>()?;
+        channels.resize(self.target.channels as usize, Section {
+            name: None,
+            items: vec![Item::Rest { duration: Some(Duration::WHOLE), span: Span::default() }],
+            span: Span::default()
+        });
+        let macros: Vec<Section> = self.macros.iter().cloned().collect::<Result<Vec<Section>, BuildError>>()?;
+        Ok(Song { channels, macros })
+    }
+
+    /// µMML source code of the song.
+    pub fn to_source(&self) -> Result<String, BuildError> {
+        Ok(self.build()?.to_string())
+    }
+
+    /// Compiles the song for the builder target.
+    pub fn compile(&self) -> Result<Vec<u8>, BuildError> {
+        let song: Song = self.build()?;
+        let mut compiler: Compiler = Compiler::new(Vec::new());
+        compiler.set_target(self.target.clone());
+        compiler.compile_song(&song).map_err(BuildError::Compile)
+    }
+}
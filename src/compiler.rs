@@ -1,342 +1,553 @@
-use std::{collections::HashMap, io::{Error, ErrorKind}};
+use std::collections::{HashMap, HashSet};
 
-use crate::token::{Token, TokenType};
+use crate::{
+    ast::{Duration, Item, Pitch, Song, Spanned},
+    diagnostic::{Diagnostic, DiagnosticCode, Span},
+    parser::Parser,
+    preprocessor::Preprocessor,
+    target::{ExtendedCommand, Target},
+    timing::{channel_name, macro_ticks, time_signature_changes, BarChecker, Timed, TimeSignature},
+    token::Token
+};
+
+/// Octave of the driver before the first octave command.
+pub const DEFAULT_OCTAVE: u8 = 4;
+
+/// An item and the bytes it compiled to, for tools like the language server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledItem {
+    pub span: Span,
+    /// Octave the driver plays at after the item.
+    pub octave: u8,
+    /// Duration nibble used by the item, or by the next notes.
+    pub duration: u8,
+    /// Bytes of the item, only the opening bytes for a loop.
+    pub bytes: Vec<u8>
+}
 
 pub struct Compiler {
     tokens: Vec<Token>,
-    current_token: Token,
-    current_index: usize,
     current_octave: u8,
     current_duration: u8,
-    num_of_headers: u8
+    num_of_headers: u8,
+    max_errors: Option<usize>,
+    /// Errors found while tokenizing, reported along the errors of the parser and the compiler.
+    lexer_errors: Vec<Diagnostic>,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Diagnostic>,
+    target: Target,
+    /// Macro id of each section name, `None` for named channels.
+    section_names: HashMap<String, Option<u8>>,
+    used_macros: HashSet<u8>,
+    /// Semitones added to the notes inside `{...}` transpositions, `None` outside of them.
+    transposition: Option<i16>,
+    /// Octave of the driver inside transpositions, `None` when unknown.
+    emitted_octave: Option<u8>,
+    /// Durations and bar lines of the section being compiled.
+    timeline: Vec<Timed>,
+    /// Time signature changes of each channel, with the tick they start at.
+    time_signatures: Vec<Vec<(u64, TimeSignature)>>,
+    /// Macro calls of the section being compiled, to check how deep they nest.
+    macro_calls: Vec<(u8, Span)>,
+    compiled_items: Vec<CompiledItem>
 }
 
 impl Compiler {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        let first_token: Token = tokens[0].clone();
+    /// Creates a compiler for the tokens of a lexer, comments and whitespace being ignored.
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        tokens.retain(|token| !token.is_trivia());
         Self {
             tokens,
-            current_token: first_token,
-            current_index: 0,
-            current_octave: 4,
+            current_octave: DEFAULT_OCTAVE,
             current_duration: 0,
-            num_of_headers: 0
+            num_of_headers: 0,
+            max_errors: None,
+            lexer_errors: Vec::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            target: Target::default(),
+            section_names: HashMap::new(),
+            used_macros: HashSet::new(),
+            transposition: None,
+            emitted_octave: None,
+            timeline: Vec::new(),
+            time_signatures: Vec::new(),
+            macro_calls: Vec::new(),
+            compiled_items: Vec::new()
         }
     }
 
-    fn is_end_of_file(&self) -> bool {
-        self.current_token.token_type == TokenType::EndOfFile || self.current_index >= self.tokens.len()
+    /// Compiles for a driver profile, commands it doesn't support become errors.
+    pub fn set_target(&mut self, target: Target) {
+        self.target = target;
+    }
+
+    /// Warnings found by the last compilation.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Items compiled by the last compilation, in the order of the source code.
+    pub fn compiled_items(&self) -> &[CompiledItem] {
+        &self.compiled_items
+    }
+
+    /// Time signature changes of each channel of the last compilation, with the tick they start at.
+    pub fn time_signatures(&self) -> &[Vec<(u64, TimeSignature)>] {
+        &self.time_signatures
+    }
+
+    /// Reports the errors of a lexer recovering from them, like [`crate::lexer::Lexer::tokenize_recovering`].
+    pub fn set_lexer_errors(&mut self, lexer_errors: Vec<Diagnostic>) {
+        self.lexer_errors = lexer_errors;
+    }
+
+    /// Stops the compilation once `max_errors` errors are found.
+    pub fn set_max_errors(&mut self, max_errors: usize) {
+        self.max_errors = Some(max_errors);
     }
 
-    fn advance(&mut self) {
-        if !self.is_end_of_file() {
-            if let Some(token) = self.tokens.get(self.current_index + 1) {
-                self.current_index += 1;
-                self.current_token = token.clone();
-                return;
+    fn check_extended_command(&mut self, command: ExtendedCommand, span: Span) -> Result<(), Diagnostic> {
+        match self.target.supports(command) {
+            Some(true) => Ok(()),
+            Some(false) => Err(Diagnostic::error(
+                DiagnosticCode::UnsupportedCommand,
+                format!("{} command is not supported by target {}", command, self.target.name),
+                span
+            )),
+            None => {
+                let code: DiagnosticCode = match command {
+                    ExtendedCommand::Transpose => DiagnosticCode::TransposeCommand,
+                    ExtendedCommand::Instrument => DiagnosticCode::InstrumentCommand,
+                    ExtendedCommand::Tie => DiagnosticCode::TieCommand,
+                    ExtendedCommand::Panning => DiagnosticCode::PanningCommand,
+                    ExtendedCommand::Stop => DiagnosticCode::StopCommand
+                };
+                self.warnings.push(Diagnostic::warning(
+                    code,
+                    format!("{} command found", command),
+                    span
+                ).with_help(format!("{} can be not supported for all µMML drivers!", command)));
+                Ok(())
             }
         }
-        self.current_token = Token::empty(self.current_token.line, self.current_token.column);
     }
 
-    fn compile_number(&mut self) -> Result<u8, Error> {
-        if self.current_token.token_type != TokenType::Number {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!("Tried to convert a non number token '{}' at line {}, column {}.", self.current_token.value, self.current_token.line, self.current_token.column)
-            ));
-        }
-        if let Ok(number) = u8::from_str_radix(&self.current_token.value, 10) {
-            self.advance();
-            return Ok(number);
+    fn octave_help(&self) -> String {
+        format!("Expected octave number {}-{}.", self.target.min_octave, self.target.max_octave)
+    }
+
+    fn compile_octave(&mut self, octave: &Spanned<u8>) -> Result<Vec<u8>, Diagnostic> {
+        let number: u8 = octave.value;
+        if number >= self.target.min_octave && number <= self.target.max_octave {
+            self.current_octave = number;
+            return Ok(vec![0xD0 | (number - 1) & 0x0F]);
         }
+        Err(Diagnostic::error(
+            DiagnosticCode::InvalidOctave,
+            format!("Invalid octave number {}", number),
+            octave.span
+        ).with_help(self.octave_help()))
+    }
 
-        Err(Error::new(
-            ErrorKind::InvalidData,
-            format!("Failed to convert number '{}' at line {}, column {}.", self.current_token.value, self.current_token.line, self.current_token.column)
-        ))
+    fn compile_volume(&mut self, volume: &Spanned<u8>) -> Result<Vec<u8>, Diagnostic> {
+        if volume.value < 9 {
+            return Ok(vec![0xE0 | (9 - volume.value)]);
+        }
+        Err(Diagnostic::error(
+            DiagnosticCode::InvalidVolume,
+            format!("Invalid volume number {}", volume.value),
+            volume.span
+        ).with_help("Expected volume number 0-8.".to_string()))
     }
 
-    fn compile_duration_number(&mut self) -> Result<u8, Error> {
-        let durations: [u8; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
-        let number_token: Token = self.current_token.clone();
-        let number: u8 = self.compile_number()?;
-        let is_dotted: bool = self.current_token.token_type == TokenType::Dot;
-        if let Some(duration_number) = durations.iter().position(|&x| x == number) {
-            if is_dotted {
-                self.advance();
-                return Ok(0x8 | (duration_number - 1) as u8);
+    /// Reports the calls of a section entered `depth` macros deep that go past the macro depth of the target.
+    /// `visited` holds the sections and depths already checked, each call is reported once.
+    fn check_macro_depth(
+        &mut self,
+        section: usize,
+        depth: usize,
+        channels: usize,
+        calls: &[Vec<(u8, Span)>],
+        visited: &mut HashSet<(usize, usize)>
+    ) {
+        if !visited.insert((section, depth)) {
+            return;
+        }
+        let max_depth: usize = self.target.max_macro_depth as usize;
+        for &(macro_id, span) in &calls[section] {
+            let called: usize = channels + macro_id as usize;
+            if depth < max_depth {
+                if called < calls.len() {
+                    self.check_macro_depth(called, depth + 1, channels, calls, visited);
+                }
+                continue;
+            }
+            if self.errors.iter().any(|error| error.code == DiagnosticCode::NestedMacroCall && error.span == span) {
+                continue;
             }
-            return Ok(duration_number as u8);
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::NestedMacroCall,
+                format!("Macro call goes {} macros deep", depth + 1),
+                span
+            ).with_help(format!(
+                "Target {} can only be inside {} macros at once, call the macro from the channel instead.",
+                self.target.name, max_depth
+            )));
         }
-        Err(Error::new(
-            ErrorKind::InvalidData,
-            format!("Invalid duration number '{}' at line {}, column {}.\nExpected 128, 64, 64., 32, 32., 16, 16., 8, 8., 4, 4., 2, 2. or 1", number, number_token.line, number_token.column)
-        ))
     }
 
-    fn compile_argument(&mut self, command_name: &str, byte: u8) -> Result<Vec<u8>, Error> {
-        match command_name {
-            "O" => {
-                let number: u8 = self.compile_number()?;
-                if number > 0 && number < 6 {
-                    self.current_octave = number;
-                    return Ok(vec![byte | (number - 1) & 0x0F]);
-                }
-                Err(Error::new(
-                    ErrorKind::Unsupported,
-                    format!("Invalid octave number at line {}, column {}:\nExpected octave number 1-5.", self.current_token.line, self.current_token.column)
-                ))
-            },
-            "V" => {
-                let number: u8 = self.compile_number()?;
-                if number < 9 {
-                    return Ok(vec![byte | 9 - number]);
-                }
-                Err(Error::new(
-                    ErrorKind::Unsupported,
-                    format!("Invalid volume number at line {}, column {}:\nExpected volume number 0-8.", self.current_token.line, self.current_token.column)
-                ))
-            },
-            "T" => {
-                let number: u8 = self.compile_number()?;
-                Ok(vec![byte, number])
-            },
-            "M" => {
-                let number: u8 = self.compile_number()?;
-                let num_of_macros: u8 = self.num_of_headers - 3;
-                let macro_id: u8 = number.wrapping_sub(1);
-                if macro_id <= num_of_macros {
-                    return Ok(vec![byte, macro_id]);
-                }
-                Err(Error::new(
-                    ErrorKind::Unsupported,
-                    format!("Invalid macro number at line {}, column {}:\nNumber of macros: {}.", self.current_token.line, self.current_token.column, num_of_macros)
-                ))
-            },
-            "K" => {
-                let number: u8 = self.compile_number()?;
-                println!(
-                    "Warning: Transpose command found at line {}, column {}. Transpose can be not supported for all µMML drivers!",
-                    self.current_token.line, self.current_token.column
-                );
-                Ok(vec![byte, number])
-            },
-            "I" => {
-                let number: u8 = self.compile_number()?;
-                println!(
-                    "Warning: Instrument command found at line {}, column {}. Instrument can be not supported for all µMML drivers!",
-                    self.current_token.line, self.current_token.column
-                );
-                Ok(vec![byte, number])
-            },
-            "P" => {
-                let number: u8 = self.compile_number()?;
-                println!(
-                    "Warning: Panning command found at line {}, column {}. Panning can be not supported for all µMML drivers!",
-                    self.current_token.line, self.current_token.column
-                );
-                Ok(vec![byte, number])
-            },
-            "&" => {
-                println!(
-                    "Warning: Tie command found at line {}, column {}. Tie can be not supported for all µMML drivers!",
-                    self.current_token.line, self.current_token.column
-                );
-                return Ok(vec![byte]);
-            },
-            "S" => {
-                println!(
-                    "Warning: Stop command found at line {}, column {}. Tie can be not supported for all µMML drivers!",
-                    self.current_token.line, self.current_token.column
-                );
-                return Ok(vec![byte]);
-            },
-            "R" | "R#" | "C" | "C#" | "D" | "D#" | "E" |
-            "E#" | "F" | "F#" | "G" | "G#" | "A" | "A#" | "B" => {
-                let duration_number: u8 = if self.current_token.token_type == TokenType::Number {
-                    self.compile_duration_number()?
-                } else {
-                    self.current_duration
-                };
-                self.current_duration = duration_number;
-                Ok(vec![byte | duration_number])
+    fn compile_macro_call(&mut self, number: &Spanned<u8>) -> Result<Vec<u8>, Diagnostic> {
+        let num_of_macros: u8 = self.num_of_headers - self.target.channels;
+        let macro_id: u8 = number.value.wrapping_sub(1);
+        if macro_id < num_of_macros {
+            self.used_macros.insert(macro_id);
+            self.timeline.push(Timed::MacroCall(macro_id));
+            return Ok(vec![0xF2, macro_id]);
+        }
+        Err(Diagnostic::error(
+            DiagnosticCode::InvalidMacro,
+            format!("Invalid macro number {}", number.value),
+            number.span
+        ).with_help(format!("Number of macros: {}.", num_of_macros)))
+    }
+
+    fn compile_named_macro_call(&mut self, name: &Spanned<String>) -> Result<Vec<u8>, Diagnostic> {
+        match self.section_names.get(&name.value) {
+            Some(&Some(macro_id)) => {
+                self.used_macros.insert(macro_id);
+                self.timeline.push(Timed::MacroCall(macro_id));
+                Ok(vec![0xF2, macro_id])
             },
-            _ => {
-                Err(Error::new(
-                    ErrorKind::Unsupported,
-                    format!("Uncompilable command called {} at line {} column {}.", command_name, self.current_token.line, self.current_token.column)
-                ))
+            Some(None) => Err(Diagnostic::error(
+                DiagnosticCode::InvalidMacro,
+                format!("Section {} is a channel, not a macro", name.value),
+                name.span
+            ).with_help(format!("Only the sections after the first {} are macros.", self.target.channels))),
+            None => Err(Diagnostic::error(
+                DiagnosticCode::UnknownMacroName,
+                format!("Unknown macro name {}", name.value),
+                name.span
+            ).with_help("Macros are named with '@name:'.".to_string()))
+        }
+    }
+
+    /// Maps section names to macro ids, reporting names used twice.
+    fn collect_section_names(&mut self, song: &Song) {
+        self.section_names.clear();
+        let mut spans: HashMap<&str, Span> = HashMap::new();
+        for (index, section) in song.sections().enumerate() {
+            let Some(name) = &section.name else {
+                continue;
+            };
+            if let Some(&first_span) = spans.get(name.value.as_str()) {
+                self.errors.push(Diagnostic::error(
+                    DiagnosticCode::DuplicateSectionName,
+                    format!("Section name {} is defined more than once", name.value),
+                    name.span
+                ).with_label(first_span, "first defined here".to_string()));
+                continue;
             }
+            spans.insert(&name.value, name.span);
+            let macro_id: Option<u8> = index.checked_sub(song.channels.len()).map(|macro_id| macro_id as u8);
+            self.section_names.insert(name.value.clone(), macro_id);
         }
     }
 
-    fn compile_command(&mut self) -> Result<Vec<u8>, Error> {
-        let commands_map: HashMap<&str, u8> = HashMap::from([
-            ("R", 0x00),
-            ("R#", 0x00),
-            ("C", 0x10),
-            ("C#", 0x20),
-            ("D", 0x30),
-            ("D#", 0x40),
-            ("E", 0x50),
-            ("E#", 0x60),
-            ("F", 0x60),
-            ("F#", 0x70),
-            ("G", 0x80),
-            ("G#", 0x90),
-            ("A", 0xA0),
-            ("A#", 0xB0),
-            ("B", 0xC0),
-            ("O", 0xD0),
-            ("V", 0xE0),
-            ("M", 0xF2),
-            ("T", 0xF3),
-            ("K", 0xF4),
-            ("I", 0xF5),
-            ("&", 0xF6),
-            ("P", 0xF7),
-            ("S", 0xF8)
-        ]);
-        let command_token: Token = self.current_token.clone();
-        let command_name: &str = &command_token.value.to_uppercase();
-        self.advance();
-        match commands_map.get(command_name) {
-            Some(byte) => self.compile_argument(command_name, *byte),
-            None => {
-                Err(Error::new(
-                    ErrorKind::Unsupported,
-                    format!("Unexpected command called {} at line {} column {}.", command_name, command_token.line, command_token.column)
-                ))
+    /// Warns about named macros that are never called, by name or by number.
+    /// Macros of included files are libraries and are not checked.
+    fn check_unused_macros(&mut self, song: &Song) {
+        for (macro_id, section) in song.macros.iter().enumerate() {
+            if let Some(name) = &section.name {
+                if name.span.file == 0 && !self.used_macros.contains(&(macro_id as u8)) {
+                    self.warnings.push(Diagnostic::warning(
+                        DiagnosticCode::UnusedMacro,
+                        format!("Macro {} is never called", name.value),
+                        name.span
+                    ).with_help(format!("Call it with 'm({})' or remove it.", name.value)));
+                }
             }
         }
     }
 
-    fn compile_loop(&mut self) -> Result<Vec<u8>, Error> {
-        let start_token: Token = self.current_token.clone();
-        self.advance();
-        let times: u8 = self.compile_number()?;
-        let mut result: Vec<u8> = vec![0xF0, times];
-        while self.current_token.token_type != TokenType::RightParen {
-            if self.current_token.token_type == TokenType::Arobase {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Loop didn't close at the end of channel.\nStart loop: line {}, column: {}.", start_token.line, start_token.column)
-                ));
+    /// Checks the bar lines of every section against the time signature.
+    fn check_bars(&mut self, song: &Song, timelines: &[Vec<Timed>]) {
+        let macro_ticks: Vec<u64> = macro_ticks(&timelines[song.channels.len().min(timelines.len())..], self.target.max_macro_depth);
+        let mut bar_checker: BarChecker = BarChecker::new(&macro_ticks);
+        for (index, (section, timeline)) in song.sections().zip(timelines).enumerate() {
+            let section_name: String = match (index.checked_sub(song.channels.len()), &section.name) {
+                (None, _) => format!("channel {}", channel_name(index)),
+                (Some(_), Some(name)) => format!("macro {}", name.value),
+                (Some(macro_id), None) => format!("macro {}", macro_id + 1)
+            };
+            bar_checker.check_section(section_name, timeline);
+        }
+        self.errors.extend(bar_checker.finish());
+        self.time_signatures = timelines.iter()
+            .take(song.channels.len())
+            .map(|timeline| time_signature_changes(timeline, &macro_ticks))
+            .collect();
+    }
+
+    /// Compiles a `<` or `>`, a run of them being compiled to a single octave command.
+    fn compile_octave_shift(&mut self, up: bool, span: Span, next_item: Option<&Item>) -> Result<Vec<u8>, Diagnostic> {
+        if up {
+            if self.current_octave >= self.target.max_octave {
+                return Err(Diagnostic::error(
+                    DiagnosticCode::OctaveOverflow,
+                    "Tried to upper octave by 1 but the octave was already at is maximum".to_string(),
+                    span
+                ).with_help(self.octave_help()));
+            }
+            self.current_octave += 1;
+            if matches!(next_item, Some(Item::OctaveUp { .. })) {
+                return Ok(Vec::new());
+            }
+        } else {
+            if self.current_octave <= self.target.min_octave {
+                return Err(Diagnostic::error(
+                    DiagnosticCode::OctaveUnderflow,
+                    "Tried to lower octave by 1 but the octave was already at is minimum".to_string(),
+                    span
+                ).with_help(self.octave_help()));
             }
-            let mut compiled_command: Vec<u8> = self.compile_token()?;
-            result.append(&mut compiled_command);
-            if self.is_end_of_file() {
-                return Err(Error::new(
-                    ErrorKind::UnexpectedEof,
-                    format!("Loop didn't close at the end of file.\nStart loop: line {}, column: {}.", start_token.line, start_token.column)
-                ));
+            self.current_octave -= 1;
+            if matches!(next_item, Some(Item::OctaveDown { .. })) {
+                return Ok(Vec::new());
             }
         }
-        self.advance();
-        result.push(0xF1);
+        Ok(vec![0xD0 | (self.current_octave - 1)])
+    }
+
+    /// Compiles a note shifted by `transposition` semitones, setting the octave of the driver when it changes.
+    fn compile_transposed_note(&mut self, pitch: Pitch, transposition: i16, span: Span) -> Result<Vec<u8>, Diagnostic> {
+        let shifted_pitch: i16 = self.current_octave as i16 * 12 + pitch.semitone() as i16 + transposition;
+        let octave: i16 = shifted_pitch.div_euclid(12);
+        let pitch: Pitch = Pitch::from_semitone(shifted_pitch.rem_euclid(12) as u8);
+        if octave < self.target.min_octave as i16 || octave > self.target.max_octave as i16 {
+            return Err(Diagnostic::error(
+                DiagnosticCode::TransposedNoteOutOfRange,
+                format!("Note transposed by {} semitones is out of the octave range", transposition),
+                span
+            ).with_help(self.octave_help()));
+        }
+        let mut result: Vec<u8> = Vec::new();
+        if self.emitted_octave != Some(octave as u8) {
+            result.push(0xD0 | (octave as u8 - 1));
+            self.emitted_octave = Some(octave as u8);
+        }
+        result.push(((pitch.semitone() + 1) << 4) | self.current_duration);
         Ok(result)
     }
 
-    fn compile_token(&mut self) -> Result<Vec<u8>, Error> {
-        match self.current_token.token_type {
-            TokenType::Arobase => {
-                self.advance();
-                Ok(vec![0xFF])
-            },
-            TokenType::LessThan => {
-                if self.current_octave <= 1 {
-                    return Err(Error::new(
-                        ErrorKind::Unsupported,
-                        format!("Octave error at line {}, column {}:\nTried to lower octave by 1 but the octave was already at is minimum.", self.current_token.line, self.current_token.column)
-                    ));
-                }
-                self.current_octave -= 1;
-                self.advance();
-                if self.current_token.token_type == TokenType::LessThan {
-                    return self.compile_token();
+    fn compile_transposition(&mut self, semitones: i8, items: &[Item]) -> Vec<u8> {
+        let outer_transposition: Option<i16> = self.transposition;
+        if outer_transposition.is_none() {
+            self.emitted_octave = None;
+        }
+        self.transposition = Some(outer_transposition.unwrap_or(0) + semitones as i16);
+        let mut result: Vec<u8> = Vec::new();
+        self.compile_items(items, &mut result);
+        self.transposition = outer_transposition;
+        if outer_transposition.is_none() && self.emitted_octave.is_some_and(|octave| octave != self.current_octave) {
+            result.push(0xD0 | (self.current_octave - 1));
+        }
+        result
+    }
+
+    fn compile_item(&mut self, item: &Item, next_item: Option<&Item>) -> Result<Vec<u8>, Diagnostic> {
+        let index: usize = self.compiled_items.len();
+        let result: Vec<u8> = self.compile_transposable_item(item, next_item)?;
+        let bytes: Vec<u8> = match item {
+            Item::Loop { .. } => result[..2].to_vec(),
+            _ => result.clone()
+        };
+        let octave: u8 = match (self.transposition, item) {
+            (Some(_), Item::Note { .. }) => self.emitted_octave.unwrap_or(self.current_octave),
+            _ => self.current_octave
+        };
+        if !matches!(item, Item::Transposition { .. }) {
+            // Inserted before the items of a loop.
+            self.compiled_items.insert(index, CompiledItem { span: item.span(), octave, duration: self.current_duration, bytes });
+        }
+        match item {
+            Item::Note { .. } | Item::Rest { .. } => self.timeline.push(Timed::Ticks(Duration::nibble_ticks(self.current_duration) as u64)),
+            Item::BarLine { span } => self.timeline.push(Timed::BarLine(*span)),
+            Item::TimeSignature { beats, unit, .. } => self.timeline.push(Timed::TimeSignature { beats: *beats, unit: *unit }),
+            _ => {}
+        }
+        Ok(result)
+    }
+
+    fn compile_transposable_item(&mut self, item: &Item, next_item: Option<&Item>) -> Result<Vec<u8>, Diagnostic> {
+        if let Some(transposition) = self.transposition {
+            match item {
+                Item::Note { pitch, duration, span } => {
+                    if let Some(duration) = duration {
+                        self.current_duration = duration.encode().unwrap_or(0);
+                    }
+                    return self.compile_transposed_note(*pitch, transposition, *span);
+                },
+                Item::Octave { .. } | Item::OctaveUp { .. } | Item::OctaveDown { .. } => {
+                    self.compile_item_untransposed(item, next_item)?;
+                    return Ok(Vec::new());
+                },
+                Item::Loop { .. } => {
+                    // Every repetition must set its octave, a loop without notes keeps the octave as is.
+                    let outer_octave: Option<u8> = self.emitted_octave.take();
+                    let result: Result<Vec<u8>, Diagnostic> = self.compile_item_untransposed(item, next_item);
+                    self.emitted_octave = self.emitted_octave.or(outer_octave);
+                    return result;
+                },
+                Item::MacroCall { span, .. } | Item::NamedMacroCall { span, .. } => return Err(Diagnostic::error(
+                    DiagnosticCode::UntransposableCommand,
+                    "Macro calls can't be transposed at compile time".to_string(),
+                    *span
+                ).with_help("Transpose the macro itself, or use a snippet.".to_string())),
+                _ => {}
+            }
+        }
+        self.compile_item_untransposed(item, next_item)
+    }
+
+    fn compile_item_untransposed(&mut self, item: &Item, next_item: Option<&Item>) -> Result<Vec<u8>, Diagnostic> {
+        match item {
+            Item::Note { pitch, duration, .. } => {
+                if let Some(duration) = duration {
+                    self.current_duration = duration.encode().unwrap_or(0);
                 }
-                Ok(vec![0xD0 | (self.current_octave - 1)])
+                Ok(vec![((pitch.semitone() + 1) << 4) | self.current_duration])
             },
-            TokenType::GreaterThan => {
-                if self.current_octave >= 5 {
-                    return Err(Error::new(
-                        ErrorKind::Unsupported,
-                        format!("Octave error at line {}, column {}:\nTried to upper octave by 1 but the octave was already at is maximum.", self.current_token.line, self.current_token.column)
-                    ));
-                }
-                self.current_octave += 1;
-                self.advance();
-                if self.current_token.token_type == TokenType::GreaterThan {
-                    return self.compile_token();
+            Item::Rest { duration, .. } => {
+                if let Some(duration) = duration {
+                    self.current_duration = duration.encode().unwrap_or(0);
                 }
-                Ok(vec![0xD0 | (self.current_octave - 1)])
+                Ok(vec![self.current_duration])
             },
-            TokenType::LeftParen => self.compile_loop(),
-            TokenType::Command => self.compile_command(),
-            TokenType::EndOfFile => {
-                Err(Error::new(
-                    ErrorKind::UnexpectedEof,
-                    format!("Unexpected end of file {} at line {}, column {}.\nExpected @, <, >, [, ] or a command.", self.current_token.value, self.current_token.line, self.current_token.column)
-                ))
-            }
-            _ => {
-                Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Unexpected token {} at line {}, column {}.\nExpected @, <, >, [, ] or a command.", self.current_token.value, self.current_token.line, self.current_token.column)
-                ))
+            Item::Octave { octave, .. } => self.compile_octave(octave),
+            Item::OctaveUp { span } => self.compile_octave_shift(true, *span, next_item),
+            Item::OctaveDown { span } => self.compile_octave_shift(false, *span, next_item),
+            Item::Volume { volume, .. } => self.compile_volume(volume),
+            Item::Loop { times, items, .. } => {
+                let outer_timeline: Vec<Timed> = std::mem::take(&mut self.timeline);
+                let mut result: Vec<u8> = vec![0xF0, *times];
+                self.compile_items(items, &mut result);
+                result.push(0xF1);
+                let body: Vec<Timed> = std::mem::replace(&mut self.timeline, outer_timeline);
+                self.timeline.push(Timed::Loop { times: *times, body });
+                Ok(result)
+            },
+            Item::Transposition { semitones, items, .. } => Ok(self.compile_transposition(semitones.value, items)),
+            Item::MacroCall { number, span } => {
+                let bytes: Vec<u8> = self.compile_macro_call(number)?;
+                self.macro_calls.push((bytes[1], *span));
+                Ok(bytes)
+            },
+            Item::NamedMacroCall { name, span } => {
+                let bytes: Vec<u8> = self.compile_named_macro_call(name)?;
+                self.macro_calls.push((bytes[1], *span));
+                Ok(bytes)
+            },
+            Item::Tempo { tempo, .. } => Ok(vec![0xF3, *tempo]),
+            Item::Transpose { value, span } => {
+                self.check_extended_command(ExtendedCommand::Transpose, *span)?;
+                Ok(vec![0xF4, *value])
+            },
+            Item::Instrument { value, span } => {
+                self.check_extended_command(ExtendedCommand::Instrument, *span)?;
+                Ok(vec![0xF5, *value])
+            },
+            Item::Tie { span } => {
+                self.check_extended_command(ExtendedCommand::Tie, *span)?;
+                Ok(vec![0xF6])
+            },
+            Item::Panning { value, span } => {
+                self.check_extended_command(ExtendedCommand::Panning, *span)?;
+                Ok(vec![0xF7, *value])
+            },
+            Item::Stop { span } => {
+                self.check_extended_command(ExtendedCommand::Stop, *span)?;
+                Ok(vec![0xF8])
+            },
+            Item::BarLine { .. } | Item::TimeSignature { .. } => Ok(Vec::new())
+        }
+    }
+
+    fn compile_items(&mut self, items: &[Item], result: &mut Vec<u8>) {
+        for (index, item) in items.iter().enumerate() {
+            match self.compile_item(item, items.get(index + 1)) {
+                Ok(mut compiled_item) => result.append(&mut compiled_item),
+                Err(diagnostic) => self.errors.push(diagnostic)
             }
         }
     }
 
-    pub fn compile(&mut self) -> Result<Vec<u8>, Error> {
+    fn generate(&mut self, song: &Song) -> Result<Vec<u8>, Vec<Diagnostic>> {
         let mut result: Vec<u8> = Vec::new();
-        let num_of_headers: usize = self.tokens.clone().into_iter()
-            .filter(|token| token.token_type == TokenType::Arobase)
-            .collect::<Vec<Token>>()
-            .len();
-        if num_of_headers > 254 {
-            return Err(Error::new(
-                ErrorKind::Unsupported,
-                format!("MMML files can support 255 headers max. Found {}", num_of_headers)
-            ));
-        } else if num_of_headers < 4 {
-            return Err(Error::new(
-                ErrorKind::Unsupported,
-                format!("MMML files require 4 headers min. Found {}", num_of_headers)
+        self.current_octave = DEFAULT_OCTAVE;
+        self.current_duration = 0;
+        self.transposition = None;
+        let first_span: Span = song.sections().next().map(|section| section.span).unwrap_or_default();
+        let num_of_headers: usize = song.channels.len() + song.macros.len();
+        self.num_of_headers = if num_of_headers > 254 {
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::TooManyHeaders,
+                format!("MMML files can support 255 headers max. Found {}", num_of_headers),
+                first_span
             ));
-        }
-        self.num_of_headers = num_of_headers as u8;
+            254
+        } else if num_of_headers < self.target.channels as usize {
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::NotEnoughHeaders,
+                format!("MMML files require {} headers min. Found {}", self.target.channels, num_of_headers),
+                first_span
+            ).with_help(format!("Each of the {} channels starts with an '@'.", self.target.channels)));
+            self.target.channels
+        } else {
+            num_of_headers as u8
+        };
+        self.collect_section_names(song);
+        self.used_macros.clear();
+        self.compiled_items.clear();
 
         result.append(&mut vec![0; num_of_headers * 2]);
-
-        if self.current_token.token_type != TokenType::Arobase {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "The file do not start with an '@'.".to_string()
-            ));
-        }
-        self.advance();
-
-        let mut headers_positions: Vec<usize> = vec![result.len()];
-
-        while !self.is_end_of_file() {
-            let mut compiled_command: Vec<u8> = self.compile_token()?;
-            if compiled_command == [0xFF] {
-                headers_positions.push(result.len() + 1);
+        let mut headers_positions: Vec<usize> = Vec::new();
+        let mut timelines: Vec<Vec<Timed>> = Vec::new();
+        let mut calls: Vec<Vec<(u8, Span)>> = Vec::new();
+        for section in song.sections() {
+            if !headers_positions.is_empty() {
+                result.push(0xFF);
             }
-            result.append(&mut compiled_command);
+            headers_positions.push(result.len());
+            self.compile_items(&section.items, &mut result);
+            timelines.push(std::mem::take(&mut self.timeline));
+            calls.push(std::mem::take(&mut self.macro_calls));
         }
+        // Macros are checked from the channels, then as if a channel called them for the ones never called.
+        let mut visited: HashSet<(usize, usize)> = HashSet::new();
+        for section in 0..calls.len() {
+            let depth: usize = if section < song.channels.len() { 0 } else { 1 };
+            self.check_macro_depth(section, depth, song.channels.len(), &calls, &mut visited);
+        }
+        self.check_unused_macros(song);
+        self.check_bars(song, &timelines);
 
         result.push(0xFF);
         //To prevent µMML player to crash & µMML driver to access out of bound.
-        result.push(0x00); 
-        if result.len() > u16::MAX.into() {
-            return Err(Error::new(
-                ErrorKind::Unsupported,
-                format!("Compiled music program if over the 16-bit limit!\nProgram size: {}", result.len())
+        result.push(0x00);
+        if result.len() > self.target.max_data_size {
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::ProgramTooLarge,
+                format!("Compiled music program if over the {} bytes limit of target {}! Program size: {}", self.target.max_data_size, self.target.name, result.len()),
+                song.sections().last().map(|section| section.span).unwrap_or_default()
             ));
         }
+        if !self.errors.is_empty() {
+            let mut errors: Vec<Diagnostic> = std::mem::take(&mut self.errors);
+            errors.sort_by_key(|error| (error.span.file, error.span.offset));
+            errors.truncate(self.max_errors.unwrap_or(errors.len()));
+            return Err(errors);
+        }
 
         for (index, &position) in headers_positions.iter().enumerate() {
             let header_pos: u16 = position as u16;
@@ -345,4 +556,33 @@ impl Compiler {
         }
         Ok(result)
     }
+
+    /// Compiles a song built without source code.
+    pub fn compile_song(&mut self, song: &Song) -> Result<Vec<u8>, Vec<Diagnostic>> {
+        self.errors.clear();
+        self.warnings.clear();
+        self.generate(song)
+    }
+
+    /// Preprocesses and parses the tokens, recovering from errors.
+    pub fn parse(&self) -> (Song, Vec<Diagnostic>) {
+        let (tokens, preprocessor_errors): (Vec<Token>, Vec<Diagnostic>) = Preprocessor::new().preprocess(&self.tokens);
+        let mut errors: Vec<Diagnostic> = self.lexer_errors.clone();
+        errors.extend(preprocessor_errors);
+        let mut parser: Parser = Parser::new(tokens);
+        parser.set_channels(self.target.channels);
+        if let Some(max_errors) = self.max_errors {
+            parser.set_max_errors(max_errors);
+        }
+        let (song, parse_errors): (Song, Vec<Diagnostic>) = parser.parse_recovering();
+        errors.extend(parse_errors);
+        (song, errors)
+    }
+
+    pub fn compile(&mut self) -> Result<Vec<u8>, Vec<Diagnostic>> {
+        let (song, errors): (Song, Vec<Diagnostic>) = self.parse();
+        self.errors = errors;
+        self.warnings.clear();
+        self.generate(&song)
+    }
 }
@@ -0,0 +1,210 @@
+use std::io::{Error, ErrorKind};
+
+const NOTE_NAMES: [&str; 13] = ["r", "c", "c#", "d", "d#", "e", "f", "f#", "g", "g#", "a", "a#", "b"];
+const DURATIONS: [u8; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
+const LINE_WIDTH: usize = 80;
+
+pub struct Decompiler {
+    data: Vec<u8>,
+    current_index: usize,
+    loop_depth: usize
+}
+
+impl Decompiler {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            current_index: 0,
+            loop_depth: 0
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        if let Some(&byte) = self.data.get(self.current_index) {
+            self.current_index += 1;
+            return Ok(byte);
+        }
+        Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("Unexpected end of data at byte 0x{:04X}.", self.current_index)
+        ))
+    }
+
+    fn read_header(&self, index: usize) -> Result<usize, Error> {
+        match (self.data.get(index * 2), self.data.get(index * 2 + 1)) {
+            (Some(&high), Some(&low)) => Ok(((high as usize) << 8) | low as usize),
+            _ => Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Header {} is out of the data bounds.", index)
+            ))
+        }
+    }
+
+    fn decompile_duration(&self, duration: u8, position: usize) -> Result<String, Error> {
+        if duration < 8 {
+            return Ok(DURATIONS[duration as usize].to_string());
+        }
+        if duration < 0xF {
+            return Ok(format!("{}.", DURATIONS[(duration & 0x7) as usize + 1]));
+        }
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid duration nibble 0x{:X} at byte 0x{:04X}.", duration, position)
+        ))
+    }
+
+    fn decompile_command(&mut self) -> Result<Option<String>, Error> {
+        let position: usize = self.current_index;
+        let byte: u8 = self.read_byte()?;
+        let command: u8 = byte >> 4;
+        let argument: u8 = byte & 0x0F;
+        match command {
+            0x0..=0xC => {
+                let duration: String = self.decompile_duration(argument, position)?;
+                Ok(Some(format!("{}{}", NOTE_NAMES[command as usize], duration)))
+            },
+            0xD => {
+                if argument > 4 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid octave 0x{:02X} at byte 0x{:04X}:\nExpected octave number 1-5.", byte, position)
+                    ));
+                }
+                Ok(Some(format!("o{}", argument + 1)))
+            },
+            0xE => {
+                if argument == 0 || argument > 9 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid volume 0x{:02X} at byte 0x{:04X}:\nExpected volume number 0-8.", byte, position)
+                    ));
+                }
+                Ok(Some(format!("v{}", 9 - argument)))
+            },
+            _ => match byte {
+                0xF0 => {
+                    let times: u8 = self.read_byte()?;
+                    self.loop_depth += 1;
+                    Ok(Some(format!("[{}", times)))
+                },
+                0xF1 => {
+                    if self.loop_depth == 0 {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Loop end without loop start at byte 0x{:04X}.", position)
+                        ));
+                    }
+                    self.loop_depth -= 1;
+                    Ok(Some("]".to_string()))
+                },
+                0xF2 => {
+                    let macro_id: u8 = self.read_byte()?;
+                    if macro_id == u8::MAX {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Invalid macro id 0x{:02X} at byte 0x{:04X}.", macro_id, position)
+                        ));
+                    }
+                    Ok(Some(format!("m{}", macro_id + 1)))
+                },
+                0xF3 => Ok(Some(format!("t{}", self.read_byte()?))),
+                0xF4 => Ok(Some(format!("k{}", self.read_byte()?))),
+                0xF5 => Ok(Some(format!("i{}", self.read_byte()?))),
+                0xF6 => Ok(Some("&".to_string())),
+                0xF7 => Ok(Some(format!("p{}", self.read_byte()?))),
+                0xF8 => Ok(Some("s".to_string())),
+                0xFF => {
+                    if self.loop_depth != 0 {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Loop didn't close at the end of section at byte 0x{:04X}.", position)
+                        ));
+                    }
+                    Ok(None)
+                },
+                _ => Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Unknown command 0x{:02X} at byte 0x{:04X}.", byte, position)
+                ))
+            }
+        }
+    }
+
+    fn decompile_section(&mut self, start: usize) -> Result<Vec<String>, Error> {
+        let mut result: Vec<String> = Vec::new();
+        self.current_index = start;
+        self.loop_depth = 0;
+        while let Some(command) = self.decompile_command()? {
+            result.push(command);
+        }
+        Ok(result)
+    }
+
+    fn format_section(name: &str, commands: &[String]) -> String {
+        let mut result: String = format!("% {}\n@", name);
+        let mut line_length: usize = 1;
+        for command in commands {
+            if line_length + command.len() + 1 > LINE_WIDTH {
+                result.push('\n');
+                line_length = 0;
+            } else {
+                result.push(' ');
+                line_length += 1;
+            }
+            result.push_str(command);
+            line_length += command.len();
+        }
+        result.push('\n');
+        result
+    }
+
+    pub fn decompile(&mut self) -> Result<String, Error> {
+        let first_header: usize = self.read_header(0)?;
+        if !first_header.is_multiple_of(2) || first_header < 8 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid header table size 0x{:04X}.", first_header)
+            ));
+        }
+        let num_of_headers: usize = first_header / 2;
+        let mut result: Vec<String> = Vec::new();
+        for index in 0..num_of_headers {
+            let start: usize = self.read_header(index)?;
+            let commands: Vec<String> = self.decompile_section(start)?;
+            let name: String = if index < 4 {
+                format!("Channel {}", (b'A' + index as u8) as char)
+            } else {
+                format!("Macro {}", index - 3)
+            };
+            result.push(Self::format_section(&name, &commands));
+        }
+        Ok(result.join("\n"))
+    }
+}
+
+/// Extracts the bytes of a C array produced by the `Code` export type.
+pub fn parse_c_array(source: &str) -> Result<Vec<u8>, Error> {
+    let start: usize = source.find('{').ok_or(Error::new(
+        ErrorKind::InvalidData,
+        "No array found in the C source.".to_string()
+    ))?;
+    let end: usize = source[start..].find('}').map(|end| start + end).ok_or(Error::new(
+        ErrorKind::InvalidData,
+        "The array in the C source is not closed.".to_string()
+    ))?;
+    source[start + 1..end]
+        .split(',')
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            let parsed = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => value.parse::<u8>()
+            };
+            parsed.map_err(|_| Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid byte \"{}\" in the C source.", value)
+            ))
+        })
+        .collect()
+}
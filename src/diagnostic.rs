@@ -0,0 +1,365 @@
+use std::{collections::HashMap, fmt::{Display, Formatter}, io::{Error, ErrorKind}};
+
+use crate::token::Token;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticCode {
+    UnexpectedCharacter,
+    NotANumber,
+    InvalidNumber,
+    InvalidDuration,
+    InvalidOctave,
+    InvalidVolume,
+    InvalidMacro,
+    UncompilableCommand,
+    UnknownCommand,
+    UnclosedLoopAtChannelEnd,
+    UnclosedLoopAtEndOfFile,
+    OctaveUnderflow,
+    OctaveOverflow,
+    UnexpectedEndOfFile,
+    UnexpectedToken,
+    TooManyHeaders,
+    NotEnoughHeaders,
+    MissingFirstHeader,
+    ProgramTooLarge,
+    UnsupportedCommand,
+    DuplicateSectionName,
+    UnknownMacroName,
+    IncludeNotFound,
+    IncludeCycle,
+    InvalidDefinition,
+    UnknownDefinition,
+    UnclosedTransposition,
+    TransposedNoteOutOfRange,
+    UntransposableCommand,
+    InvalidTimeSignature,
+    InvalidBarLength,
+    NestedMacroCall,
+    TransposeCommand,
+    InstrumentCommand,
+    TieCommand,
+    PanningCommand,
+    StopCommand,
+    UnusedMacro,
+    UnsynchronizedChannels,
+    RedundantCommand,
+    OctaveShiftRun,
+    SingleLoop,
+    ZeroLoop,
+    SilentNote,
+    UnreachableCommand
+}
+
+impl DiagnosticCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnexpectedCharacter => "E0001",
+            Self::NotANumber => "E0002",
+            Self::InvalidNumber => "E0003",
+            Self::InvalidDuration => "E0004",
+            Self::InvalidOctave => "E0005",
+            Self::InvalidVolume => "E0006",
+            Self::InvalidMacro => "E0007",
+            Self::UncompilableCommand => "E0008",
+            Self::UnknownCommand => "E0009",
+            Self::UnclosedLoopAtChannelEnd => "E0010",
+            Self::UnclosedLoopAtEndOfFile => "E0011",
+            Self::OctaveUnderflow => "E0012",
+            Self::OctaveOverflow => "E0013",
+            Self::UnexpectedEndOfFile => "E0014",
+            Self::UnexpectedToken => "E0015",
+            Self::TooManyHeaders => "E0016",
+            Self::NotEnoughHeaders => "E0017",
+            Self::MissingFirstHeader => "E0018",
+            Self::ProgramTooLarge => "E0019",
+            Self::UnsupportedCommand => "E0020",
+            Self::DuplicateSectionName => "E0021",
+            Self::UnknownMacroName => "E0022",
+            Self::IncludeNotFound => "E0023",
+            Self::IncludeCycle => "E0024",
+            Self::InvalidDefinition => "E0025",
+            Self::UnknownDefinition => "E0026",
+            Self::UnclosedTransposition => "E0027",
+            Self::TransposedNoteOutOfRange => "E0028",
+            Self::UntransposableCommand => "E0029",
+            Self::InvalidTimeSignature => "E0030",
+            Self::InvalidBarLength => "E0031",
+            Self::NestedMacroCall => "E0032",
+            Self::TransposeCommand => "W0001",
+            Self::InstrumentCommand => "W0002",
+            Self::TieCommand => "W0003",
+            Self::PanningCommand => "W0004",
+            Self::StopCommand => "W0005",
+            Self::UnusedMacro => "W0006",
+            Self::UnsynchronizedChannels => "W0007",
+            Self::RedundantCommand => "W0008",
+            Self::OctaveShiftRun => "W0009",
+            Self::SingleLoop => "W0010",
+            Self::ZeroLoop => "W0011",
+            Self::SilentNote => "W0012",
+            Self::UnreachableCommand => "W0013"
+        }
+    }
+
+    /// Name of a warning, used to allow or deny it.
+    pub fn warning_name(&self) -> Option<&'static str> {
+        match self {
+            Self::TransposeCommand => Some("transpose"),
+            Self::InstrumentCommand => Some("instrument"),
+            Self::TieCommand => Some("tie"),
+            Self::PanningCommand => Some("panning"),
+            Self::StopCommand => Some("stop"),
+            Self::UnusedMacro => Some("unused-macro"),
+            Self::UnsynchronizedChannels => Some("unsynchronized-channels"),
+            Self::RedundantCommand => Some("redundant-command"),
+            Self::OctaveShiftRun => Some("octave-shift-run"),
+            Self::SingleLoop => Some("single-loop"),
+            Self::ZeroLoop => Some("zero-loop"),
+            Self::SilentNote => Some("silent-note"),
+            Self::UnreachableCommand => Some("unreachable-command"),
+            _ => None
+        }
+    }
+
+    /// Finds a warning by its name or its code.
+    pub fn from_warning_name(name: &str) -> Option<Self> {
+        WARNINGS.into_iter().find(|code| code.warning_name() == Some(name) || code.as_str() == name)
+    }
+}
+
+pub const WARNINGS: [DiagnosticCode; 13] = [
+    DiagnosticCode::TransposeCommand,
+    DiagnosticCode::InstrumentCommand,
+    DiagnosticCode::TieCommand,
+    DiagnosticCode::PanningCommand,
+    DiagnosticCode::StopCommand,
+    DiagnosticCode::UnusedMacro,
+    DiagnosticCode::UnsynchronizedChannels,
+    DiagnosticCode::RedundantCommand,
+    DiagnosticCode::OctaveShiftRun,
+    DiagnosticCode::SingleLoop,
+    DiagnosticCode::ZeroLoop,
+    DiagnosticCode::SilentNote,
+    DiagnosticCode::UnreachableCommand
+];
+
+impl Display for DiagnosticCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Location of a piece of source code. Lines start at 1, columns at 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    /// Byte offset from the start of the source code.
+    pub offset: usize,
+    /// Length in characters.
+    pub length: usize,
+    /// Index of the source file, 0 being the compiled file.
+    pub file: usize
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, offset: usize, length: usize) -> Self {
+        Self {
+            line,
+            column,
+            offset,
+            length,
+            file: 0
+        }
+    }
+}
+
+impl From<&Token> for Span {
+    fn from(token: &Token) -> Self {
+        Self {
+            file: token.file,
+            ..Self::new(token.line, token.column, token.offset, token.value.chars().count().max(1))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: DiagnosticCode,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub help: Option<String>
+}
+
+impl Diagnostic {
+    pub fn error(code: DiagnosticCode, message: String, span: Span) -> Self {
+        Self {
+            code,
+            severity: Severity::Error,
+            message,
+            span,
+            labels: Vec::new(),
+            help: None
+        }
+    }
+
+    pub fn warning(code: DiagnosticCode, message: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, message, span)
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, message: String) -> Self {
+        self.labels.push(Label { span, message });
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    /// Renders the diagnostic like rustc, with the offending source lines underlined.
+    pub fn render(&self, source: &str, file_name: &str) -> String {
+        self.render_files(&[(file_name, source)])
+    }
+
+    /// Renders the diagnostic with the name and source code of each file, indexed by `Span::file`.
+    pub fn render_files(&self, files: &[(&str, &str)]) -> String {
+        let file = |index: usize| files.get(index).or(files.first()).copied().unwrap_or(("", ""));
+        let gutter: usize = self.labels.iter()
+            .map(|label| label.span.line)
+            .chain([self.span.line])
+            .max()
+            .unwrap_or(1)
+            .to_string()
+            .len();
+        let padding: String = " ".repeat(gutter);
+        let mut result: String = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        result.push_str(&format!("{}--> {}:{}:{}\n", padding, file(self.span.file).0, self.span.line, self.span.column + 1));
+        result.push_str(&format!("{} |\n", padding));
+        let mut underlines: Vec<(Span, char, &str)> = [(self.span, '^', "")]
+            .into_iter()
+            .chain(self.labels.iter().map(|label| (label.span, '-', label.message.as_str())))
+            .collect();
+        underlines.sort_by_key(|(span, _, _)| (span.file != self.span.file, span.file, span.offset));
+        let mut current_file: usize = self.span.file;
+        for (span, marker, message) in underlines {
+            let (file_name, source): (&str, &str) = file(span.file);
+            if span.file != current_file {
+                current_file = span.file;
+                result.push_str(&format!("{}::: {}:{}:{}\n", padding, file_name, span.line, span.column + 1));
+            }
+            let line: &str = source.lines().nth(span.line.wrapping_sub(1)).unwrap_or("");
+            result.push_str(&format!("{:>width$} | {}\n", span.line, line, width = gutter));
+            let underline: String = format!(
+                "{} | {}{} {}",
+                padding,
+                " ".repeat(span.column),
+                marker.to_string().repeat(span.length.max(1)),
+                message
+            );
+            result.push_str(underline.trim_end());
+            result.push('\n');
+        }
+        if let Some(help) = &self.help {
+            result.push_str(&format!("{} |\n{} = help: {}\n", padding, padding, help));
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningLevel {
+    Allow,
+    Warn,
+    Deny
+}
+
+/// Decides which warnings are silenced, kept or turned into errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarningLevels {
+    default_level: WarningLevel,
+    levels: HashMap<DiagnosticCode, WarningLevel>
+}
+
+impl Default for WarningLevels {
+    fn default() -> Self {
+        Self {
+            default_level: WarningLevel::Warn,
+            levels: HashMap::new()
+        }
+    }
+}
+
+impl WarningLevels {
+    /// Sets the level of every warning without a level of its own.
+    pub fn set_default_level(&mut self, level: WarningLevel) {
+        self.default_level = level;
+    }
+
+    pub fn set_level(&mut self, code: DiagnosticCode, level: WarningLevel) {
+        self.levels.insert(code, level);
+    }
+
+    pub fn level(&self, code: DiagnosticCode) -> WarningLevel {
+        self.levels.get(&code).copied().unwrap_or(self.default_level)
+    }
+
+    /// Drops allowed warnings and turns denied ones into errors.
+    pub fn apply(&self, warnings: &[Diagnostic]) -> Vec<Diagnostic> {
+        warnings.iter()
+            .filter_map(|warning| match self.level(warning.code) {
+                WarningLevel::Allow => None,
+                WarningLevel::Warn => Some(warning.clone()),
+                WarningLevel::Deny => Some(Diagnostic {
+                    severity: Severity::Error,
+                    ..warning.clone()
+                })
+            })
+            .collect()
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]: {} at line {}, column {}.", self.severity, self.code, self.message, self.span.line, self.span.column)?;
+        if let Some(help) = &self.help {
+            write!(f, "\n{}", help)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl From<Diagnostic> for Error {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Error::new(ErrorKind::InvalidData, diagnostic.to_string())
+    }
+}
@@ -0,0 +1,280 @@
+use std::{fmt::{Display, Formatter}, io::{Error, ErrorKind}};
+
+use crate::ast::Duration;
+
+pub const CHANNELS: usize = 4;
+/// Size of the loop stack of each channel in the reference driver.
+pub const MAX_LOOPS: usize = 5;
+/// The reference driver keeps a single return address per channel, so macros can't call macros.
+pub const MAX_MACRO_DEPTH: usize = 1;
+/// Tempo used until the first `T` command, as the reference driver has no default in the data.
+pub const DEFAULT_TEMPO: u8 = 64;
+const MAX_COMMANDS_PER_TICK: usize = 0x10000;
+
+/// Faults the reference driver would hit while playing the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriverFault {
+    /// The header table is missing or too small for the channels.
+    InvalidHeaderTable,
+    /// A channel tried to read out of the data bounds.
+    OutOfBounds { channel: usize, address: usize },
+    /// A loop start was found while the loop stack was full.
+    LoopStackOverflow { channel: usize, address: usize },
+    /// A loop end was found without a loop start.
+    LoopStackUnderflow { channel: usize, address: usize },
+    /// A macro was called from a macro.
+    MacroRecursion { channel: usize, address: usize },
+    /// A macro call reads its address past the header table.
+    InvalidMacro { channel: usize, address: usize, macro_id: u8 },
+    /// A command the driver doesn't know.
+    UnknownCommand { channel: usize, address: usize, byte: u8 },
+    /// A channel loops forever without reaching a note.
+    NoNote { channel: usize }
+}
+
+impl Display for DriverFault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHeaderTable => write!(f, "Invalid header table."),
+            Self::OutOfBounds { channel, address } =>
+                write!(f, "Channel {} tried to read out of the data bounds at byte 0x{:04X}.", channel, address),
+            Self::LoopStackOverflow { channel, address } =>
+                write!(f, "Channel {} overflowed the loop stack ({} loops max) at byte 0x{:04X}.", channel, MAX_LOOPS, address),
+            Self::LoopStackUnderflow { channel, address } =>
+                write!(f, "Channel {} found a loop end without loop start at byte 0x{:04X}.", channel, address),
+            Self::MacroRecursion { channel, address } =>
+                write!(f, "Channel {} called a macro from a macro at byte 0x{:04X}.", channel, address),
+            Self::InvalidMacro { channel, address, macro_id } =>
+                write!(f, "Channel {} called macro {} past the header table at byte 0x{:04X}.", channel, *macro_id as usize + 1, address),
+            Self::UnknownCommand { channel, address, byte } =>
+                write!(f, "Channel {} found unknown command 0x{:02X} at byte 0x{:04X}.", channel, byte, address),
+            Self::NoNote { channel } =>
+                write!(f, "Channel {} never reaches a note.", channel)
+        }
+    }
+}
+
+impl std::error::Error for DriverFault {}
+
+impl From<DriverFault> for Error {
+    fn from(fault: DriverFault) -> Self {
+        Error::new(ErrorKind::InvalidData, fault.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopFrame {
+    /// Address of the first command of the loop body.
+    pub start: usize,
+    /// Number of times the body will be played again.
+    pub remaining: u8
+}
+
+/// State of a channel as the reference driver stores it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelState {
+    pub start: usize,
+    pub program_counter: usize,
+    pub loop_stack: Vec<LoopFrame>,
+    pub macro_stack: Vec<usize>,
+    /// Octave as encoded in the data (0-4 for µMML octaves 1-5).
+    pub octave: u8,
+    /// Volume as encoded in the data (1 loudest, 9 silent).
+    pub volume: u8,
+    /// Current note (0 is a rest, 1-12 are C to B).
+    pub note: u8,
+    /// Ticks left for the current note after the current tick.
+    pub remaining_ticks: u16,
+    /// Number of times the channel went back to its start.
+    pub loop_count: usize,
+    pub stopped: bool,
+    played_note: bool
+}
+
+impl ChannelState {
+    fn new(start: usize) -> Self {
+        Self {
+            start,
+            program_counter: start,
+            loop_stack: Vec::new(),
+            macro_stack: Vec::new(),
+            octave: 3,
+            volume: 1,
+            note: 0,
+            remaining_ticks: 0,
+            loop_count: 0,
+            stopped: false,
+            played_note: false
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.stopped || self.loop_count > 0
+    }
+}
+
+/// Tick by tick emulation of the reference µMML driver.
+pub struct Driver {
+    data: Vec<u8>,
+    num_of_headers: usize,
+    channels: Vec<ChannelState>,
+    tempo: u8,
+    ticks: u64
+}
+
+impl Driver {
+    pub fn new(data: Vec<u8>) -> Result<Self, DriverFault> {
+        Self::with_channels(data, CHANNELS)
+    }
+
+    /// Emulates a driver built for another number of channels, like the channels of a target.
+    pub fn with_channels(data: Vec<u8>, channels: usize) -> Result<Self, DriverFault> {
+        let mut driver: Driver = Self {
+            data,
+            num_of_headers: 0,
+            channels: Vec::new(),
+            tempo: DEFAULT_TEMPO,
+            ticks: 0
+        };
+        let first_header: usize = driver.read_header(0).map_err(|_| DriverFault::InvalidHeaderTable)?;
+        if first_header < channels * 2 || !first_header.is_multiple_of(2) {
+            return Err(DriverFault::InvalidHeaderTable);
+        }
+        driver.num_of_headers = first_header / 2;
+        for index in 0..channels {
+            let start: usize = driver.read_header(index).map_err(|_| DriverFault::InvalidHeaderTable)?;
+            driver.channels.push(ChannelState::new(start));
+        }
+        Ok(driver)
+    }
+
+    pub fn channels(&self) -> &[ChannelState] {
+        &self.channels
+    }
+
+    pub fn tempo(&self) -> u8 {
+        self.tempo
+    }
+
+    /// Number of ticks played since the start.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Whether every channel reached its end or stopped at least once.
+    pub fn is_finished(&self) -> bool {
+        self.channels.iter().all(ChannelState::is_finished)
+    }
+
+    fn read_byte(&self, address: usize) -> Result<u8, usize> {
+        self.data.get(address).copied().ok_or(address)
+    }
+
+    fn read_header(&self, index: usize) -> Result<usize, usize> {
+        Ok(((self.read_byte(index * 2)? as usize) << 8) | self.read_byte(index * 2 + 1)? as usize)
+    }
+
+    /// Reads commands of a channel until it reaches a note.
+    fn step_channel(&mut self, index: usize) -> Result<(), DriverFault> {
+        let out_of_bounds = |address: usize| DriverFault::OutOfBounds { channel: index, address };
+        for _ in 0..MAX_COMMANDS_PER_TICK {
+            let channel: &ChannelState = &self.channels[index];
+            if channel.stopped {
+                return Ok(());
+            }
+            let address: usize = channel.program_counter;
+            let byte: u8 = self.read_byte(address).map_err(out_of_bounds)?;
+            let argument: u8 = byte & 0x0F;
+            match byte >> 4 {
+                0x0..=0xC => {
+                    let channel: &mut ChannelState = &mut self.channels[index];
+                    channel.note = byte >> 4;
+                    channel.remaining_ticks = Duration::nibble_ticks(argument);
+                    channel.played_note = true;
+                    channel.program_counter += 1;
+                    return Ok(());
+                },
+                0xD => {
+                    self.channels[index].octave = argument;
+                    self.channels[index].program_counter += 1;
+                },
+                0xE => {
+                    self.channels[index].volume = argument;
+                    self.channels[index].program_counter += 1;
+                },
+                _ => match byte {
+                    0xF0 => {
+                        let times: u8 = self.read_byte(address + 1).map_err(out_of_bounds)?;
+                        let channel: &mut ChannelState = &mut self.channels[index];
+                        if channel.loop_stack.len() >= MAX_LOOPS {
+                            return Err(DriverFault::LoopStackOverflow { channel: index, address });
+                        }
+                        channel.loop_stack.push(LoopFrame { start: address + 2, remaining: times.wrapping_sub(1) });
+                        channel.program_counter += 2;
+                    },
+                    0xF1 => {
+                        let channel: &mut ChannelState = &mut self.channels[index];
+                        match channel.loop_stack.last_mut() {
+                            Some(frame) if frame.remaining > 0 => {
+                                frame.remaining -= 1;
+                                channel.program_counter = frame.start;
+                            },
+                            Some(_) => {
+                                channel.loop_stack.pop();
+                                channel.program_counter += 1;
+                            },
+                            None => return Err(DriverFault::LoopStackUnderflow { channel: index, address })
+                        }
+                    },
+                    0xF2 => {
+                        let macro_id: u8 = self.read_byte(address + 1).map_err(out_of_bounds)?;
+                        if self.channels[index].macro_stack.len() >= MAX_MACRO_DEPTH {
+                            return Err(DriverFault::MacroRecursion { channel: index, address });
+                        }
+                        if macro_id as usize + self.channels.len() >= self.num_of_headers {
+                            return Err(DriverFault::InvalidMacro { channel: index, address, macro_id });
+                        }
+                        let macro_start: usize = self.read_header(macro_id as usize + self.channels.len()).map_err(out_of_bounds)?;
+                        let channel: &mut ChannelState = &mut self.channels[index];
+                        channel.macro_stack.push(address + 2);
+                        channel.program_counter = macro_start;
+                    },
+                    0xF3 => {
+                        self.tempo = self.read_byte(address + 1).map_err(out_of_bounds)?;
+                        self.channels[index].program_counter += 2;
+                    },
+                    0xF4 | 0xF5 | 0xF7 => self.channels[index].program_counter += 2,
+                    0xF6 => self.channels[index].program_counter += 1,
+                    0xF8 => self.channels[index].stopped = true,
+                    0xFF => {
+                        let channel: &mut ChannelState = &mut self.channels[index];
+                        if let Some(return_address) = channel.macro_stack.pop() {
+                            channel.program_counter = return_address;
+                        } else {
+                            if !channel.played_note {
+                                channel.stopped = true;
+                            }
+                            channel.program_counter = channel.start;
+                            channel.loop_count += 1;
+                        }
+                    },
+                    _ => return Err(DriverFault::UnknownCommand { channel: index, address, byte })
+                }
+            }
+        }
+        Err(DriverFault::NoNote { channel: index })
+    }
+
+    /// Plays one tick: every channel with no note left reads its next note.
+    pub fn tick(&mut self) -> Result<(), DriverFault> {
+        for index in 0..self.channels.len() {
+            if self.channels[index].remaining_ticks == 0 {
+                self.step_channel(index)?;
+            }
+            let channel: &mut ChannelState = &mut self.channels[index];
+            channel.remaining_ticks = channel.remaining_ticks.saturating_sub(1);
+        }
+        self.ticks += 1;
+        Ok(())
+    }
+}
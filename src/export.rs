@@ -0,0 +1,423 @@
+use std::io::{Error, ErrorKind};
+
+use serde::Serialize;
+
+use crate::{midi::export_midi, timing::channel_name};
+
+/// Bytes per line of the C array, as written by the original compiler.
+pub const C_BYTES_PER_LINE: usize = 17;
+const BYTES_PER_LINE: usize = 16;
+/// Largest S0 header record, the name being cut after it.
+const MAX_S_RECORD_HEADER: usize = 64;
+
+/// Output format of the compiled data. Implement it to add a format to a tool using the compiler.
+pub trait Exporter {
+    /// Extension of the output file, without the dot.
+    fn extension(&self) -> &str;
+
+    /// Converts the compiled data, `name` being the name of the music in the output.
+    fn export(&self, data: &[u8], name: &str) -> Result<Vec<u8>, Error>;
+
+    /// Files written next to the output file, as their extension and content.
+    fn companion_files(&self, _data: &[u8], _name: &str) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        Ok(Vec::new())
+    }
+}
+
+/// Bytes written `0xNN`, `per_line` bytes by line joined by `separator`.
+fn hex_lines(data: &[u8], per_line: usize, separator: &str) -> Vec<String> {
+    data.chunks(per_line)
+        .map(|chunk| chunk.iter().map(|byte| format!("0x{:02X}", byte)).collect::<Vec<String>>().join(separator))
+        .collect()
+}
+
+/// Offsets of the header table, channels first then macros.
+fn read_headers(data: &[u8]) -> Result<Vec<u16>, Error> {
+    let read_header = |index: usize| -> Result<u16, Error> {
+        match (data.get(index * 2), data.get(index * 2 + 1)) {
+            (Some(&high), Some(&low)) => Ok(((high as u16) << 8) | low as u16),
+            _ => Err(Error::new(ErrorKind::InvalidData, format!("Header {} is out of the data bounds.", index)))
+        }
+    };
+    let num_of_headers: usize = read_header(0)? as usize / 2;
+    (0..num_of_headers).map(read_header).collect()
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// Checks that the data fits in the 32-bit address space from the base address.
+fn check_address_range(data: &[u8], base_address: u32) -> Result<(), Error> {
+    if base_address as u64 + data.len() as u64 > 1 << 32 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} bytes don't fit in the 32-bit address space from 0x{:08X}.", data.len(), base_address)
+        ));
+    }
+    Ok(())
+}
+
+/// C array with a header declaring it.
+/// The default output is the one of the original compiler, the options adapting it to a platform.
+#[derive(Debug, Clone)]
+pub struct CExporter {
+    bytes_per_line: usize,
+    progmem: bool,
+    section: Option<String>,
+    stdint: bool,
+    is_static: bool,
+    size_define: bool,
+    offset_defines: bool,
+    channels: u8,
+    defines: Vec<(String, String)>
+}
+
+impl Default for CExporter {
+    fn default() -> Self {
+        Self {
+            bytes_per_line: C_BYTES_PER_LINE,
+            progmem: false,
+            section: None,
+            stdint: false,
+            is_static: false,
+            size_define: false,
+            offset_defines: false,
+            channels: 4,
+            defines: Vec::new()
+        }
+    }
+}
+
+impl CExporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_bytes_per_line(&mut self, bytes_per_line: usize) {
+        self.bytes_per_line = bytes_per_line.max(1);
+    }
+
+    /// Stores the array in the program memory of AVR microcontrollers with `PROGMEM`.
+    pub fn set_progmem(&mut self, progmem: bool) {
+        self.progmem = progmem;
+    }
+
+    /// Places the array in a linker section.
+    pub fn set_section(&mut self, section: Option<String>) {
+        self.section = section;
+    }
+
+    /// Uses `uint8_t` from `<stdint.h>` instead of `unsigned char`.
+    pub fn set_stdint(&mut self, stdint: bool) {
+        self.stdint = stdint;
+    }
+
+    /// Makes the array `static`, the header no longer declaring it.
+    pub fn set_static(&mut self, is_static: bool) {
+        self.is_static = is_static;
+    }
+
+    /// Defines `NAME_SIZE` in the header.
+    pub fn set_size_define(&mut self, size_define: bool) {
+        self.size_define = size_define;
+    }
+
+    /// Defines the offset of each channel and macro in the header, channels after Z being numbered from 27.
+    pub fn set_offset_defines(&mut self, offset_defines: bool) {
+        self.offset_defines = offset_defines;
+    }
+
+    /// Number of channels, the following headers being macros.
+    pub fn set_channels(&mut self, channels: u8) {
+        self.channels = channels;
+    }
+
+    /// Adds a `#define` to the header, after the ones of the options.
+    pub fn add_define(&mut self, name: String, value: String) {
+        self.defines.push((name, value));
+    }
+
+    fn byte_type(&self) -> &str {
+        if self.stdint { "uint8_t" } else { "unsigned char" }
+    }
+
+    /// `#include` lines needed by the options.
+    fn includes(&self) -> Option<String> {
+        let mut includes: Vec<&str> = Vec::new();
+        if self.stdint {
+            includes.push("#include <stdint.h>");
+        }
+        if self.progmem {
+            includes.push("#include <avr/pgmspace.h>");
+        }
+        (!includes.is_empty()).then(|| includes.join("\n"))
+    }
+
+    /// Attributes written after the array name.
+    fn attributes(&self) -> String {
+        let mut attributes: String = String::new();
+        if let Some(section) = &self.section {
+            attributes.push_str(&format!(" __attribute__((section(\"{}\")))", section));
+        }
+        if self.progmem {
+            attributes.push_str(" PROGMEM");
+        }
+        attributes
+    }
+
+    fn defines(&self, data: &[u8], name: &str) -> Result<Option<String>, Error> {
+        let mut defines: Vec<String> = Vec::new();
+        if self.size_define {
+            defines.push(format!("#define {}_SIZE {}", name, data.len()));
+        }
+        if self.offset_defines {
+            for (index, offset) in read_headers(data)?.into_iter().enumerate() {
+                let section: String = match index < self.channels as usize {
+                    true => format!("CHANNEL_{}", channel_name(index)),
+                    false => format!("MACRO_{}", index - self.channels as usize + 1)
+                };
+                defines.push(format!("#define {}_{}_OFFSET 0x{:04X}", name, section, offset));
+            }
+        }
+        for (define_name, value) in &self.defines {
+            defines.push(format!("#define {} {}", define_name, value));
+        }
+        Ok((!defines.is_empty()).then(|| defines.join("\n")))
+    }
+}
+
+impl Exporter for CExporter {
+    fn extension(&self) -> &str {
+        "c"
+    }
+
+    fn export(&self, data: &[u8], name: &str) -> Result<Vec<u8>, Error> {
+        let bytes: String = hex_lines(data, self.bytes_per_line, ",").join(",\n\t");
+        let array: String = format!(
+            "{}const {} {}[{}]{} = {{\n\t{}\n}};",
+            if self.is_static { "static " } else { "" }, self.byte_type(), name, data.len(), self.attributes(), bytes
+        );
+        let blocks: Vec<String> = self.includes().into_iter().chain([array]).collect();
+        Ok(blocks.join("\n\n").into_bytes())
+    }
+
+    fn companion_files(&self, data: &[u8], name: &str) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let mut blocks: Vec<String> = vec![format!("#ifndef {0}_H\n#define {0}_H", name)];
+        blocks.extend(self.includes());
+        blocks.extend(self.defines(data, name)?);
+        if !self.is_static {
+            let progmem: &str = if self.progmem { " PROGMEM" } else { "" };
+            blocks.push(format!("extern const {} {}[]{};", self.byte_type(), name, progmem));
+        }
+        blocks.push(String::from("#endif"));
+        Ok(vec![(String::from("h"), blocks.join("\n\n").into_bytes())])
+    }
+}
+
+/// Compiled data as is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RawExporter;
+
+impl Exporter for RawExporter {
+    fn extension(&self) -> &str {
+        "mbf" // µMML Binary File
+    }
+
+    fn export(&self, data: &[u8], _name: &str) -> Result<Vec<u8>, Error> {
+        Ok(data.to_vec())
+    }
+}
+
+/// Standard MIDI File, with loops and macros expanded.
+#[derive(Debug, Clone, Copy)]
+pub struct MidiExporter {
+    /// Number of channels, the following headers being macros.
+    pub channels: u8
+}
+
+impl Default for MidiExporter {
+    fn default() -> Self {
+        Self { channels: 4 }
+    }
+}
+
+impl Exporter for MidiExporter {
+    fn extension(&self) -> &str {
+        "mid"
+    }
+
+    fn export(&self, data: &[u8], _name: &str) -> Result<Vec<u8>, Error> {
+        export_midi(data.to_vec(), self.channels)
+    }
+}
+
+/// Rust `pub static` array.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RustExporter;
+
+impl Exporter for RustExporter {
+    fn extension(&self) -> &str {
+        "rs"
+    }
+
+    fn export(&self, data: &[u8], name: &str) -> Result<Vec<u8>, Error> {
+        let bytes: String = hex_lines(data, BYTES_PER_LINE, ", ").join(",\n    ");
+        Ok(format!("pub static {}: [u8; {}] = [\n    {}\n];\n", name, data.len(), bytes).into_bytes())
+    }
+}
+
+/// GNU assembler `.byte` listing, placed in the program memory of AVR microcontrollers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AssemblyExporter;
+
+impl Exporter for AssemblyExporter {
+    fn extension(&self) -> &str {
+        "s"
+    }
+
+    fn export(&self, data: &[u8], name: &str) -> Result<Vec<u8>, Error> {
+        let mut result: String = format!(
+            "\t.section .progmem.data,\"a\",@progbits\n\t.global {0}\n\t.type {0}, @object\n\t.size {0}, {1}\n{0}:\n",
+            name, data.len()
+        );
+        for line in hex_lines(data, BYTES_PER_LINE, ", ") {
+            result.push_str(&format!("\t.byte {}\n", line));
+        }
+        Ok(result.into_bytes())
+    }
+}
+
+/// Python `bytes` constant.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PythonExporter;
+
+impl Exporter for PythonExporter {
+    fn extension(&self) -> &str {
+        "py"
+    }
+
+    fn export(&self, data: &[u8], name: &str) -> Result<Vec<u8>, Error> {
+        let bytes: String = hex_lines(data, BYTES_PER_LINE, ", ").join(",\n    ");
+        Ok(format!("{} = bytes([\n    {}\n])\n", name, bytes).into_bytes())
+    }
+}
+
+#[derive(Serialize)]
+struct JsonDocument<'a> {
+    name: &'a str,
+    size: usize,
+    /// Offsets of the channels then of the macros.
+    headers: Vec<u16>,
+    data: &'a [u8]
+}
+
+/// JSON document with the bytes and the offsets of the header table.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonExporter;
+
+impl Exporter for JsonExporter {
+    fn extension(&self) -> &str {
+        "json"
+    }
+
+    fn export(&self, data: &[u8], name: &str) -> Result<Vec<u8>, Error> {
+        let document: JsonDocument = JsonDocument {
+            name,
+            size: data.len(),
+            headers: read_headers(data)?,
+            data
+        };
+        let mut result: Vec<u8> = serde_json::to_vec(&document)?;
+        result.push(b'\n');
+        Ok(result)
+    }
+}
+
+/// Intel HEX records loading the data from a base address.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IntelHexExporter {
+    pub base_address: u32
+}
+
+impl IntelHexExporter {
+    fn record(address: u16, record_type: u8, data: &[u8]) -> String {
+        let mut bytes: Vec<u8> = vec![data.len() as u8, (address >> 8) as u8, address as u8, record_type];
+        bytes.extend(data);
+        let checksum: u8 = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+        bytes.push(checksum);
+        format!(":{}\n", hex_string(&bytes))
+    }
+}
+
+impl Exporter for IntelHexExporter {
+    fn extension(&self) -> &str {
+        "hex"
+    }
+
+    fn export(&self, data: &[u8], _name: &str) -> Result<Vec<u8>, Error> {
+        check_address_range(data, self.base_address)?;
+        let mut result: String = String::new();
+        let mut upper_address: u32 = 0;
+        let mut offset: usize = 0;
+        while offset < data.len() {
+            let address: u32 = self.base_address + offset as u32;
+            if address >> 16 != upper_address {
+                upper_address = address >> 16;
+                result.push_str(&Self::record(0, 0x04, &(upper_address as u16).to_be_bytes()));
+            }
+            // A record can't cross a 64 KiB boundary.
+            let length: usize = BYTES_PER_LINE.min(data.len() - offset).min(0x10000 - (address & 0xFFFF) as usize);
+            result.push_str(&Self::record(address as u16, 0x00, &data[offset..offset + length]));
+            offset += length;
+        }
+        result.push_str(&Self::record(0, 0x01, &[]));
+        Ok(result.into_bytes())
+    }
+}
+
+/// Motorola S-records loading the data from a base address, with the shortest addresses that fit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SRecordExporter {
+    pub base_address: u32
+}
+
+impl SRecordExporter {
+    fn record(record_type: u8, address: u32, address_size: usize, data: &[u8]) -> String {
+        let mut bytes: Vec<u8> = vec![(address_size + data.len() + 1) as u8];
+        bytes.extend(&address.to_be_bytes()[4 - address_size..]);
+        bytes.extend(data);
+        let checksum: u8 = !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        bytes.push(checksum);
+        format!("S{}{}\n", record_type, hex_string(&bytes))
+    }
+}
+
+impl Exporter for SRecordExporter {
+    fn extension(&self) -> &str {
+        "srec"
+    }
+
+    fn export(&self, data: &[u8], name: &str) -> Result<Vec<u8>, Error> {
+        check_address_range(data, self.base_address)?;
+        let last_address: u32 = self.base_address + data.len().saturating_sub(1) as u32;
+        let (data_type, end_type, address_size): (u8, u8, usize) = match last_address {
+            0..=0xFFFF => (1, 9, 2),
+            0x10000..=0xFFFFFF => (2, 8, 3),
+            _ => (3, 7, 4)
+        };
+        let header: &[u8] = &name.as_bytes()[..name.len().min(MAX_S_RECORD_HEADER)];
+        let mut result: String = Self::record(0, 0, 2, header);
+        let records: usize = data.len().div_ceil(BYTES_PER_LINE);
+        for (index, chunk) in data.chunks(BYTES_PER_LINE).enumerate() {
+            let address: u32 = self.base_address + (index * BYTES_PER_LINE) as u32;
+            result.push_str(&Self::record(data_type, address, address_size, chunk));
+        }
+        match records {
+            0..=0xFFFF => result.push_str(&Self::record(5, records as u32, 2, &[])),
+            _ => result.push_str(&Self::record(6, records as u32, 3, &[]))
+        }
+        result.push_str(&Self::record(end_type, self.base_address, address_size, &[]));
+        Ok(result.into_bytes())
+    }
+}
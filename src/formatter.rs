@@ -0,0 +1,222 @@
+use crate::token::{Token, TokenType};
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// A line of formatted source code.
+#[derive(Debug, Clone, Default)]
+struct Line {
+    words: Vec<String>,
+    comment: Option<String>,
+    /// Index of the `@` section of the line, `None` before the first one.
+    section: Option<usize>,
+    /// A definition lasts until the end of its line, so it is never wrapped.
+    is_definition: bool
+}
+
+impl Line {
+    fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.comment.is_none()
+    }
+}
+
+/// Text of a token, commands in lowercase and sharps written `#`.
+fn token_text(token: &Token) -> String {
+    match token.token_type {
+        TokenType::Command => token.value.to_lowercase(),
+        TokenType::TimeSignature => format!("#time {}", token.value.trim_start_matches("#time").trim()),
+        TokenType::Include => format!("#include \"{}\"", token.include_path()),
+        _ => token.value.clone()
+    }
+}
+
+/// Whether a token is written right after the previous one, like the `4` of `c4` or the `>` of `>c`.
+fn is_attached(previous: &Token, token: &Token) -> bool {
+    let is_note: bool = token.token_type == TokenType::Command && token.value.starts_with(|ch: char| "abcdefgrABCDEFGR".contains(ch));
+    if previous.token_type == TokenType::ArgumentsStart {
+        return true;
+    }
+    match token.token_type {
+        TokenType::Number => matches!(previous.token_type, TokenType::Command | TokenType::LeftParen),
+        TokenType::Dot | TokenType::ArgumentsStart | TokenType::ArgumentsEnd | TokenType::Comma => true,
+        TokenType::Name => previous.token_type == TokenType::Command,
+        TokenType::Label => previous.token_type == TokenType::Arobase,
+        TokenType::LessThan | TokenType::GreaterThan => matches!(previous.token_type, TokenType::LessThan | TokenType::GreaterThan),
+        _ => is_note && matches!(previous.token_type, TokenType::LessThan | TokenType::GreaterThan)
+    }
+}
+
+/// Reformats µMML source code from its tokens, trivia included.
+/// Line breaks are kept, each `@` section starting on a new line and long lines being wrapped.
+pub struct Formatter {
+    width: usize,
+    align: bool,
+    channels: u8
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_WIDTH,
+            align: false,
+            channels: 4
+        }
+    }
+}
+
+impl Formatter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum length of a line, longer lines being wrapped between two commands.
+    pub fn set_width(&mut self, width: usize) {
+        self.width = width;
+    }
+
+    /// Aligns the bar lines of the channels, the nth line of a channel facing the nth line of the others.
+    pub fn set_align(&mut self, align: bool) {
+        self.align = align;
+    }
+
+    /// Number of channels, the following sections being macros.
+    pub fn set_channels(&mut self, channels: u8) {
+        self.channels = channels;
+    }
+
+    fn end_line(lines: &mut Vec<Line>, line: &mut Line) {
+        if !line.is_empty() {
+            lines.push(std::mem::take(line));
+        }
+    }
+
+    fn split_lines(&self, tokens: &[Token]) -> Vec<Line> {
+        let mut lines: Vec<Line> = Vec::new();
+        let mut line: Line = Line::default();
+        let mut section: Option<usize> = None;
+        let mut previous: Option<&Token> = None;
+        for token in tokens {
+            match token.token_type {
+                TokenType::Whitespace => {
+                    let line_breaks: usize = token.value.matches('\n').count();
+                    if line_breaks > 0 {
+                        Self::end_line(&mut lines, &mut line);
+                    }
+                    if line_breaks > 1 && lines.last().is_some_and(|last_line| !last_line.is_empty()) {
+                        lines.push(Line::default());
+                    }
+                    continue;
+                },
+                TokenType::Comment => {
+                    line.comment = Some(token.value.trim_end().to_string());
+                    line.section = line.section.or(section);
+                    Self::end_line(&mut lines, &mut line);
+                    continue;
+                },
+                TokenType::EndOfFile => break,
+                TokenType::Arobase if !line.is_definition => {
+                    Self::end_line(&mut lines, &mut line);
+                    section = Some(section.map_or(0, |section| section + 1));
+                },
+                TokenType::Include => Self::end_line(&mut lines, &mut line),
+                TokenType::Equals => line.is_definition = true,
+                _ => {}
+            }
+            if line.words.is_empty() {
+                line.section = section;
+            }
+            let text: String = token_text(token);
+            match (previous, line.words.last_mut()) {
+                (Some(previous), Some(word)) if is_attached(previous, token) => word.push_str(&text),
+                _ => line.words.push(text)
+            }
+            previous = Some(token);
+            if token.token_type == TokenType::Include {
+                Self::end_line(&mut lines, &mut line);
+            }
+        }
+        Self::end_line(&mut lines, &mut line);
+        lines
+    }
+
+    fn wrap(&self, line: Line) -> Vec<Line> {
+        if line.is_definition {
+            return vec![line];
+        }
+        let mut lines: Vec<Line> = Vec::new();
+        let mut current_line: Line = Line { section: line.section, ..Line::default() };
+        let mut length: usize = 0;
+        for word in line.words {
+            let word_length: usize = word.chars().count();
+            if !current_line.words.is_empty() && length + 1 + word_length > self.width {
+                lines.push(std::mem::replace(&mut current_line, Line { section: line.section, ..Line::default() }));
+                length = 0;
+            }
+            length += word_length + if current_line.words.is_empty() { 0 } else { 1 };
+            current_line.words.push(word);
+        }
+        current_line.comment = line.comment;
+        lines.push(current_line);
+        lines
+    }
+
+    /// Pads the bars of the nth lines of the channels to the same width.
+    fn align_channels(&self, lines: &mut [Line]) {
+        let channel_lines: Vec<Vec<usize>> = (0..self.channels as usize)
+            .map(|channel| (0..lines.len())
+                .filter(|&index| lines[index].section == Some(channel) && !lines[index].words.is_empty())
+                .collect())
+            .collect();
+        let rows: usize = channel_lines.iter().map(Vec::len).max().unwrap_or(0);
+        for row in 0..rows {
+            let row_lines: Vec<usize> = channel_lines.iter().filter_map(|indices| indices.get(row).copied()).collect();
+            let bars: Vec<Vec<String>> = row_lines.iter()
+                .map(|&index| lines[index].words
+                    .split_inclusive(|word| word == "|")
+                    .map(|bar| bar.join(" "))
+                    .collect())
+                .collect();
+            let mut widths: Vec<usize> = Vec::new();
+            for line_bars in &bars {
+                for (bar_index, bar) in line_bars.iter().enumerate() {
+                    if bar_index == widths.len() {
+                        widths.push(0);
+                    }
+                    widths[bar_index] = widths[bar_index].max(bar.chars().count());
+                }
+            }
+            for (&index, line_bars) in row_lines.iter().zip(bars) {
+                let last_bar: usize = line_bars.len() - 1;
+                lines[index].words = line_bars.into_iter()
+                    .enumerate()
+                    .map(|(bar_index, bar)| match bar_index == last_bar {
+                        true => bar,
+                        false => format!("{:width$}", bar, width = widths[bar_index])
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    pub fn format(&self, tokens: &[Token]) -> String {
+        let mut lines: Vec<Line> = self.split_lines(tokens).into_iter().flat_map(|line| self.wrap(line)).collect();
+        if self.align {
+            self.align_channels(&mut lines);
+        }
+        while lines.last().is_some_and(Line::is_empty) {
+            lines.pop();
+        }
+        let mut result: String = String::new();
+        for line in lines {
+            let mut text: String = line.words.join(" ");
+            if let Some(comment) = &line.comment {
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                text.push_str(comment);
+            }
+            result.push_str(text.trim_end());
+            result.push('\n');
+        }
+        result
+    }
+}
@@ -0,0 +1,143 @@
+use std::{collections::HashSet, path::{Path, PathBuf}};
+
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCode, Span},
+    lexer::Lexer,
+    token::{Token, TokenType}
+};
+
+/// A file loaded by the includer, its index being used by `Span::file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub source: String
+}
+
+/// Tokenizes a song, replacing its `#include "path"` directives with the tokens of the included files.
+/// Paths are looked up next to the including file, then in the include paths.
+/// A file is included once, later includes of it being ignored.
+#[derive(Debug, Default)]
+pub struct Includer {
+    include_paths: Vec<PathBuf>,
+    files: Vec<SourceFile>,
+    /// Files being included, to detect cycles.
+    stack: Vec<PathBuf>,
+    /// Canonical paths of the loaded files, a file included twice being loaded once.
+    loaded: HashSet<PathBuf>,
+    errors: Vec<Diagnostic>
+}
+
+impl Includer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory searched for included files, like the `-I` option.
+    pub fn add_include_path(&mut self, path: PathBuf) {
+        self.include_paths.push(path);
+    }
+
+    /// Loaded files, the first one being the compiled file.
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Names and sources of the loaded files, to render diagnostics.
+    pub fn sources(&self) -> Vec<(String, &str)> {
+        self.files.iter()
+            .map(|file| (file.path.display().to_string(), file.source.as_str()))
+            .collect()
+    }
+
+    fn find(&self, name: &str, including_file: usize) -> Option<PathBuf> {
+        let directory: &Path = self.files[including_file].path.parent().unwrap_or(Path::new(""));
+        [directory].into_iter()
+            .chain(self.include_paths.iter().map(PathBuf::as_path))
+            .map(|directory| directory.join(name))
+            .find(|path| path.is_file())
+    }
+
+    fn include(&mut self, token: &Token) -> Vec<Token> {
+        let name: &str = token.include_path();
+        let Some(path) = self.find(name, token.file) else {
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::IncludeNotFound,
+                format!("Could not find included file {}", name),
+                Span::from(token)
+            ).with_help("Included files are looked up next to the including file, then in the -I paths.".to_string()));
+            return Vec::new();
+        };
+        let canonical_path: PathBuf = path.canonicalize().unwrap_or(path.clone());
+        if let Some(position) = self.stack.iter().position(|included| *included == canonical_path) {
+            let cycle: Vec<String> = self.stack[position..].iter()
+                .chain([&canonical_path])
+                .map(|included| included.file_name().unwrap_or_default().to_string_lossy().to_string())
+                .collect();
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::IncludeCycle,
+                format!("File {} includes itself", name),
+                Span::from(token)
+            ).with_help(format!("Include cycle: {}.", cycle.join(" -> "))));
+            return Vec::new();
+        }
+        if !self.loaded.insert(canonical_path.clone()) {
+            return Vec::new();
+        }
+        let source: String = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) => {
+                self.errors.push(Diagnostic::error(
+                    DiagnosticCode::IncludeNotFound,
+                    format!("Could not read included file {}: {}", name, err),
+                    Span::from(token)
+                ));
+                return Vec::new();
+            }
+        };
+        self.stack.push(canonical_path);
+        let mut tokens: Vec<Token> = self.tokenize_file(path, source);
+        self.stack.pop();
+        tokens.retain(|token| !token.is_end_of_file());
+        tokens
+    }
+
+    fn tokenize_file(&mut self, path: PathBuf, source: String) -> Vec<Token> {
+        let file: usize = self.files.len();
+        let mut lexer: Lexer = Lexer::new(source.clone());
+        self.files.push(SourceFile { path, source });
+        let (tokens, errors): (Vec<Token>, Vec<Diagnostic>) = lexer.tokenize_recovering();
+        self.errors.extend(errors.into_iter().map(|mut diagnostic| {
+            diagnostic.span.file = file;
+            diagnostic
+        }));
+        let mut result: Vec<Token> = Vec::new();
+        for mut token in tokens {
+            token.file = file;
+            if token.token_type == TokenType::Include {
+                result.append(&mut self.include(&token));
+            } else {
+                result.push(token);
+            }
+        }
+        result
+    }
+
+    /// Tokenizes the source code of the file at `path` and the files it includes.
+    pub fn tokenize(&mut self, path: PathBuf, source: String) -> Result<Vec<Token>, Vec<Diagnostic>> {
+        let (tokens, errors): (Vec<Token>, Vec<Diagnostic>) = self.tokenize_recovering(path, source);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(tokens)
+    }
+
+    /// Tokenizes the files like [`Includer::tokenize`], returning the tokens along the errors so the compiler can check them too.
+    pub fn tokenize_recovering(&mut self, path: PathBuf, source: String) -> (Vec<Token>, Vec<Diagnostic>) {
+        self.files.clear();
+        self.errors.clear();
+        self.stack = vec![path.canonicalize().unwrap_or(path.clone())];
+        self.loaded = self.stack.iter().cloned().collect();
+        let tokens: Vec<Token> = self.tokenize_file(path, source);
+        (tokens, std::mem::take(&mut self.errors))
+    }
+}
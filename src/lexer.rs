@@ -1,28 +1,38 @@
-use std::io::{Error, ErrorKind};
-
-use crate::token::{Token, TokenType};
+use crate::{diagnostic::{Diagnostic, DiagnosticCode, Span}, token::{Token, TokenType}};
 
 pub struct Lexer {
     source: Vec<char>,
     current_char: char,
     current_index: usize,
     current_line: usize,
-    current_column: usize
+    current_column: usize,
+    current_offset: usize,
+    /// Number of `$NAME(` waiting for their `)`.
+    arguments_depth: usize,
+    keep_trivia: bool
 }
 
 impl Lexer {
     pub fn new(source_code: String) -> Self {
         let source: Vec<char> = source_code.chars().collect();
-        let first_char: char = source[0].clone();
+        let first_char: char = source.first().copied().unwrap_or('\0');
         Self {
             source,
             current_char: first_char,
             current_index: 0,
             current_line: 1,
-            current_column: 0
+            current_column: 0,
+            current_offset: 0,
+            arguments_depth: 0,
+            keep_trivia: false
         }
     }
 
+    /// Keeps comments and whitespace as tokens, for tools like the formatter.
+    pub fn set_keep_trivia(&mut self, keep_trivia: bool) {
+        self.keep_trivia = keep_trivia;
+    }
+
     fn is_end_of_file(&self) -> bool {
         self.current_char == '\0' || self.current_index >= self.source.len()
     }
@@ -36,6 +46,7 @@ impl Lexer {
                 } else {
                     self.current_column += 1;
                 }
+                self.current_offset += self.current_char.len_utf8();
                 self.current_char = *next_char;
                 self.current_index += 1;
                 return
@@ -44,20 +55,33 @@ impl Lexer {
         self.current_char = '\0';
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Skips whitespace, returning it as a token when trivia are kept.
+    fn skip_whitespace(&mut self) -> Option<Token> {
+        let line: usize = self.current_line;
+        let column: usize = self.current_column;
+        let offset: usize = self.current_offset;
+        let mut value: String = String::new();
         while (self.current_char.is_whitespace() || self.current_char.is_ascii_whitespace()) && !self.is_end_of_file() {
+            value.push(self.current_char);
             self.advance();
         }
+        (self.keep_trivia && !value.is_empty()).then(|| Token::new(value, TokenType::Whitespace, line, column, offset))
     }
 
-    fn skip_line(&mut self) {
+    /// Skips a comment, returning it as a token when trivia are kept.
+    fn skip_line(&mut self) -> Option<Token> {
+        let column: usize = self.current_column;
+        let offset: usize = self.current_offset;
+        let mut value: String = String::new();
         while self.current_char != '\n' && !self.is_end_of_file() {
+            value.push(self.current_char);
             self.advance();
         }
+        self.keep_trivia.then(|| Token::new(value, TokenType::Comment, self.current_line, column, offset))
     }
 
     fn token_char_advance(&mut self, token_type: TokenType) -> Token {
-        let token: Token = Token::new(self.current_char.to_string(), token_type, self.current_line, self.current_column);
+        let token: Token = Token::new(self.current_char.to_string(), token_type, self.current_line, self.current_column, self.current_offset);
         self.advance();
         token
     }
@@ -65,50 +89,236 @@ impl Lexer {
     fn scan_number(&mut self) -> Token {
         let mut value: String = self.current_char.to_string();
         let column: usize = self.current_column;
+        let offset: usize = self.current_offset;
         self.advance();
         while self.current_char.is_numeric() {
             value.push(self.current_char);
             self.advance();
         }
-        Token::new(value, TokenType::Number, self.current_line, column)
+        Token::new(value, TokenType::Number, self.current_line, column, offset)
+    }
+
+    fn is_name_char(ch: char) -> bool {
+        ch.is_alphanumeric() || ch == '_' || ch == '-'
+    }
+
+    /// Whether a `name:` label starts at the current character.
+    fn is_label_start(&self) -> bool {
+        let name_length: usize = self.source[self.current_index..].iter()
+            .take_while(|&&ch| Self::is_name_char(ch))
+            .count();
+        name_length > 0 && self.source.get(self.current_index + name_length) == Some(&':')
+    }
+
+    /// Scans the name and its closing delimiter, which must be `closing`.
+    fn scan_name(&mut self, token_type: TokenType, closing: char) -> Result<Token, Diagnostic> {
+        let line: usize = self.current_line;
+        let column: usize = self.current_column;
+        let offset: usize = self.current_offset;
+        let mut value: String = String::new();
+        if token_type == TokenType::Name {
+            value.push(self.current_char);
+            self.advance();
+        }
+        while Self::is_name_char(self.current_char) && !self.is_end_of_file() {
+            value.push(self.current_char);
+            self.advance();
+        }
+        if self.current_char != closing || (token_type == TokenType::Name && value.len() <= 1) {
+            return Err(Diagnostic::error(
+                DiagnosticCode::UnexpectedCharacter,
+                format!("Unclosed name \"{}\"", value),
+                Span::new(line, column, offset, value.chars().count().max(1))
+            ).with_help(format!("Names are made of letters, digits, '_' and '-' and end with '{}'.", closing)));
+        }
+        value.push(closing);
+        self.advance();
+        Ok(Token::new(value, token_type, line, column, offset))
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, Error> {
+    /// Scans an `#include "path"` or `#time 3/4` directive, which must fit on one line.
+    fn scan_directive(&mut self) -> Result<Token, Diagnostic> {
+        let line: usize = self.current_line;
+        let column: usize = self.current_column;
+        let offset: usize = self.current_offset;
+        let mut value: String = String::new();
+        while !self.current_char.is_whitespace() && self.current_char != '"' && !self.is_end_of_file() {
+            value.push(self.current_char);
+            self.advance();
+        }
+        while self.current_char == ' ' || self.current_char == '\t' {
+            value.push(self.current_char);
+            self.advance();
+        }
+        let token_type: Option<TokenType> = match (value.trim_end(), self.current_char) {
+            ("#include", '"') => {
+                value.push(self.current_char);
+                self.advance();
+                while self.current_char != '"' && self.current_char != '\n' && !self.is_end_of_file() {
+                    value.push(self.current_char);
+                    self.advance();
+                }
+                (self.current_char == '"').then_some(TokenType::Include)
+            },
+            ("#time", ch) if ch.is_ascii_digit() => {
+                while self.current_char.is_ascii_digit() || self.current_char == '/' {
+                    value.push(self.current_char);
+                    self.advance();
+                }
+                let signature: &str = value.trim_start_matches("#time").trim();
+                let is_valid: bool = signature.split_once('/').is_some_and(|(beats, unit)| {
+                    !beats.is_empty() && !unit.is_empty() && !unit.contains('/')
+                });
+                is_valid.then_some(TokenType::TimeSignature)
+            },
+            _ => None
+        };
+        match token_type {
+            Some(TokenType::Include) => {
+                value.push('"');
+                self.advance();
+                Ok(Token::new(value, TokenType::Include, line, column, offset))
+            },
+            Some(token_type) => Ok(Token::new(value, token_type, line, column, offset)),
+            None => Err(Diagnostic::error(
+                DiagnosticCode::UnexpectedCharacter,
+                format!("Invalid directive \"{}\"", value.trim_end()),
+                Span::new(line, column, offset, value.trim_end().chars().count().max(1))
+            ).with_help("Expected #include \"file.mmml\" or #time 3/4.".to_string()))
+        }
+    }
+
+    fn scan_variable(&mut self) -> Result<Token, Diagnostic> {
+        let column: usize = self.current_column;
+        let offset: usize = self.current_offset;
+        let mut value: String = self.current_char.to_string();
+        self.advance();
+        while Self::is_name_char(self.current_char) && !self.is_end_of_file() {
+            value.push(self.current_char);
+            self.advance();
+        }
+        if value.len() == 1 {
+            return Err(Diagnostic::error(
+                DiagnosticCode::UnexpectedCharacter,
+                "Expected a name after \"$\"".to_string(),
+                Span::new(self.current_line, column, offset, 1)
+            ).with_help("Names are made of letters, digits, '_' and '-'.".to_string()));
+        }
+        Ok(Token::new(value, TokenType::Variable, self.current_line, column, offset))
+    }
+
+    /// Scans a `{` followed by a signed number of semitones.
+    fn scan_transpose_start(&mut self) -> Result<Token, Diagnostic> {
+        let column: usize = self.current_column;
+        let offset: usize = self.current_offset;
+        let mut value: String = self.current_char.to_string();
+        self.advance();
+        if self.current_char == '+' || self.current_char == '-' {
+            value.push(self.current_char);
+            self.advance();
+        }
+        while self.current_char.is_ascii_digit() {
+            value.push(self.current_char);
+            self.advance();
+        }
+        if !value.ends_with(|ch: char| ch.is_ascii_digit()) {
+            return Err(Diagnostic::error(
+                DiagnosticCode::UnexpectedCharacter,
+                format!("Expected a number of semitones after \"{}\"", value),
+                Span::new(self.current_line, column, offset, value.chars().count())
+            ).with_help("Transpositions start like {+3 or {-12.".to_string()));
+        }
+        Ok(Token::new(value, TokenType::TransposeStart, self.current_line, column, offset))
+    }
+
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, Vec<Diagnostic>> {
+        let (tokens, errors): (Vec<Token>, Vec<Diagnostic>) = self.tokenize_recovering();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(tokens)
+    }
+
+    /// Tokenizes the whole source code, skipping invalid characters to report as many errors as possible.
+    /// The tokens are returned along the errors so the parser can check them too.
+    pub fn tokenize_recovering(&mut self) -> (Vec<Token>, Vec<Diagnostic>) {
         let mut result: Vec<Token> = Vec::new();
+        let mut errors: Vec<Diagnostic> = Vec::new();
         while !self.is_end_of_file() {
-            self.skip_whitespace();
+            result.extend(self.skip_whitespace());
             match self.current_char {
                 '<' => result.push(self.token_char_advance(TokenType::LessThan)),
                 '>' => result.push(self.token_char_advance(TokenType::GreaterThan)),
                 '[' => result.push(self.token_char_advance(TokenType::LeftParen)),
                 ']' => result.push(self.token_char_advance(TokenType::RightParen)),
                 '.' => result.push(self.token_char_advance(TokenType::Dot)),
-                '@' => result.push(self.token_char_advance(TokenType::Arobase)),
+                '@' => {
+                    result.push(self.token_char_advance(TokenType::Arobase));
+                    if self.is_label_start() {
+                        match self.scan_name(TokenType::Label, ':') {
+                            Ok(token) => result.push(token),
+                            Err(diagnostic) => errors.push(diagnostic)
+                        }
+                    }
+                },
+                '#' => match self.scan_directive() {
+                    Ok(token) => result.push(token),
+                    Err(diagnostic) => errors.push(diagnostic)
+                },
+                '(' => match self.scan_name(TokenType::Name, ')') {
+                    Ok(token) => result.push(token),
+                    Err(diagnostic) => errors.push(diagnostic)
+                },
+                '$' => match self.scan_variable() {
+                    Ok(token) => {
+                        result.push(token);
+                        if self.current_char == '(' {
+                            result.push(self.token_char_advance(TokenType::ArgumentsStart));
+                            self.arguments_depth += 1;
+                        }
+                    },
+                    Err(diagnostic) => errors.push(diagnostic)
+                },
+                ')' if self.arguments_depth > 0 => {
+                    result.push(self.token_char_advance(TokenType::ArgumentsEnd));
+                    self.arguments_depth -= 1;
+                },
+                '{' => match self.scan_transpose_start() {
+                    Ok(token) => result.push(token),
+                    Err(diagnostic) => errors.push(diagnostic)
+                },
+                '}' => result.push(self.token_char_advance(TokenType::TransposeEnd)),
+                '|' => result.push(self.token_char_advance(TokenType::BarLine)),
+                '=' => result.push(self.token_char_advance(TokenType::Equals)),
+                ',' => result.push(self.token_char_advance(TokenType::Comma)),
                 '&' => result.push(self.token_char_advance(TokenType::Command)),
-                '%' => self.skip_line(),
+                '%' => result.extend(self.skip_line()),
                 ch => {
                     if ch.is_alphabetic() {
                         let mut value: String = ch.to_string();
                         let column: usize = self.current_column;
+                        let offset: usize = self.current_offset;
                         self.advance();
                         if self.current_char == '+' || self.current_char == '#' {
                             value.push('#');
                             self.advance();
                         }
-                        let token: Token = Token::new(value, TokenType::Command, self.current_line, column);
+                        let token: Token = Token::new(value, TokenType::Command, self.current_line, column, offset);
                         result.push(token);
                     } else if ch.is_numeric() {
                         result.push(self.scan_number());
                     } else if !self.is_end_of_file() {
-                        return Err(Error::new(
-                            ErrorKind::InvalidData,
-                            format!("Unexpected character \"{}\" at line {}, column {}.", ch, self.current_line, self.current_column)
-                        ))
+                        errors.push(Diagnostic::error(
+                            DiagnosticCode::UnexpectedCharacter,
+                            format!("Unexpected character \"{}\"", ch),
+                            Span::new(self.current_line, self.current_column, self.current_offset, 1)
+                        ));
+                        self.advance();
                     }
                 }
             }
         }
-        result.push(Token::empty(self.current_line, self.current_column));
-        Ok(result)
+        result.push(Token::empty(self.current_line, self.current_column, self.current_offset));
+        (result, errors)
     }
 }
@@ -1,4 +1,22 @@
 pub mod args;
 pub mod token;
 pub mod lexer;
+pub mod include;
+pub mod preprocessor;
+pub mod ast;
+pub mod parser;
 pub mod compiler;
+pub mod builder;
+pub mod diagnostic;
+pub mod decompiler;
+pub mod player;
+pub mod driver;
+pub mod target;
+pub mod midi;
+pub mod optimizer;
+pub mod timing;
+pub mod lsp;
+pub mod formatter;
+pub mod linter;
+pub mod export;
+pub mod bank;
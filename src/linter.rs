@@ -0,0 +1,230 @@
+use crate::{
+    ast::{Item, Song},
+    compiler::CompiledItem,
+    diagnostic::{Diagnostic, DiagnosticCode, Span}
+};
+
+/// Value of an octave or volume command, and where it was set.
+type Setting = Option<(u8, Span)>;
+
+/// Octave and volume known at a point of a section, `None` when they depend on what was played before.
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    octave: Setting,
+    volume: Setting
+}
+
+fn sets_octave(items: &[Item]) -> bool {
+    items.iter().any(|item| match item {
+        Item::Octave { .. } | Item::OctaveUp { .. } | Item::OctaveDown { .. } => true,
+        Item::Loop { items, .. } | Item::Transposition { items, .. } => sets_octave(items),
+        _ => false
+    })
+}
+
+fn sets_volume(items: &[Item]) -> bool {
+    items.iter().any(|item| match item {
+        Item::Volume { .. } => true,
+        Item::Loop { items, .. } | Item::Transposition { items, .. } => sets_volume(items),
+        _ => false
+    })
+}
+
+fn is_octave_shift(item: &Item) -> bool {
+    matches!(item, Item::OctaveUp { .. } | Item::OctaveDown { .. })
+}
+
+struct Linter<'a> {
+    song: &'a Song,
+    compiled_items: &'a [CompiledItem],
+    /// Volume commands whose silent notes were reported.
+    reported_volumes: Vec<Span>,
+    warnings: Vec<Diagnostic>
+}
+
+impl Linter<'_> {
+    /// Octave after an item, as computed by the compiler.
+    fn compiled_octave(&self, item: &Item) -> Option<u8> {
+        self.compiled_items.iter().find(|compiled_item| compiled_item.span == item.span()).map(|compiled_item| compiled_item.octave)
+    }
+
+    fn called_macro(&self, item: &Item) -> Option<usize> {
+        match item {
+            Item::MacroCall { number, .. } => (number.value as usize).checked_sub(1).filter(|&index| index < self.song.macros.len()),
+            Item::NamedMacroCall { name, .. } => self.song.macros.iter()
+                .position(|section| section.name.as_ref().is_some_and(|section_name| section_name.value == name.value)),
+            _ => None
+        }
+    }
+
+    /// Marks the macros called by the items.
+    fn mark_calls(&self, items: &[Item], called: &mut [bool]) {
+        for item in items {
+            match item {
+                Item::Loop { items, .. } | Item::Transposition { items, .. } => self.mark_calls(items, called),
+                _ => if let Some(index) = self.called_macro(item) {
+                    called[index] = true;
+                }
+            }
+        }
+    }
+
+    /// Reports the numbered macros never called, the compiler reporting the named ones.
+    fn check_unused_macros(&mut self) {
+        let mut called: Vec<bool> = vec![false; self.song.macros.len()];
+        for section in self.song.sections() {
+            self.mark_calls(&section.items, &mut called);
+        }
+        for (index, section) in self.song.macros.iter().enumerate() {
+            if section.name.is_none() && section.span.file == 0 && !called[index] {
+                self.warnings.push(Diagnostic::warning(
+                    DiagnosticCode::UnusedMacro,
+                    format!("Macro {} is never called", index + 1),
+                    section.span
+                ).with_help(format!("Call it with 'm{}' or remove it.", index + 1)));
+            }
+        }
+    }
+
+    /// Returns the setting after the command, which stays the previous one when the command is redundant.
+    fn check_redundant(&mut self, setting: Setting, value: u8, command: &str, span: Span) -> Setting {
+        if let Some((current_value, setting_span)) = setting.filter(|(current_value, _)| *current_value == value) {
+            self.warnings.push(Diagnostic::warning(
+                DiagnosticCode::RedundantCommand,
+                format!("{}{} doesn't change the {}", command, value, if command == "o" { "octave" } else { "volume" }),
+                span
+            ).with_label(setting_span, format!("already set to {} here", current_value)));
+            return setting;
+        }
+        Some((value, span))
+    }
+
+    fn check_octave_shifts(&mut self, shifts: &[Item]) {
+        let (Some(first_shift), Some(last_shift)) = (shifts.first(), shifts.last()) else {
+            return;
+        };
+        let Some(octave) = self.compiled_octave(last_shift) else {
+            return;
+        };
+        let (first_span, last_span): (Span, Span) = (first_shift.span(), last_shift.span());
+        let span: Span = match first_span.line == last_span.line {
+            true => Span { length: last_span.column + last_span.length - first_span.column, ..first_span },
+            false => first_span
+        };
+        self.warnings.push(Diagnostic::warning(
+            DiagnosticCode::OctaveShiftRun,
+            format!("{} octave shifts in a row could be a single octave command", shifts.len()),
+            span
+        ).with_help(format!("Write o{} instead.", octave)));
+    }
+
+    fn check_loop(&mut self, times: u8, span: Span) {
+        match times {
+            0 => self.warnings.push(Diagnostic::warning(
+                DiagnosticCode::ZeroLoop,
+                "Loop of 0 repetitions is played 256 times".to_string(),
+                span
+            ).with_help("The driver counts the repetitions down from 0, write [255 or less.".to_string())),
+            1 => self.warnings.push(Diagnostic::warning(
+                DiagnosticCode::SingleLoop,
+                "Loop is played only once".to_string(),
+                span
+            ).with_help("Remove the loop and keep its content.".to_string())),
+            _ => {}
+        }
+    }
+
+    fn check_note(&mut self, state: &State, span: Span) {
+        let Some((0, volume_span)) = state.volume else {
+            return;
+        };
+        if self.reported_volumes.contains(&volume_span) {
+            return;
+        }
+        self.reported_volumes.push(volume_span);
+        self.warnings.push(Diagnostic::warning(
+            DiagnosticCode::SilentNote,
+            "Note played at volume 0".to_string(),
+            span
+        ).with_label(volume_span, "volume set to 0 here".to_string()).with_help("Volume 0 is silent, write a rest instead.".to_string()));
+    }
+
+    /// Lints a list of items, returning whether it ends with an `S` reached every time.
+    fn lint_items(&mut self, items: &[Item], state: &mut State) -> bool {
+        let mut stop_span: Option<Span> = None;
+        for (index, item) in items.iter().enumerate() {
+            if let Some(stop_span) = stop_span {
+                if matches!(item, Item::BarLine { .. } | Item::TimeSignature { .. }) {
+                    continue;
+                }
+                self.warnings.push(Diagnostic::warning(
+                    DiagnosticCode::UnreachableCommand,
+                    "Commands after S are never played".to_string(),
+                    item.span()
+                ).with_label(stop_span, "the channel stops here".to_string()));
+                return true;
+            }
+            if is_octave_shift(item) && !index.checked_sub(1).is_some_and(|previous| is_octave_shift(&items[previous])) {
+                let run: usize = items[index..].iter().take_while(|item| is_octave_shift(item)).count();
+                if run > 1 {
+                    self.check_octave_shifts(&items[index..index + run]);
+                }
+            }
+            match item {
+                Item::Note { span, .. } => self.check_note(state, *span),
+                Item::Octave { octave, span } => {
+                    state.octave = self.check_redundant(state.octave, octave.value, "o", *span);
+                },
+                Item::OctaveUp { span } | Item::OctaveDown { span } => {
+                    state.octave = self.compiled_octave(item).map(|octave| (octave, *span));
+                },
+                Item::Volume { volume, span } => {
+                    state.volume = self.check_redundant(state.volume, volume.value, "v", *span);
+                },
+                Item::Loop { times, items, span } => {
+                    self.check_loop(*times, *span);
+                    // From the second repetition, the loop starts with the state it ended with.
+                    if *times != 1 && sets_octave(items) {
+                        state.octave = None;
+                    }
+                    if *times != 1 && sets_volume(items) {
+                        state.volume = None;
+                    }
+                    if self.lint_items(items, state) {
+                        stop_span = Some(*span);
+                    }
+                },
+                Item::Transposition { items, span, .. } if self.lint_items(items, state) => stop_span = Some(*span),
+                Item::MacroCall { .. } | Item::NamedMacroCall { .. } => {
+                    let macro_items: Option<&[Item]> = self.called_macro(item).map(|index| self.song.macros[index].items.as_slice());
+                    if macro_items.is_none_or(sets_octave) {
+                        state.octave = None;
+                    }
+                    if macro_items.is_none_or(sets_volume) {
+                        state.volume = None;
+                    }
+                },
+                Item::Stop { span } => stop_span = Some(*span),
+                _ => {}
+            }
+        }
+        stop_span.is_some()
+    }
+}
+
+/// Finds commands that compile but are useless or likely mistakes.
+/// The compiled items give the octaves computed by the compiler, sections of included files are not linted.
+pub fn lint(song: &Song, compiled_items: &[CompiledItem]) -> Vec<Diagnostic> {
+    let mut linter: Linter = Linter {
+        song,
+        compiled_items,
+        reported_volumes: Vec::new(),
+        warnings: Vec::new()
+    };
+    for section in song.sections().filter(|section| section.span.file == 0) {
+        linter.lint_items(&section.items, &mut State::default());
+    }
+    linter.check_unused_macros();
+    linter.warnings.sort_by_key(|warning| warning.span.offset);
+    linter.warnings
+}
@@ -0,0 +1,436 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Error, ErrorKind, Write},
+    path::{Path, PathBuf}
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    ast::{Duration, Item, Section, Song},
+    compiler::{CompiledItem, Compiler},
+    diagnostic::{Diagnostic, Severity, Span},
+    include::Includer,
+    timing::channel_name,
+    token::Token
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// LSP symbol kinds of channels and macros.
+const CHANNEL_SYMBOL_KIND: u8 = 2;
+const MACRO_SYMBOL_KIND: u8 = 12;
+
+/// Reads a `Content-Length` framed JSON-RPC message, `None` at the end of the input.
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>, Error> {
+    let mut content_length: Option<usize> = None;
+    loop {
+        let mut line: String = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line: &str = line.trim_end();
+        if line.is_empty() && content_length.is_some() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse().ok();
+            }
+        }
+    }
+    let mut content: Vec<u8> = vec![0; content_length.unwrap_or(0)];
+    reader.read_exact(&mut content)?;
+    serde_json::from_slice(&content).map(Some).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+/// Writes a JSON-RPC message with its `Content-Length` header.
+pub fn write_message(writer: &mut impl Write, message: &Value) -> Result<(), Error> {
+    let content: String = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+    writer.flush()
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path: &str = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes: &[u8] = path.as_bytes();
+    let mut decoded: Vec<u8> = Vec::new();
+    let mut index: usize = 0;
+    while index < bytes.len() {
+        let escaped: Option<u8> = path.get(index + 1..index + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).to_string())
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let path: PathBuf = path.canonicalize().unwrap_or(path.to_path_buf());
+    format!("file://{}", path.display().to_string().replace('%', "%25").replace(' ', "%20"))
+}
+
+fn position(line: usize, character: usize) -> Value {
+    json!({ "line": line.saturating_sub(1), "character": character })
+}
+
+fn range(span: Span) -> Value {
+    json!({ "start": position(span.line, span.column), "end": position(span.line, span.column + span.length) })
+}
+
+/// Whether the span is in the document, at the 0 based LSP `line` and `character`.
+fn contains(span: Span, line: usize, character: usize) -> bool {
+    span.file == 0 && span.line == line + 1 && span.column <= character && character < span.column + span.length
+}
+
+/// Innermost item at a position.
+fn find_item(items: &[Item], line: usize, character: usize) -> Option<&Item> {
+    items.iter().find_map(|item| {
+        let nested_item: Option<&Item> = match item {
+            Item::Loop { items, .. } | Item::Transposition { items, .. } => find_item(items, line, character),
+            _ => None
+        };
+        nested_item.or_else(|| contains(item.span(), line, character).then_some(item))
+    })
+}
+
+/// Index in `song.macros` of the macro called by an item.
+fn called_macro(song: &Song, item: &Item) -> Option<usize> {
+    match item {
+        Item::MacroCall { number, .. } => (number.value as usize).checked_sub(1).filter(|&index| index < song.macros.len()),
+        Item::NamedMacroCall { name, .. } => song.macros.iter()
+            .position(|section| section.name.as_ref().is_some_and(|section_name| section_name.value == name.value)),
+        _ => None
+    }
+}
+
+fn for_each_item<'a>(items: &'a [Item], visit: &mut impl FnMut(&'a Item)) {
+    for item in items {
+        visit(item);
+        if let Item::Loop { items, .. } | Item::Transposition { items, .. } = item {
+            for_each_item(items, visit);
+        }
+    }
+}
+
+fn section_title(song: &Song, index: usize) -> String {
+    let section: Option<&Section> = song.sections().nth(index);
+    let number: String = match index.checked_sub(song.channels.len()) {
+        None => format!("Channel {}", channel_name(index)),
+        Some(macro_index) => format!("Macro {}", macro_index + 1)
+    };
+    match section.and_then(|section| section.name.as_ref()) {
+        Some(name) => format!("{} `{}`", number, name.value),
+        None => number
+    }
+}
+
+fn describe_duration(nibble: u8) -> String {
+    let duration: String = Duration::decode(nibble).map(|duration| duration.to_string()).unwrap_or_default();
+    format!("duration {} ({} ticks)", duration, Duration::nibble_ticks(nibble))
+}
+
+/// An open document and what the compiler found in it.
+struct Document {
+    source: String,
+    /// Paths of the document and of the files it includes, indexed by `Span::file`.
+    files: Vec<PathBuf>,
+    song: Song,
+    compiled_items: Vec<CompiledItem>,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl Document {
+    fn new(path: PathBuf, source: String, include_paths: &[PathBuf]) -> Self {
+        let mut includer: Includer = Includer::new();
+        for include_path in include_paths {
+            includer.add_include_path(include_path.clone());
+        }
+        let (tokens, lexer_errors): (Vec<Token>, Vec<Diagnostic>) = includer.tokenize_recovering(path, source.clone());
+        let mut compiler: Compiler = Compiler::new(tokens);
+        compiler.set_lexer_errors(lexer_errors);
+        let (song, _): (Song, Vec<Diagnostic>) = compiler.parse();
+        let mut diagnostics: Vec<Diagnostic> = compiler.compile().err().unwrap_or_default();
+        diagnostics.extend_from_slice(compiler.warnings());
+        Self {
+            source,
+            files: includer.files().iter().map(|file| file.path.clone()).collect(),
+            song,
+            compiled_items: compiler.compiled_items().to_vec(),
+            diagnostics
+        }
+    }
+
+    fn location(&self, uri: &str, span: Span) -> Value {
+        let uri: String = match self.files.get(span.file) {
+            Some(path) if span.file > 0 => path_to_uri(path),
+            _ => uri.to_string()
+        };
+        json!({ "uri": uri, "range": range(span) })
+    }
+
+    /// Diagnostics of the document, those of included files being published when they are opened.
+    fn lsp_diagnostics(&self, uri: &str) -> Vec<Value> {
+        self.diagnostics.iter()
+            .filter(|diagnostic| diagnostic.span.file == 0)
+            .map(|diagnostic| {
+                let message: String = match &diagnostic.help {
+                    Some(help) => format!("{}\n{}", diagnostic.message, help),
+                    None => diagnostic.message.clone()
+                };
+                let related_information: Vec<Value> = diagnostic.labels.iter()
+                    .map(|label| json!({ "location": self.location(uri, label.span), "message": label.message }))
+                    .collect();
+                json!({
+                    "range": range(diagnostic.span),
+                    "severity": if diagnostic.severity == Severity::Error { 1 } else { 2 },
+                    "code": diagnostic.code.as_str(),
+                    "source": "mmml",
+                    "message": message,
+                    "relatedInformation": related_information
+                })
+            })
+            .collect()
+    }
+
+    fn hover(&self, line: usize, character: usize) -> Option<Value> {
+        let item: &Item = self.song.sections().find_map(|section| find_item(&section.items, line, character))?;
+        let compiled_item: Option<&CompiledItem> = self.compiled_items.iter().find(|compiled_item| compiled_item.span == item.span());
+        let mut lines: Vec<String> = vec![format!("`{}`", item)];
+        if let Some(compiled_item) = compiled_item {
+            match item {
+                Item::Note { .. } => lines.push(format!("Octave {}, {}", compiled_item.octave, describe_duration(compiled_item.duration))),
+                Item::Rest { .. } => lines.push(format!("Rest, {}", describe_duration(compiled_item.duration))),
+                Item::Octave { .. } | Item::OctaveUp { .. } | Item::OctaveDown { .. } => lines.push(format!("Octave {}", compiled_item.octave)),
+                _ => {}
+            }
+        }
+        if let Some(macro_index) = called_macro(&self.song, item) {
+            lines.push(format!("Calls {}", section_title(&self.song, self.song.channels.len() + macro_index)));
+        }
+        if let Some(compiled_item) = compiled_item {
+            let bytes: Vec<String> = compiled_item.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            lines.push(match bytes.is_empty() {
+                true => "Compiles to no bytes".to_string(),
+                false => format!("Compiles to `{}`", bytes.join(" "))
+            });
+        }
+        Some(json!({ "contents": { "kind": "markdown", "value": lines.join("\n\n") }, "range": range(item.span()) }))
+    }
+
+    /// Macro called at a position, or whose `@` or name is at this position.
+    fn macro_at(&self, line: usize, character: usize) -> Option<usize> {
+        let declared_macro: Option<usize> = self.song.macros.iter().position(|section| {
+            contains(section.span, line, character) || section.name.as_ref().is_some_and(|name| contains(name.span, line, character))
+        });
+        declared_macro.or_else(|| {
+            let item: &Item = self.song.sections().find_map(|section| find_item(&section.items, line, character))?;
+            called_macro(&self.song, item)
+        })
+    }
+
+    fn definition(&self, uri: &str, line: usize, character: usize) -> Option<Value> {
+        let macro_index: usize = self.macro_at(line, character)?;
+        Some(self.location(uri, self.song.macros[macro_index].span))
+    }
+
+    fn references(&self, uri: &str, line: usize, character: usize, include_declaration: bool) -> Vec<Value> {
+        let Some(macro_index) = self.macro_at(line, character) else {
+            return Vec::new();
+        };
+        let mut references: Vec<Value> = Vec::new();
+        if include_declaration {
+            references.push(self.location(uri, self.song.macros[macro_index].span));
+        }
+        for section in self.song.sections() {
+            for_each_item(&section.items, &mut |item| {
+                if called_macro(&self.song, item) == Some(macro_index) {
+                    references.push(self.location(uri, item.span()));
+                }
+            });
+        }
+        references
+    }
+
+    /// Channels and macros of the document, each one lasting until the next one.
+    fn symbols(&self) -> Vec<Value> {
+        let lines: Vec<&str> = self.source.split('\n').collect();
+        let document_end: Value = position(lines.len(), lines.last().map_or(0, |line| line.chars().count()));
+        let sections: Vec<(usize, &Section)> = self.song.sections()
+            .enumerate()
+            .filter(|(_, section)| section.span.file == 0)
+            .collect();
+        sections.iter()
+            .enumerate()
+            .map(|(position_index, &(index, section))| {
+                let end: Value = sections.get(position_index + 1)
+                    .map_or(document_end.clone(), |(_, next_section)| position(next_section.span.line, next_section.span.column));
+                let is_channel: bool = index < self.song.channels.len();
+                let selection_span: Span = section.name.as_ref().map_or(section.span, |name| name.span);
+                json!({
+                    "name": section_title(&self.song, index).replace('`', ""),
+                    "detail": if is_channel { "channel" } else { "macro" },
+                    "kind": if is_channel { CHANNEL_SYMBOL_KIND } else { MACRO_SYMBOL_KIND },
+                    "range": { "start": position(section.span.line, section.span.column), "end": end },
+                    "selectionRange": range(selection_span)
+                })
+            })
+            .collect()
+    }
+}
+
+/// Language server answering the messages of an editor.
+/// Documents are compiled for the default target each time they change.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    include_paths: Vec<PathBuf>,
+    is_shut_down: bool,
+    is_exited: bool
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the client asked to shut down.
+    pub fn is_shut_down(&self) -> bool {
+        self.is_shut_down
+    }
+
+    /// Whether the client sent the exit notification.
+    pub fn is_exited(&self) -> bool {
+        self.is_exited
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let diagnostics: Vec<Value> = self.documents.get(uri).map(|document| document.lsp_diagnostics(uri)).unwrap_or_default();
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics }
+        })
+    }
+
+    fn open(&mut self, uri: &str, source: String) -> Value {
+        let document: Document = Document::new(uri_to_path(uri), source, &self.include_paths);
+        self.documents.insert(uri.to_string(), document);
+        self.publish_diagnostics(uri)
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri: &str = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let source: String = params["textDocument"]["text"].as_str().unwrap_or_default().to_string();
+                vec![self.open(uri, source)]
+            },
+            "textDocument/didChange" => {
+                // Only full document changes are asked for.
+                let Some(source) = params["contentChanges"].as_array().and_then(|changes| changes.last()?["text"].as_str()) else {
+                    return Vec::new();
+                };
+                vec![self.open(uri, source.to_string())]
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![self.publish_diagnostics(uri)]
+            },
+            "exit" => {
+                self.is_exited = true;
+                Vec::new()
+            },
+            _ => Vec::new()
+        }
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if self.is_shut_down {
+            return Err((INVALID_REQUEST, "The server is shut down".to_string()));
+        }
+        let uri: &str = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let line: usize = params["position"]["line"].as_u64().unwrap_or_default() as usize;
+        let character: usize = params["position"]["character"].as_u64().unwrap_or_default() as usize;
+        let document: Option<&Document> = self.documents.get(uri);
+        match method {
+            "initialize" => {
+                if let Some(include_paths) = params["initializationOptions"]["includePaths"].as_array() {
+                    self.include_paths = include_paths.iter().filter_map(Value::as_str).map(PathBuf::from).collect();
+                }
+                Ok(json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "documentSymbolProvider": true
+                    },
+                    "serverInfo": { "name": "mmml-lsp", "version": env!("CARGO_PKG_VERSION") }
+                }))
+            },
+            "shutdown" => {
+                self.is_shut_down = true;
+                Ok(Value::Null)
+            },
+            "textDocument/hover" => Ok(document.and_then(|document| document.hover(line, character)).unwrap_or_default()),
+            "textDocument/definition" => Ok(document.and_then(|document| document.definition(uri, line, character)).unwrap_or_default()),
+            "textDocument/references" => {
+                let include_declaration: bool = params["context"]["includeDeclaration"].as_bool().unwrap_or(false);
+                Ok(document.map(|document| document.references(uri, line, character, include_declaration)).unwrap_or_default().into())
+            },
+            "textDocument/documentSymbol" => Ok(document.map(Document::symbols).unwrap_or_default().into()),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method)))
+        }
+    }
+
+    /// Handles a request or a notification, returning the response and the notifications to send.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let Some(method) = message["method"].as_str() else {
+            // Responses are ignored, the server doesn't send requests.
+            return Vec::new();
+        };
+        let params: &Value = &message["params"];
+        match message.get("id") {
+            Some(id) => vec![match self.handle_request(method, params) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, error_message)) => error_response(id.clone(), code, error_message)
+            }],
+            None => self.handle_notification(method, params)
+        }
+    }
+}
+
+/// Serves the messages of `reader` until the exit notification.
+/// Returns whether the client asked to shut down before, the exit code depending on it.
+pub fn run(mut reader: impl BufRead, mut writer: impl Write) -> Result<bool, Error> {
+    let mut server: Server = Server::new();
+    while !server.is_exited() {
+        let message: Value = match read_message(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                write_message(&mut writer, &error_response(Value::Null, PARSE_ERROR, err.to_string()))?;
+                continue;
+            },
+            Err(err) => return Err(err)
+        };
+        for response in server.handle(&message) {
+            write_message(&mut writer, &response)?;
+        }
+    }
+    Ok(server.is_shut_down())
+}
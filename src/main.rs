@@ -1,73 +1,292 @@
-use mmml_compiler::{args::{CompilerArgs, ExportType}, compiler::Compiler, lexer::Lexer, token::Token};
-use std::{fs::File, io::{Error, Write}, path::PathBuf, process::exit};
+use mmml_compiler::{
+    args::{BankArgs, CheckArgs, Cli, Command, CompilerArgs, DisassembleArgs, ExportType, FmtArgs, ImportArgs, ImportFormat, LintArgs, RenderArgs},
+    ast::Song,
+    bank::{build_bank, identifier, song_name, Bank, Manifest},
+    compiler::Compiler,
+    decompiler::{parse_c_array, Decompiler},
+    diagnostic::{Diagnostic, Severity, WarningLevels},
+    formatter::Formatter,
+    include::Includer,
+    lexer::Lexer,
+    linter::lint,
+    export::Exporter,
+    midi::{import_midi, MidiImport},
+    optimizer::{compress, optimize, CompressionReport, OptimizationReport},
+    player::{write_wav, Player},
+    target::Target,
+    timing::{channel_name, channel_timings, check_timing, ChannelTiming},
+    token::Token
+};
+use std::{fs::File, io::{BufWriter, Error, ErrorKind, Write}, path::{Path, PathBuf}, process::exit};
 use clap::Parser;
 
 fn main() {
-    let args: CompilerArgs = CompilerArgs::parse();
-    if let Err(err) = compile(args) {
+    let cli: Cli = Cli::parse();
+    let result: Result<(), Error> = match (cli.command, cli.compile) {
+        (Some(Command::Disassemble(args)), _) => disassemble(args),
+        (Some(Command::Render(args)), _) => render(args).map(|_| println!("Rendered sucessfuly!")),
+        (Some(Command::Import(args)), _) => import(args).map(|_| println!("Imported sucessfuly!")),
+        (Some(Command::Check(args)), _) => check(args).map(|_| println!("Checked sucessfuly!")),
+        (Some(Command::Fmt(args)), _) => format(args),
+        (Some(Command::Lint(args)), _) => lint_song(args).map(|_| println!("Linted sucessfuly!")),
+        (Some(Command::Bank(args)), _) => bank(args).map(|_| println!("Banked sucessfuly!")),
+        (None, Some(args)) => compile(args).map(|_| println!("Compiled sucessfuly!")),
+        (None, None) => Ok(())
+    };
+    if let Err(err) = result {
         println!("Error: {}", err);
         exit(1);
-    } else {
-        println!("Compiled sucessfuly!");
     }
 }
 
-fn compile(args: CompilerArgs) -> Result<(), Error> {
-    let source_code: String = std::fs::read_to_string(args.input_path.clone())?;
+/// Loads compiled data from C code, raw binary or µMML source code depending on the file extension.
+fn load_data(path: &Path) -> Result<Vec<u8>, Error> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("c" | "h") => parse_c_array(&std::fs::read_to_string(path)?),
+        Some("mbf") => std::fs::read(path),
+        _ => {
+            let (includer, mut compiler): (Includer, Compiler) = load_song(path, &[], false)?;
+            run_compiler(&mut compiler, &WarningLevels::default(), &includer)
+        }
+    }
+}
 
-    if args.verbose {
+/// Reads a source file and the files it includes into a compiler, its lexer errors being reported with the others.
+fn load_song(path: &Path, include_paths: &[PathBuf], verbose: bool) -> Result<(Includer, Compiler), Error> {
+    let source_code: String = std::fs::read_to_string(path)?;
+    if verbose {
         println!("Source code:\n{}", source_code);
     }
+    let mut includer: Includer = Includer::new();
+    for include_path in include_paths {
+        includer.add_include_path(include_path.clone());
+    }
+    let (tokens, lexer_errors): (Vec<Token>, Vec<Diagnostic>) = includer.tokenize_recovering(path.to_path_buf(), source_code);
+    if verbose {
+        println!("Tokens:\n{:#?}", tokens);
+    }
+    let mut compiler: Compiler = Compiler::new(tokens);
+    compiler.set_lexer_errors(lexer_errors);
+    Ok((includer, compiler))
+}
 
-    let mut lexer: Lexer = Lexer::new(source_code);
-    let tokens: Vec<Token> = lexer.tokenize()?;
+/// Prints diagnostics with the source code of the files they point to.
+fn print_diagnostics(diagnostics: &[Diagnostic], includer: &Includer) {
+    let sources: Vec<(String, &str)> = includer.sources();
+    let files: Vec<(&str, &str)> = sources.iter().map(|(name, source)| (name.as_str(), *source)).collect();
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render_files(&files));
+    }
+}
 
-    if args.verbose {
-        println!("Tokens:\n{:#?}", tokens);
+/// Compiles the tokens and prints the warnings that are not allowed.
+fn run_compiler(compiler: &mut Compiler, warning_levels: &WarningLevels, includer: &Includer) -> Result<Vec<u8>, Error> {
+    let result: Result<Vec<u8>, Vec<Diagnostic>> = compiler.compile();
+    let (denied, warnings): (Vec<Diagnostic>, Vec<Diagnostic>) = warning_levels.apply(compiler.warnings())
+        .into_iter()
+        .partition(|warning| warning.severity == Severity::Error);
+    print_diagnostics(&warnings, includer);
+    match result {
+        Ok(_) if !denied.is_empty() => Err(report(denied, includer)),
+        Ok(data) => Ok(data),
+        Err(mut errors) => {
+            errors.extend(denied);
+            errors.sort_by_key(|error| (error.span.file, error.span.offset));
+            Err(report(errors, includer))
+        }
     }
+}
 
-    let mut compiler: Compiler = Compiler::new(tokens);
-    let data: Vec<u8> = compiler.compile()?;
-
-    let mut result: String = format!("const unsigned char {}[{}] = {{\n\t", args.get_music_name(), data.len());
-    let bytes: &[u8] = match args.export_type {
-        ExportType::Code => {
-            let bytes_per_line = 17;
-            let formatted_bytes: Vec<String> = data.iter().enumerate()
-                .map(|(idx, &byte)| {
-                    let byte_str = format!("0x{:02X}", byte);
-                    if (idx + 1) % bytes_per_line == 0 && idx < data.len() - 1 {
-                        format!("{},\n\t", byte_str)
-                    } else if idx < data.len() - 1 {
-                        format!("{},", byte_str)
-                    } else {
-                        byte_str
-                    }
-                })
-                .collect();
-
-            result.push_str(&formatted_bytes.join(""));
-            result.push_str("\n};");
-            if args.verbose {
-                println!("Result:\n{}", result);
+/// Prints the warnings found after compiling, failing if one of them is denied.
+fn report_warnings(warnings: &[Diagnostic], warning_levels: &WarningLevels, includer: &Includer) -> Result<(), Error> {
+    let (denied, warnings): (Vec<Diagnostic>, Vec<Diagnostic>) = warning_levels.apply(warnings)
+        .into_iter()
+        .partition(|warning| warning.severity == Severity::Error);
+    print_diagnostics(&warnings, includer);
+    if !denied.is_empty() {
+        return Err(report(denied, includer));
+    }
+    Ok(())
+}
+
+/// Prints diagnostics with the source code they point to.
+fn report(diagnostics: Vec<Diagnostic>, includer: &Includer) -> Error {
+    print_diagnostics(&diagnostics, includer);
+    match diagnostics.len() {
+        1 => Error::new(ErrorKind::InvalidData, "Could not compile due to previous error."),
+        count => Error::new(ErrorKind::InvalidData, format!("Could not compile due to {} previous errors.", count))
+    }
+}
+
+fn disassemble(args: DisassembleArgs) -> Result<(), Error> {
+    let data: Vec<u8> = load_data(&args.input_path)?;
+    let mut decompiler: Decompiler = Decompiler::new(data);
+    let source_code: String = decompiler.decompile()?;
+
+    match &args.output_path {
+        Some(path) => File::create(path)?.write_all(source_code.as_bytes())?,
+        None => print!("{}", source_code)
+    }
+    Ok(())
+}
+
+fn render(args: RenderArgs) -> Result<(), Error> {
+    let data: Vec<u8> = load_data(&args.input_path)?;
+    let mut player: Player = Player::new(data, args.sample_rate)?;
+    let samples: Vec<u8> = player.render(args.max_seconds)?;
+    let mut file: BufWriter<File> = BufWriter::new(File::create(args.get_output_path())?);
+    write_wav(&mut file, &samples, args.sample_rate)?;
+    file.flush()
+}
+
+fn import(args: ImportArgs) -> Result<(), Error> {
+    match args.format {
+        ImportFormat::Midi(args) => {
+            let midi_import: MidiImport = import_midi(&std::fs::read(&args.input_path)?)?;
+            for warning in &midi_import.warnings {
+                eprintln!("warning: {}", warning);
             }
-            result.as_bytes()
-        },
-        ExportType::Raw => {
-            data.as_slice()
+            File::create(args.get_output_path())?.write_all(midi_import.song.to_string().as_bytes())
         }
-    };
+    }
+}
+
+fn check(args: CheckArgs) -> Result<(), Error> {
+    let (includer, mut compiler): (Includer, Compiler) = load_song(&args.input_path, &args.song.include_paths, false)?;
+    let target: Target = args.song.get_target()?;
+    compiler.set_target(target.clone());
+    let (song, _): (Song, Vec<Diagnostic>) = compiler.parse();
+    let warning_levels: WarningLevels = args.song.get_warning_levels()?;
+    let data: Vec<u8> = run_compiler(&mut compiler, &warning_levels, &includer)?;
+    if args.timing {
+        let timings: Vec<ChannelTiming> = channel_timings(&data, &target)?;
+        for (channel, timing) in timings.iter().enumerate() {
+            println!(
+                "Channel {}: {} ticks, ends at {}{}",
+                channel_name(channel), timing.ticks,
+                timing.end(compiler.time_signatures().get(channel).map_or(&[], Vec::as_slice)),
+                if timing.stopped { " and stops" } else { "" }
+            );
+        }
+        report_warnings(&check_timing(&song, &timings, compiler.time_signatures()), &warning_levels, &includer)?;
+    }
+    Ok(())
+}
+
+/// Compiles the song, then reports its lints like warnings.
+fn lint_song(args: LintArgs) -> Result<(), Error> {
+    let (includer, mut compiler): (Includer, Compiler) = load_song(&args.input_path, &args.song.include_paths, false)?;
+    let warning_levels: WarningLevels = args.song.get_warning_levels()?;
+    compiler.set_target(args.song.get_target()?);
+    let (song, _): (Song, Vec<Diagnostic>) = compiler.parse();
+    run_compiler(&mut compiler, &warning_levels, &includer)?;
+    report_warnings(&lint(&song, compiler.compiled_items()), &warning_levels, &includer)
+}
+
+/// Compiles the input files and the songs of the manifest into one bank.
+fn bank(args: BankArgs) -> Result<(), Error> {
+    let manifest: Option<Manifest> = args.manifest.as_deref().map(Manifest::load).transpose()?;
+    let mut song_paths: Vec<(String, PathBuf)> = args.input_paths.iter().map(|path| (song_name(path), path.clone())).collect();
+    if let Some(manifest) = &manifest {
+        song_paths.extend(manifest.songs.iter().map(|song| (song.name.clone().unwrap_or(song_name(&song.path)), song.path.clone())));
+    }
+    let target: Target = args.song.get_target()?;
+    let warning_levels: WarningLevels = args.song.get_warning_levels()?;
+    let mut songs: Vec<(String, Vec<u8>)> = Vec::new();
+    for (name, path) in song_paths {
+        let (includer, mut compiler): (Includer, Compiler) = load_song(&path, &args.song.include_paths, false)?;
+        compiler.set_target(target.clone());
+        let mut data: Vec<u8> = run_compiler(&mut compiler, &warning_levels, &includer)?;
+        if args.optimize {
+            let report: OptimizationReport;
+            (data, report) = optimize(&data, target.channels)?;
+            println!("{}: {}", name, report);
+        }
+        songs.push((name, data));
+    }
 
-    let mut file: File = File::create(args.get_output_path())?;
-    file.write_all(bytes)?;
+    let bank: Bank = build_bank(&songs, target.channels)?;
+    let bank_name: String = args.bank_name.clone()
+        .or(manifest.and_then(|manifest| manifest.name))
+        .map_or(String::from("BANK"), |name| identifier(&name));
+    let exporter: Box<dyn Exporter> = args.get_exporter(&bank, &bank_name, &target)?;
+    println!("{}", bank);
+    let output_path: PathBuf = args.get_output_path(exporter.as_ref());
+    File::create(&output_path)?.write_all(&exporter.export(&bank.data, &bank_name)?)?;
+    for (extension, content) in exporter.companion_files(&bank.data, &bank_name)? {
+        File::create(output_path.with_extension(extension))?.write_all(&content)?;
+    }
+    Ok(())
+}
+
+/// Formats the files in place, or only lists the unformatted ones with `--check`.
+fn format(args: FmtArgs) -> Result<(), Error> {
+    let mut formatter: Formatter = Formatter::new();
+    formatter.set_width(args.width);
+    formatter.set_align(args.align);
+    formatter.set_channels(args.get_target()?.channels);
+    let mut unformatted_files: usize = 0;
+    for path in &args.input_paths {
+        let source_code: String = std::fs::read_to_string(path)?;
+        let mut lexer: Lexer = Lexer::new(source_code.clone());
+        lexer.set_keep_trivia(true);
+        let tokens: Vec<Token> = lexer.tokenize().map_err(|diagnostics| {
+            for diagnostic in &diagnostics {
+                eprintln!("{}", diagnostic.render(&source_code, &path.display().to_string()));
+            }
+            Error::new(ErrorKind::InvalidData, format!("Could not format {} due to previous errors.", path.display()))
+        })?;
+        let formatted_code: String = formatter.format(&tokens);
+        if formatted_code == source_code {
+            continue;
+        }
+        if args.check {
+            println!("{} is not formatted", path.display());
+            unformatted_files += 1;
+        } else {
+            std::fs::write(path, formatted_code)?;
+        }
+    }
+    match (args.check, unformatted_files) {
+        (true, 0) => println!("Checked sucessfuly!"),
+        (true, count) => return Err(Error::new(ErrorKind::InvalidData, format!("{} files are not formatted.", count))),
+        (false, _) => println!("Formatted sucessfuly!")
+    }
+    Ok(())
+}
+
+fn compile(args: CompilerArgs) -> Result<(), Error> {
+    let (includer, mut compiler): (Includer, Compiler) = load_song(&args.input_path, &args.song.include_paths, args.verbose)?;
+    let target: Target = args.song.get_target()?;
+    compiler.set_target(target.clone());
+    if let Some(max_errors) = args.max_errors {
+        compiler.set_max_errors(max_errors.get());
+    }
+    let mut data: Vec<u8> = run_compiler(&mut compiler, &args.song.get_warning_levels()?, &includer)?;
+    if args.optimize {
+        let report: OptimizationReport;
+        (data, report) = optimize(&data, target.channels)?;
+        println!("{}", report);
+    }
+    if args.compress {
+        let report: CompressionReport;
+        (data, report) = compress(&data, target.channels)?;
+        println!("{}", report);
+    }
+
+    let exporter: Box<dyn Exporter> = args.get_exporter(&target);
+    let music_name: String = args.get_music_name(exporter.as_ref());
+    let output: Vec<u8> = exporter.export(&data, &music_name)?;
+    if args.verbose && !matches!(args.export_type, ExportType::Raw | ExportType::Midi) {
+        println!("Result:\n{}", String::from_utf8_lossy(&output));
+    }
 
-    if args.export_type == ExportType::Code {
-        let mut path: PathBuf = args.get_output_path();
-        path.set_extension("h");
-        let mut header_file: File = File::create(path)?;
-        let music_name: String = args.get_music_name();
-        let header: String = format!("#ifndef {0}_H\n#define {0}_H\n\nextern const unsigned char {0}[];\n\n#endif", music_name);
-        header_file.write_all(header.as_bytes())?;
+    let output_path: PathBuf = args.get_output_path(exporter.as_ref());
+    let mut file: File = File::create(&output_path)?;
+    file.write_all(&output)?;
+    for (extension, content) in exporter.companion_files(&data, &music_name)? {
+        let mut companion_file: File = File::create(output_path.with_extension(extension))?;
+        companion_file.write_all(&content)?;
     }
 
     Ok(())
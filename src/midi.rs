@@ -0,0 +1,488 @@
+use std::{fmt::{Display, Formatter}, io::{Error, ErrorKind}};
+
+use crate::{
+    ast::{Duration, Pitch, Song},
+    builder::{ChannelBuilder, SequenceBuilder, SongBuilder},
+    driver::{ChannelState, Driver, CHANNELS},
+    player::SYNTHESIS_LOOP_RATE,
+    timing::channel_name
+};
+
+/// A whole note lasts 128 driver ticks, so a MIDI tick is a driver tick.
+pub const TICKS_PER_QUARTER: u16 = 32;
+/// Most channels of an export, the MIDI channel 10 being kept for percussions.
+pub const MAX_MIDI_CHANNELS: u8 = 15;
+/// Highest key of a MIDI note event.
+const MAX_MIDI_KEY: u8 = 127;
+
+/// MIDI key of the note a channel plays, using the same octave model as the player.
+/// Notes above the G of octave 9 go past the MIDI keys.
+fn midi_key(channel: &ChannelState) -> Option<u8> {
+    if channel.note == 0 || channel.volume == 0 || channel.volume > 8 {
+        return None;
+    }
+    Some(12 * (channel.octave + 2) + channel.note - 1)
+}
+
+/// MIDI channel of a driver channel, skipping the percussion channel.
+fn midi_channel(index: usize) -> u8 {
+    match index < 9 {
+        true => index as u8,
+        false => index as u8 + 1
+    }
+}
+
+/// Velocity of a `V` command, `v8` being the loudest.
+fn velocity(channel: &ChannelState) -> u8 {
+    15 + (9 - channel.volume) * 14
+}
+
+fn tempo_event(tempo: u8) -> Vec<u8> {
+    let seconds_per_tick: f64 = ((tempo as u32) << 4) as f64 / SYNTHESIS_LOOP_RATE;
+    let microseconds: u32 = ((seconds_per_tick * TICKS_PER_QUARTER as f64 * 1_000_000.0) as u32).clamp(1, 0xFF_FFFF);
+    vec![0xFF, 0x51, 0x03, (microseconds >> 16) as u8, (microseconds >> 8) as u8, microseconds as u8]
+}
+
+fn write_variable_length(bytes: &mut Vec<u8>, value: u64) {
+    let mut groups: Vec<u8> = vec![(value & 0x7F) as u8];
+    let mut value: u64 = value >> 7;
+    while value > 0 {
+        groups.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+struct Track {
+    bytes: Vec<u8>,
+    last_tick: u64
+}
+
+impl Track {
+    fn new(name: &str) -> Self {
+        let mut track: Track = Self {
+            bytes: Vec::new(),
+            last_tick: 0
+        };
+        let mut event: Vec<u8> = vec![0xFF, 0x03];
+        write_variable_length(&mut event, name.len() as u64);
+        event.extend(name.as_bytes());
+        track.event(0, &event);
+        track
+    }
+
+    fn event(&mut self, tick: u64, event: &[u8]) {
+        write_variable_length(&mut self.bytes, tick - self.last_tick);
+        self.bytes.extend(event);
+        self.last_tick = tick;
+    }
+
+    fn finish(mut self, tick: u64, result: &mut Vec<u8>) {
+        self.event(tick, &[0xFF, 0x2F, 0x00]);
+        result.extend(b"MTrk");
+        result.extend((self.bytes.len() as u32).to_be_bytes());
+        result.extend(self.bytes);
+    }
+}
+
+/// Plays compiled data with the driver emulation and records it as a Standard MIDI File,
+/// with a tempo track and one track per channel. Loops and macros are expanded.
+/// `channels` is the number of channels of the target, at most [`MAX_MIDI_CHANNELS`].
+pub fn export_midi(data: Vec<u8>, channels: u8) -> Result<Vec<u8>, Error> {
+    if channels > MAX_MIDI_CHANNELS {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("A MIDI file holds {} channels besides percussions but the target has {}.", MAX_MIDI_CHANNELS, channels)
+        ));
+    }
+    let mut driver: Driver = Driver::with_channels(data, channels as usize)?;
+    let mut tempo: u8 = driver.tempo();
+    let mut tempo_track: Track = Track::new("Tempo");
+    tempo_track.event(0, &tempo_event(tempo));
+    let mut tracks: Vec<Track> = (0..channels as usize)
+        .map(|index| Track::new(&format!("Channel {}", channel_name(index))))
+        .collect();
+    let mut playing: Vec<Option<u8>> = vec![None; channels as usize];
+
+    let mut tick: u64 = 0;
+    loop {
+        let starting: Vec<bool> = driver.channels().iter()
+            .map(|channel| channel.remaining_ticks == 0 && !channel.is_finished())
+            .collect();
+        driver.tick()?;
+        if driver.is_finished() {
+            break;
+        }
+        if driver.tempo() != tempo {
+            tempo = driver.tempo();
+            tempo_track.event(tick, &tempo_event(tempo));
+        }
+        for (index, channel) in driver.channels().iter().enumerate() {
+            if !starting[index] {
+                continue;
+            }
+            if let Some(key) = playing[index].take() {
+                tracks[index].event(tick, &[0x80 | midi_channel(index), key, 0]);
+            }
+            if channel.is_finished() {
+                continue;
+            }
+            if let Some(key) = midi_key(channel) {
+                if key > MAX_MIDI_KEY {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Channel {} plays MIDI key {} at tick {}, above the highest key {}.", channel_name(index), key, tick, MAX_MIDI_KEY)
+                    ));
+                }
+                tracks[index].event(tick, &[0x90 | midi_channel(index), key, velocity(channel)]);
+                playing[index] = Some(key);
+            }
+        }
+        tick += 1;
+    }
+
+    let mut result: Vec<u8> = Vec::new();
+    result.extend(b"MThd");
+    result.extend(6u32.to_be_bytes());
+    result.extend(1u16.to_be_bytes());
+    result.extend((channels as u16 + 1).to_be_bytes());
+    result.extend(TICKS_PER_QUARTER.to_be_bytes());
+    tempo_track.finish(tick, &mut result);
+    for (index, mut track) in tracks.into_iter().enumerate() {
+        if let Some(key) = playing[index] {
+            track.event(tick, &[0x80 | midi_channel(index), key, 0]);
+        }
+        track.finish(tick, &mut result);
+    }
+    Ok(result)
+}
+
+/// Tempo of MIDI files without tempo event, 120 BPM.
+const DEFAULT_MICROSECONDS_PER_QUARTER: u32 = 500_000;
+
+/// Notes the import had to change to fit µMML.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportWarning {
+    /// The 4 channels were busy.
+    Dropped { track: usize, tick: u64, key: u8 },
+    /// The note was delayed by `units` 128th of a whole note to wait for a free channel.
+    Shifted { track: usize, tick: u64, key: u8, units: u64 },
+    /// The note was moved by whole octaves into octaves 1-5.
+    Transposed { track: usize, tick: u64, key: u8, octaves: i8 },
+    /// The note was shortened by `lost_ticks` 128th of a whole note, as no duration has its length.
+    Truncated { track: usize, tick: u64, lost_ticks: u64 },
+    /// Only the first tempo of the file is used.
+    TempoChangeIgnored { tick: u64 }
+}
+
+impl ImportWarning {
+    /// MIDI tick of the note or tempo change.
+    pub fn tick(&self) -> u64 {
+        match self {
+            Self::Dropped { tick, .. } | Self::Shifted { tick, .. } |
+            Self::Transposed { tick, .. } | Self::Truncated { tick, .. } | Self::TempoChangeIgnored { tick } => *tick
+        }
+    }
+}
+
+fn key_name(key: u8) -> String {
+    format!("{}{}", Pitch::from_semitone(key % 12).name().to_uppercase(), (key / 12) as i8 - 1)
+}
+
+impl Display for ImportWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dropped { track, tick, key } =>
+                write!(f, "Track {}, tick {}: note {} dropped, the {} channels are busy.", track, tick, key_name(*key), CHANNELS),
+            Self::Shifted { track, tick, key, units } =>
+                write!(f, "Track {}, tick {}: note {} delayed by {}/128 of a whole note to fit in a channel.", track, tick, key_name(*key), units),
+            Self::Transposed { track, tick, key, octaves } =>
+                write!(f, "Track {}, tick {}: note {} moved by {} octaves.", track, tick, key_name(*key), octaves),
+            Self::Truncated { track, tick, lost_ticks } =>
+                write!(f, "Track {}, tick {}: note shortened by {}/128 of a whole note, no duration matches its length.", track, tick, lost_ticks),
+            Self::TempoChangeIgnored { tick } =>
+                write!(f, "Tick {}: tempo change ignored.", tick)
+        }
+    }
+}
+
+/// Song converted from a MIDI file.
+#[derive(Debug, Clone)]
+pub struct MidiImport {
+    pub song: Song,
+    pub warnings: Vec<ImportWarning>
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MidiNote {
+    track: usize,
+    channel: u8,
+    start: u64,
+    end: u64,
+    key: u8,
+    velocity: u8
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Invalid MIDI file: {}", message))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            position: 0
+        }
+    }
+
+    fn is_end(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let bytes: &[u8] = self.bytes.get(self.position..self.position + length)
+            .ok_or_else(|| invalid_data("unexpected end of file."))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        let bytes: &[u8] = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        let bytes: &[u8] = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_variable_length(&mut self) -> Result<u64, Error> {
+        let mut value: u64 = 0;
+        for _ in 0..4 {
+            let byte: u8 = self.read_u8()?;
+            value = (value << 7) | (byte & 0x7F) as u64;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("variable length quantity over 4 bytes."))
+    }
+}
+
+/// Reads the notes of a track, and its tempo events as `(tick, microseconds per quarter)`.
+fn read_track(track: usize, bytes: &[u8], notes: &mut Vec<MidiNote>, tempos: &mut Vec<(u64, u32)>) -> Result<(), Error> {
+    let mut reader: Reader = Reader::new(bytes);
+    let mut tick: u64 = 0;
+    let mut running_status: Option<u8> = None;
+    let mut playing: Vec<(u8, u8, u64, u8)> = Vec::new();
+    while !reader.is_end() {
+        tick += reader.read_variable_length()?;
+        let mut status: u8 = reader.read_u8()?;
+        if status < 0x80 {
+            status = running_status.ok_or_else(|| invalid_data("data byte without status."))?;
+            reader.position -= 1;
+        }
+        match status {
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let channel: u8 = status & 0x0F;
+                let data_length: usize = if matches!(status >> 4, 0xC | 0xD) { 1 } else { 2 };
+                let data: &[u8] = reader.read_bytes(data_length)?;
+                match (status >> 4, data) {
+                    (0x9, &[key, velocity]) if velocity > 0 => playing.push((channel, key, tick, velocity)),
+                    (0x8 | 0x9, &[key, _]) => {
+                        if let Some(index) = playing.iter().position(|note| note.0 == channel && note.1 == key) {
+                            let (_, _, start, velocity) = playing.remove(index);
+                            notes.push(MidiNote { track, channel, start, end: tick, key, velocity });
+                        }
+                    },
+                    _ => {}
+                }
+            },
+            0xF0 | 0xF7 => {
+                let length: u64 = reader.read_variable_length()?;
+                reader.read_bytes(length as usize)?;
+            },
+            0xFF => {
+                let meta_type: u8 = reader.read_u8()?;
+                let length: u64 = reader.read_variable_length()?;
+                let data: &[u8] = reader.read_bytes(length as usize)?;
+                match (meta_type, data) {
+                    (0x51, &[high, middle, low]) => tempos.push((tick, u32::from_be_bytes([0, high, middle, low]))),
+                    (0x2F, _) => break,
+                    _ => {}
+                }
+            },
+            _ => return Err(invalid_data(&format!("unknown status byte 0x{:02X}.", status)))
+        }
+    }
+    for (channel, key, start, velocity) in playing {
+        notes.push(MidiNote { track, channel, start, end: tick, key, velocity });
+    }
+    Ok(())
+}
+
+/// Durations the driver can play as `(ticks, duration)`, longest first.
+fn playable_durations() -> Vec<(u16, Duration)> {
+    let mut durations: Vec<(u16, Duration)> = (0..=0xE)
+        .filter_map(Duration::decode)
+        .map(|duration| (duration.ticks(), duration))
+        .collect();
+    durations.sort_by_key(|(ticks, duration)| (std::cmp::Reverse(*ticks), duration.dotted));
+    durations.dedup_by_key(|(ticks, _)| *ticks);
+    durations
+}
+
+/// Longest playable duration not longer than `units`.
+fn longest_duration(durations: &[(u16, Duration)], units: u64) -> (u64, Duration) {
+    durations.iter()
+        .find(|(ticks, _)| *ticks as u64 <= units)
+        .map(|(ticks, duration)| (*ticks as u64, *duration))
+        .unwrap_or((1, Duration::HUNDRED_TWENTY_EIGHTH))
+}
+
+struct Lane {
+    end: u64,
+    source: Option<(usize, u8)>,
+    /// Notes with their start and length in 128th of a whole note.
+    notes: Vec<(u64, u64, MidiNote)>
+}
+
+/// Converts a Standard MIDI File to a µMML song, quantized to 128th notes.
+/// Notes are spread over the 4 channels, keeping notes of the same track and MIDI channel together when possible.
+pub fn import_midi(bytes: &[u8]) -> Result<MidiImport, Error> {
+    let mut reader: Reader = Reader::new(bytes);
+    if reader.read_bytes(4)? != b"MThd" {
+        return Err(invalid_data("missing MThd header."));
+    }
+    let header_length: u32 = reader.read_u32()?;
+    let _format: u16 = reader.read_u16()?;
+    let num_of_tracks: u16 = reader.read_u16()?;
+    let division: u16 = reader.read_u16()?;
+    if division & 0x8000 != 0 || division == 0 {
+        return Err(invalid_data("SMPTE time division is not supported."));
+    }
+    reader.read_bytes(header_length.saturating_sub(6) as usize)?;
+
+    let mut notes: Vec<MidiNote> = Vec::new();
+    let mut tempos: Vec<(u64, u32)> = Vec::new();
+    let mut track: usize = 0;
+    while !reader.is_end() && track < num_of_tracks as usize {
+        let chunk_type: &[u8] = reader.read_bytes(4)?;
+        let length: u32 = reader.read_u32()?;
+        let chunk: &[u8] = reader.read_bytes(length as usize)?;
+        if chunk_type == b"MTrk" {
+            track += 1;
+            read_track(track, chunk, &mut notes, &mut tempos)?;
+        }
+    }
+
+    let mut warnings: Vec<ImportWarning> = Vec::new();
+    tempos.sort_by_key(|(tick, _)| *tick);
+    let microseconds: u32 = tempos.first().map(|(_, tempo)| *tempo).unwrap_or(DEFAULT_MICROSECONDS_PER_QUARTER);
+    for (tick, tempo) in tempos.iter().skip(1) {
+        if *tempo != microseconds {
+            warnings.push(ImportWarning::TempoChangeIgnored { tick: *tick });
+        }
+    }
+    let quarter_seconds: f64 = microseconds as f64 / 1_000_000.0;
+    let tempo: u8 = (quarter_seconds * SYNTHESIS_LOOP_RATE / (16 * TICKS_PER_QUARTER as u32) as f64)
+        .round()
+        .clamp(1.0, 255.0) as u8;
+
+    let to_units = |tick: u64| (tick * TICKS_PER_QUARTER as u64 + division as u64 / 2) / division as u64;
+    notes.sort_by_key(|note| (note.start, std::cmp::Reverse(note.key)));
+    let mut lanes: Vec<Lane> = (0..CHANNELS).map(|_| Lane { end: 0, source: None, notes: Vec::new() }).collect();
+    for note in &notes {
+        let source: (usize, u8) = (note.track, note.channel);
+        let mut start: u64 = to_units(note.start);
+        let end: u64 = to_units(note.end).max(start + 1);
+        let free_lane: Option<usize> = lanes.iter()
+            .position(|lane| lane.end <= start && lane.source == Some(source))
+            .or_else(|| lanes.iter().position(|lane| lane.end <= start));
+        let index: usize = match free_lane {
+            Some(index) => index,
+            None => {
+                let index: usize = (0..CHANNELS).min_by_key(|&index| lanes[index].end).unwrap_or(0);
+                if lanes[index].end >= end {
+                    warnings.push(ImportWarning::Dropped { track: note.track, tick: note.start, key: note.key });
+                    continue;
+                }
+                warnings.push(ImportWarning::Shifted {
+                    track: note.track,
+                    tick: note.start,
+                    key: note.key,
+                    units: lanes[index].end - start
+                });
+                start = lanes[index].end;
+                index
+            }
+        };
+        let lane: &mut Lane = &mut lanes[index];
+        lane.end = end;
+        lane.source = Some(source);
+        lane.notes.push((start, end - start, *note));
+    }
+
+    let durations: Vec<(u16, Duration)> = playable_durations();
+    let song_length: u64 = lanes.iter().map(|lane| lane.end).max().unwrap_or(0);
+    let rests = |channel: &mut ChannelBuilder, mut units: u64| {
+        while units > 0 {
+            let (ticks, duration) = longest_duration(&durations, units);
+            channel.rest(duration);
+            units -= ticks;
+        }
+    };
+    let mut builder: SongBuilder = SongBuilder::default();
+    for (index, lane) in lanes.iter().enumerate() {
+        builder.channel(|channel| {
+            if index == 0 {
+                channel.tempo(tempo);
+            }
+            let mut time: u64 = 0;
+            let mut octave: Option<u8> = None;
+            let mut volume: Option<u8> = None;
+            for &(start, length, note) in &lane.notes {
+                rests(channel, start - time);
+                let note_octave: i8 = (note.key / 12) as i8 - 1;
+                let clamped_octave: u8 = note_octave.clamp(1, 5) as u8;
+                if clamped_octave as i8 != note_octave {
+                    warnings.push(ImportWarning::Transposed {
+                        track: note.track,
+                        tick: note.start,
+                        key: note.key,
+                        octaves: clamped_octave as i8 - note_octave
+                    });
+                }
+                if octave != Some(clamped_octave) {
+                    channel.octave(clamped_octave);
+                    octave = Some(clamped_octave);
+                }
+                let note_volume: u8 = ((note.velocity as u16 * 8 + 63) / 127).clamp(1, 8) as u8;
+                if volume != Some(note_volume) {
+                    channel.volume(note_volume);
+                    volume = Some(note_volume);
+                }
+                let (ticks, duration) = longest_duration(&durations, length);
+                if ticks < length {
+                    warnings.push(ImportWarning::Truncated { track: note.track, tick: note.start, lost_ticks: length - ticks });
+                }
+                channel.note(Pitch::from_semitone(note.key % 12), duration);
+                time = start + ticks;
+            }
+            rests(channel, song_length - time);
+        });
+    }
+    let song: Song = builder.build()?;
+    warnings.sort_by_key(ImportWarning::tick);
+    Ok(MidiImport { song, warnings })
+}
@@ -0,0 +1,511 @@
+use std::{cmp::Reverse, collections::HashMap, fmt::{Display, Formatter}, io::{Error, ErrorKind}};
+
+use crate::{ast::Duration, driver::MAX_LOOPS};
+
+/// Longest phrase, in commands, looked for when compressing.
+const MAX_PHRASE_LENGTH: usize = 32;
+/// Same limit as the compiler.
+const MAX_HEADERS: usize = 254;
+
+/// A command with its argument.
+pub(crate) type Command = Vec<u8>;
+/// Section and command index of a phrase.
+type Position = (usize, usize);
+/// Bytes saved by a phrase, then its first position and length.
+type PhraseRank = (usize, Reverse<(Position, usize)>);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionReport {
+    pub original_size: usize,
+    pub compressed_size: usize,
+    pub macros: usize,
+    pub loops: usize
+}
+
+impl CompressionReport {
+    pub fn bytes_saved(&self) -> usize {
+        self.original_size.saturating_sub(self.compressed_size)
+    }
+}
+
+impl Display for CompressionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Compression saved {} bytes ({} -> {}) with {} new macros and {} new loops.",
+            self.bytes_saved(), self.original_size, self.compressed_size, self.macros, self.loops
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OptimizationReport {
+    pub original_size: usize,
+    pub optimized_size: usize,
+    /// Octave and volume commands removed.
+    pub state_commands: usize,
+    pub merged_rests: usize,
+    pub empty_loops: usize
+}
+
+impl OptimizationReport {
+    pub fn bytes_saved(&self) -> usize {
+        self.original_size.saturating_sub(self.optimized_size)
+    }
+}
+
+impl Display for OptimizationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Optimization saved {} bytes ({} -> {}) by removing {} octave and volume commands, merging {} rests and dropping {} empty loops.",
+            self.bytes_saved(), self.original_size, self.optimized_size, self.state_commands, self.merged_rests, self.empty_loops
+        )
+    }
+}
+
+fn command_length(byte: u8) -> Option<usize> {
+    match byte {
+        0x00..=0xEF | 0xF1 | 0xF6 | 0xF8 => Some(1),
+        0xF0 | 0xF2 | 0xF3 | 0xF4 | 0xF5 | 0xF7 => Some(2),
+        _ => None
+    }
+}
+
+fn size(commands: &[Command]) -> usize {
+    commands.iter().map(Vec::len).sum()
+}
+
+/// Loop stack needed to play the commands, `None` if their loops are not balanced.
+fn loop_depth(commands: &[Command], macro_depths: &[usize]) -> Option<usize> {
+    let mut depth: usize = 0;
+    let mut max_depth: usize = 0;
+    for command in commands {
+        match command[0] {
+            0xF0 => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            },
+            0xF1 => depth = depth.checked_sub(1)?,
+            0xF2 => max_depth = max_depth.max(depth + macro_depths.get(command[1] as usize).copied().unwrap_or(MAX_LOOPS)),
+            _ => {}
+        }
+    }
+    (depth == 0).then_some(max_depth)
+}
+
+/// Loop depth before each command of a section.
+fn depths_before(commands: &[Command]) -> Vec<usize> {
+    let mut depth: usize = 0;
+    commands.iter()
+        .map(|command| {
+            let before: usize = depth;
+            match command[0] {
+                0xF0 => depth += 1,
+                0xF1 => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            before
+        })
+        .collect()
+}
+
+/// Splits compiled data into the commands of each section, channels first.
+pub(crate) fn read_sections(data: &[u8], channels: usize) -> Result<Vec<Vec<Command>>, Error> {
+    let invalid_data = |message: String| Error::new(ErrorKind::InvalidData, message);
+    let read_header = |index: usize| -> Result<usize, Error> {
+        match (data.get(index * 2), data.get(index * 2 + 1)) {
+            (Some(&high), Some(&low)) => Ok(((high as usize) << 8) | low as usize),
+            _ => Err(invalid_data(format!("Header {} is out of the data bounds.", index)))
+        }
+    };
+    let num_of_headers: usize = read_header(0)? / 2;
+    if num_of_headers < channels {
+        return Err(invalid_data(format!("Expected at least {} headers. Found {}.", channels, num_of_headers)));
+    }
+    let mut sections: Vec<Vec<Command>> = Vec::new();
+    for index in 0..num_of_headers {
+        let mut address: usize = read_header(index)?;
+        let mut commands: Vec<Command> = Vec::new();
+        loop {
+            let byte: u8 = *data.get(address)
+                .ok_or_else(|| invalid_data(format!("Section {} reads out of the data bounds.", index)))?;
+            if byte == 0xFF {
+                break;
+            }
+            let length: usize = command_length(byte)
+                .ok_or_else(|| invalid_data(format!("Unknown command 0x{:02X} at byte 0x{:04X}.", byte, address)))?;
+            let command: &[u8] = data.get(address..address + length)
+                .ok_or_else(|| invalid_data(format!("Section {} reads out of the data bounds.", index)))?;
+            commands.push(command.to_vec());
+            address += length;
+        }
+        sections.push(commands);
+    }
+    Ok(sections)
+}
+
+/// Writes sections back with their header table.
+fn write_sections(sections: &[Vec<Command>]) -> Result<Vec<u8>, Error> {
+    let mut result: Vec<u8> = vec![0; sections.len() * 2];
+    for (index, commands) in sections.iter().enumerate() {
+        let position: usize = result.len();
+        result[index * 2] = (position >> 8) as u8;
+        result[index * 2 + 1] = (position & 0xFF) as u8;
+        result.extend(commands.iter().flatten());
+        result.push(0xFF);
+    }
+    result.push(0x00);
+    if result.len() > u16::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidData, "Data is too large for 16-bit headers."));
+    }
+    Ok(result)
+}
+
+/// Factors repeated phrases of compiled data into loops and macros.
+/// Only channels are changed, as macros can't call other macros.
+struct Compressor {
+    channels: usize,
+    sections: Vec<Vec<Command>>,
+    macro_depths: Vec<usize>,
+    report: CompressionReport
+}
+
+impl Compressor {
+    fn new(data: &[u8], channels: usize) -> Result<Self, Error> {
+        let sections: Vec<Vec<Command>> = read_sections(data, channels)?;
+        let mut compressor: Compressor = Self {
+            channels,
+            sections,
+            macro_depths: Vec::new(),
+            report: CompressionReport {
+                original_size: data.len(),
+                ..CompressionReport::default()
+            }
+        };
+        compressor.macro_depths = compressor.sections[channels..].iter()
+            .map(|commands| loop_depth(commands, &[]).unwrap_or(MAX_LOOPS))
+            .collect();
+        Ok(compressor)
+    }
+
+    /// Turns consecutive repeats of a phrase into a loop. Returns whether a loop was added.
+    fn compress_loops(&mut self, section: usize) -> bool {
+        let commands: &Vec<Command> = &self.sections[section];
+        let depths: Vec<usize> = depths_before(commands);
+        let mut best: Option<(usize, usize, usize, usize)> = None;
+        for start in 0..commands.len() {
+            for length in 1..=MAX_PHRASE_LENGTH.min(commands.len() - start) {
+                let phrase: &[Command] = &commands[start..start + length];
+                let Some(depth) = loop_depth(phrase, &self.macro_depths) else {
+                    continue;
+                };
+                if depths[start] + 1 + depth > MAX_LOOPS {
+                    continue;
+                }
+                let mut repeats: usize = 1;
+                while repeats < 255 && commands.get(start + repeats * length..start + (repeats + 1) * length) == Some(phrase) {
+                    repeats += 1;
+                }
+                let saving: isize = ((repeats - 1) * size(phrase)) as isize - 3;
+                if saving > 0 && best.is_none_or(|(_, _, _, best_saving)| saving as usize > best_saving) {
+                    best = Some((start, length, repeats, saving as usize));
+                }
+            }
+        }
+        let Some((start, length, repeats, _)) = best else {
+            return false;
+        };
+        let commands: &mut Vec<Command> = &mut self.sections[section];
+        let mut replacement: Vec<Command> = vec![vec![0xF0, repeats as u8]];
+        replacement.extend_from_slice(&commands[start..start + length]);
+        replacement.push(vec![0xF1]);
+        commands.splice(start..start + repeats * length, replacement);
+        self.report.loops += 1;
+        true
+    }
+
+    /// Moves the phrases saving the most bytes into new macros, one at a time.
+    /// Phrases are indexed once: the calls replacing phrases split the channels, the other phrases keeping their positions.
+    fn extract_macros(&mut self) {
+        let channels: Vec<Vec<Command>> = self.sections[..self.channels].to_vec();
+        let depths: Vec<Vec<usize>> = channels.iter().map(|commands| depths_before(commands)).collect();
+        let mut occurrences: HashMap<&[Command], Vec<Position>> = HashMap::new();
+        for (section, commands) in channels.iter().enumerate() {
+            for start in 0..commands.len() {
+                for length in 2..=MAX_PHRASE_LENGTH.min(commands.len() - start) {
+                    let phrase: &[Command] = &commands[start..start + length];
+                    if phrase.iter().any(|command| command[0] == 0xF2) {
+                        break;
+                    }
+                    occurrences.entry(phrase).or_default().push((section, start));
+                }
+            }
+        }
+        // Phrases found once or with unbalanced loops can't become macros.
+        occurrences.retain(|phrase, positions| positions.len() > 1 && loop_depth(phrase, &[]).is_some());
+
+        // Start, length and macro id of the calls added to each channel.
+        let mut calls: Vec<Vec<(usize, usize, u8)>> = vec![Vec::new(); self.channels];
+        while self.sections.len() < MAX_HEADERS {
+            // Ties go to the phrase found first, then to the shortest one, for the same result on every run.
+            let mut best: Option<(PhraseRank, &[Command], Vec<Position>)> = None;
+            for (&phrase, positions) in &occurrences {
+                let depth: usize = loop_depth(phrase, &[]).unwrap_or(MAX_LOOPS);
+                let mut kept: Vec<Position> = Vec::new();
+                for &(section, start) in positions {
+                    let overlaps: bool = kept.last().is_some_and(|&(last_section, last_start)| {
+                        last_section == section && start < last_start + phrase.len()
+                    });
+                    if !overlaps && depths[section][start] + depth <= MAX_LOOPS {
+                        kept.push((section, start));
+                    }
+                }
+                let phrase_size: usize = size(phrase);
+                let saving: isize = (kept.len() * phrase_size) as isize - (kept.len() * 2 + phrase_size + 3) as isize;
+                if saving <= 0 {
+                    continue;
+                }
+                let rank: PhraseRank = (saving as usize, Reverse((kept[0], phrase.len())));
+                if best.as_ref().is_none_or(|(best_rank, _, _)| rank > *best_rank) {
+                    best = Some((rank, phrase, kept));
+                }
+            }
+            let Some((_, phrase, positions)) = best else {
+                break;
+            };
+            let macro_id: u8 = (self.sections.len() - self.channels) as u8;
+            for &(section, start) in &positions {
+                calls[section].push((start, phrase.len(), macro_id));
+                // Phrases overlapping the call are gone.
+                for other_start in start.saturating_sub(MAX_PHRASE_LENGTH - 1)..start + phrase.len() {
+                    for length in 2..=MAX_PHRASE_LENGTH.min(channels[section].len() - other_start) {
+                        if other_start + length <= start {
+                            continue;
+                        }
+                        if let Some(other_positions) = occurrences.get_mut(&channels[section][other_start..other_start + length]) {
+                            other_positions.retain(|&position| position != (section, other_start));
+                        }
+                    }
+                }
+            }
+            occurrences.retain(|_, positions| positions.len() > 1);
+            self.macro_depths.push(loop_depth(phrase, &self.macro_depths).unwrap_or(MAX_LOOPS));
+            self.sections.push(phrase.to_vec());
+            self.report.macros += 1;
+        }
+
+        for (section, mut section_calls) in calls.into_iter().enumerate() {
+            section_calls.sort_unstable();
+            let mut commands: Vec<Command> = Vec::new();
+            let mut index: usize = 0;
+            for (start, length, macro_id) in section_calls {
+                commands.extend_from_slice(&channels[section][index..start]);
+                commands.push(vec![0xF2, macro_id]);
+                index = start + length;
+            }
+            commands.extend_from_slice(&channels[section][index..]);
+            self.sections[section] = commands;
+        }
+    }
+
+    fn compress_channel_loops(&mut self) {
+        for section in 0..self.channels {
+            while self.compress_loops(section) {}
+        }
+    }
+
+    fn finish(mut self) -> Result<(Vec<u8>, CompressionReport), Error> {
+        let result: Vec<u8> = write_sections(&self.sections)?;
+        self.report.compressed_size = result.len();
+        Ok((result, self.report))
+    }
+}
+
+/// Compresses compiled data by factoring repeated phrases of the channels into loops and new macros.
+pub fn compress(data: &[u8], channels: u8) -> Result<(Vec<u8>, CompressionReport), Error> {
+    let mut compressor: Compressor = Compressor::new(data, channels as usize)?;
+    compressor.compress_channel_loops();
+    compressor.extract_macros();
+    compressor.compress_channel_loops();
+    let (compressed, report) = compressor.finish()?;
+    if compressed.len() >= data.len() {
+        return Ok((data.to_vec(), CompressionReport {
+            original_size: data.len(),
+            compressed_size: data.len(),
+            ..CompressionReport::default()
+        }));
+    }
+    Ok((compressed, report))
+}
+
+/// Octave and volume nibbles known at a point of a section, `None` when they depend on what was played before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Settings {
+    octave: Option<u8>,
+    volume: Option<u8>
+}
+
+/// What calling a macro does to the octave and volume.
+#[derive(Debug, Clone, Copy, Default)]
+struct MacroEffect {
+    sets_octave: bool,
+    sets_volume: bool,
+    /// Settings left by the macro, whatever they were before the call.
+    end: Settings
+}
+
+fn is_state_command(command: &Command) -> bool {
+    matches!(command[0] >> 4, 0xD | 0xE)
+}
+
+/// Duration nibble of a rest lasting as long as two rests, if there is one.
+fn merged_rest(first: u8, second: u8) -> Option<u8> {
+    let ticks: u16 = Duration::nibble_ticks(first & 0x0F) + Duration::nibble_ticks(second & 0x0F);
+    (0..0x10).find(|&nibble| Duration::nibble_ticks(nibble) == ticks)
+}
+
+/// Whether the commands may change the octave and the volume.
+fn changed_settings(commands: &[Command], macros: &[MacroEffect]) -> (bool, bool) {
+    commands.iter().fold((false, false), |(octave, volume), command| match command[0] {
+        0xD0..=0xDF => (true, volume),
+        0xE0..=0xEF => (octave, true),
+        0xF2 => match macros.get(command[1] as usize) {
+            Some(effect) => (octave || effect.sets_octave, volume || effect.sets_volume),
+            None => (true, true)
+        },
+        _ => (octave, volume)
+    })
+}
+
+/// Index of the loop end matching the loop start at `start`.
+fn loop_end(commands: &[Command], start: usize) -> usize {
+    let mut depth: usize = 0;
+    for (index, command) in commands.iter().enumerate().skip(start) {
+        match command[0] {
+            0xF0 => depth += 1,
+            0xF1 => {
+                depth -= 1;
+                if depth == 0 {
+                    return index;
+                }
+            },
+            _ => {}
+        }
+    }
+    commands.len()
+}
+
+/// Removes commands that don't change what the driver plays, section by section.
+struct Peephole {
+    macros: Vec<MacroEffect>,
+    report: OptimizationReport
+}
+
+impl Peephole {
+    /// Removes the command of the same kind from the octave and volume commands ending the result, as it is overwritten.
+    fn remove_overwritten(&mut self, result: &mut Vec<Command>, kind: u8) {
+        let run_start: usize = result.iter().rposition(|command| !is_state_command(command)).map_or(0, |index| index + 1);
+        if let Some(offset) = result[run_start..].iter().position(|command| command[0] & 0xF0 == kind) {
+            result.remove(run_start + offset);
+            self.report.state_commands += 1;
+        }
+    }
+
+    /// Optimizes the commands once, returning them with the settings at their end.
+    fn optimize_once(&mut self, commands: &[Command]) -> (Vec<Command>, Settings) {
+        let mut result: Vec<Command> = Vec::new();
+        let mut settings: Settings = Settings::default();
+        for (index, command) in commands.iter().enumerate() {
+            match command[0] {
+                0xD0..=0xEF => {
+                    let kind: u8 = command[0] & 0xF0;
+                    let setting: &mut Option<u8> = if kind == 0xD0 { &mut settings.octave } else { &mut settings.volume };
+                    if *setting == Some(command[0] & 0x0F) {
+                        self.report.state_commands += 1;
+                        continue;
+                    }
+                    *setting = Some(command[0] & 0x0F);
+                    self.remove_overwritten(&mut result, kind);
+                },
+                0xF0 => {
+                    // From the second repetition, the loop starts with the settings it ended with.
+                    let (sets_octave, sets_volume) = changed_settings(&commands[index + 1..loop_end(commands, index)], &self.macros);
+                    if sets_octave {
+                        settings.octave = None;
+                    }
+                    if sets_volume {
+                        settings.volume = None;
+                    }
+                },
+                0xF1 if result.last().is_some_and(|last| last[0] == 0xF0) => {
+                    result.pop();
+                    self.report.empty_loops += 1;
+                    continue;
+                },
+                0xF2 => match self.macros.get(command[1] as usize) {
+                    Some(effect) => {
+                        if effect.sets_octave {
+                            settings.octave = effect.end.octave;
+                        }
+                        if effect.sets_volume {
+                            settings.volume = effect.end.volume;
+                        }
+                    },
+                    None => settings = Settings::default()
+                },
+                0x00..=0x0F => {
+                    let last_rest: Option<&mut Command> = result.last_mut().filter(|last| last[0] <= 0x0F);
+                    if let Some(last_rest) = last_rest {
+                        if let Some(nibble) = merged_rest(last_rest[0], command[0]) {
+                            last_rest[0] = nibble;
+                            self.report.merged_rests += 1;
+                            continue;
+                        }
+                    }
+                },
+                _ => {}
+            }
+            result.push(command.clone());
+        }
+        (result, settings)
+    }
+
+    /// Optimizes the commands until nothing more can be removed.
+    fn optimize_commands(&mut self, commands: &[Command]) -> (Vec<Command>, Settings) {
+        let (mut result, mut settings) = self.optimize_once(commands);
+        let mut length: usize = commands.len();
+        while result.len() < length {
+            length = result.len();
+            (result, settings) = self.optimize_once(&result);
+        }
+        (result, settings)
+    }
+}
+
+/// Removes octave and volume commands that don't change the state of the driver, merges adjacent rests and drops empty loops.
+/// A section starts with unknown settings, as a channel loops back with the settings it ended with.
+pub fn optimize(data: &[u8], channels: u8) -> Result<(Vec<u8>, OptimizationReport), Error> {
+    let mut sections: Vec<Vec<Command>> = read_sections(data, channels as usize)?;
+    let mut peephole: Peephole = Peephole {
+        macros: Vec::new(),
+        report: OptimizationReport {
+            original_size: data.len(),
+            ..OptimizationReport::default()
+        }
+    };
+    let (channel_sections, macro_sections) = sections.split_at_mut(channels as usize);
+    // Macros come first, so that the channels know what calling them does.
+    for commands in macro_sections {
+        let (optimized, end): (Vec<Command>, Settings) = peephole.optimize_commands(commands);
+        let (sets_octave, sets_volume) = changed_settings(&optimized, &[]);
+        peephole.macros.push(MacroEffect { sets_octave, sets_volume, end });
+        *commands = optimized;
+    }
+    for commands in channel_sections {
+        *commands = peephole.optimize_commands(commands).0;
+    }
+    let result: Vec<u8> = write_sections(&sections)?;
+    peephole.report.optimized_size = result.len();
+    Ok((result, peephole.report))
+}
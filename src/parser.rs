@@ -0,0 +1,379 @@
+use crate::{
+    ast::{Duration, Item, Pitch, Section, Song, Spanned},
+    diagnostic::{Diagnostic, DiagnosticCode, Span},
+    driver::CHANNELS,
+    token::{Token, TokenType}
+};
+
+/// Builds the syntax tree of a song from its tokens.
+pub struct Parser {
+    tokens: Vec<Token>,
+    current_token: Token,
+    current_index: usize,
+    /// End offset of the last consumed token.
+    last_end: usize,
+    channels: usize,
+    max_errors: Option<usize>,
+    errors: Vec<Diagnostic>
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        let first_token: Token = tokens.first().cloned().unwrap_or(Token::empty(1, 0, 0));
+        Self {
+            tokens,
+            current_token: first_token,
+            current_index: 0,
+            last_end: 0,
+            channels: CHANNELS,
+            max_errors: None,
+            errors: Vec::new()
+        }
+    }
+
+    /// Number of sections that are channels, the next ones being macros.
+    pub fn set_channels(&mut self, channels: u8) {
+        self.channels = channels as usize;
+    }
+
+    /// Stops the parsing once `max_errors` errors are found.
+    pub fn set_max_errors(&mut self, max_errors: usize) {
+        self.max_errors = Some(max_errors);
+    }
+
+    fn is_end_of_file(&self) -> bool {
+        self.current_token.token_type == TokenType::EndOfFile || self.current_index >= self.tokens.len()
+    }
+
+    fn advance(&mut self) {
+        if !self.is_end_of_file() {
+            self.last_end = self.current_token.offset + self.current_token.value.len();
+            if let Some(token) = self.tokens.get(self.current_index + 1) {
+                self.current_index += 1;
+                self.current_token = token.clone();
+                return;
+            }
+        }
+        self.current_token = Token::empty(self.current_token.line, self.current_token.column, self.current_token.offset);
+    }
+
+    /// Span from the start token to the last consumed token.
+    fn span_from(&self, start_token: &Token) -> Span {
+        Span {
+            length: self.last_end.saturating_sub(start_token.offset).max(1),
+            ..Span::from(start_token)
+        }
+    }
+
+    fn is_error_limit_reached(&self) -> bool {
+        self.max_errors.is_some_and(|max_errors| self.errors.len() >= max_errors)
+    }
+
+    /// Records an error and skips to the next command, `]` or `@`.
+    /// Jumps to the end of file once the maximum number of errors is reached.
+    fn recover(&mut self, diagnostic: Diagnostic, start_index: usize) {
+        self.errors.push(diagnostic);
+        if self.is_error_limit_reached() {
+            while !self.is_end_of_file() {
+                self.advance();
+            }
+            return;
+        }
+        if self.current_index == start_index {
+            self.advance();
+        }
+        while !matches!(
+            self.current_token.token_type,
+            TokenType::Command | TokenType::RightParen | TokenType::Arobase | TokenType::LeftParen |
+            TokenType::LessThan | TokenType::GreaterThan | TokenType::TransposeStart | TokenType::TransposeEnd |
+            TokenType::BarLine | TokenType::TimeSignature | TokenType::EndOfFile
+        ) {
+            self.advance();
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Spanned<u8>, Diagnostic> {
+        if self.current_token.token_type != TokenType::Number {
+            return Err(Diagnostic::error(
+                DiagnosticCode::NotANumber,
+                format!("Tried to convert a non number token '{}'", self.current_token.value),
+                Span::from(&self.current_token)
+            ));
+        }
+        let number_token: Token = self.current_token.clone();
+        self.advance();
+        if let Ok(number) = number_token.value.parse::<u8>() {
+            return Ok(Spanned::new(number, Span::from(&number_token)));
+        }
+
+        Err(Diagnostic::error(
+            DiagnosticCode::InvalidNumber,
+            format!("Failed to convert number '{}'", number_token.value),
+            Span::from(&number_token)
+        ).with_help("Expected a number between 0 and 255.".to_string()))
+    }
+
+    /// Name of a label or `(name)` token, without its delimiters.
+    fn parse_name(&mut self) -> Spanned<String> {
+        let name_token: Token = self.current_token.clone();
+        self.advance();
+        let name: &str = name_token.value.trim_start_matches('(').trim_end_matches([')', ':']);
+        Spanned::new(name.to_string(), Span::from(&name_token))
+    }
+
+    fn parse_duration(&mut self) -> Result<Option<Duration>, Diagnostic> {
+        if self.current_token.token_type != TokenType::Number {
+            return Ok(None);
+        }
+        let number_token: Token = self.current_token.clone();
+        let number: u8 = self.parse_number()?.value;
+        let is_dotted: bool = self.current_token.token_type == TokenType::Dot;
+        if let Some(duration) = Duration::new(number, is_dotted) {
+            if is_dotted {
+                self.advance();
+            }
+            return Ok(Some(duration));
+        }
+        Err(Diagnostic::error(
+            DiagnosticCode::InvalidDuration,
+            format!("Invalid duration number '{}{}'", number, if is_dotted { "." } else { "" }),
+            Span::from(&number_token)
+        ).with_help("Expected 128, 64, 64., 32, 32., 16, 16., 8, 8., 4, 4., 2, 2. or 1".to_string()))
+    }
+
+    fn parse_time_signature(&mut self) -> Result<Item, Diagnostic> {
+        let token: Token = self.current_token.clone();
+        self.advance();
+        let span: Span = Span::from(&token);
+        let signature: &str = token.value.trim_start_matches("#time").trim();
+        let (beats, unit): (&str, &str) = signature.split_once('/').unwrap_or((signature, ""));
+        match (beats.parse::<u8>(), unit.parse::<u8>()) {
+            (Ok(beats), Ok(unit)) if beats > 0 && Duration::new(unit, false).is_some() =>
+                Ok(Item::TimeSignature { beats, unit, span }),
+            _ => Err(Diagnostic::error(
+                DiagnosticCode::InvalidTimeSignature,
+                format!("Invalid time signature {}", signature),
+                span
+            ).with_help("Expected 1 to 255 beats of 1, 2, 4, 8, 16, 32, 64 or 128, like 3/4.".to_string()))
+        }
+    }
+
+    fn parse_command(&mut self) -> Result<Item, Diagnostic> {
+        let command_token: Token = self.current_token.clone();
+        let command_name: &str = &command_token.value.to_uppercase();
+        self.advance();
+        match command_name {
+            "O" => {
+                let octave: Spanned<u8> = self.parse_number()?;
+                Ok(Item::Octave { octave, span: self.span_from(&command_token) })
+            },
+            "V" => {
+                let volume: Spanned<u8> = self.parse_number()?;
+                Ok(Item::Volume { volume, span: self.span_from(&command_token) })
+            },
+            "M" => {
+                if self.current_token.token_type == TokenType::Name {
+                    let name: Spanned<String> = self.parse_name();
+                    return Ok(Item::NamedMacroCall { name, span: self.span_from(&command_token) });
+                }
+                let number: Spanned<u8> = self.parse_number()?;
+                Ok(Item::MacroCall { number, span: self.span_from(&command_token) })
+            },
+            "T" => {
+                let tempo: u8 = self.parse_number()?.value;
+                Ok(Item::Tempo { tempo, span: self.span_from(&command_token) })
+            },
+            "K" => {
+                let value: u8 = self.parse_number()?.value;
+                Ok(Item::Transpose { value, span: self.span_from(&command_token) })
+            },
+            "I" => {
+                let value: u8 = self.parse_number()?.value;
+                Ok(Item::Instrument { value, span: self.span_from(&command_token) })
+            },
+            "P" => {
+                let value: u8 = self.parse_number()?.value;
+                Ok(Item::Panning { value, span: self.span_from(&command_token) })
+            },
+            "&" => Ok(Item::Tie { span: Span::from(&command_token) }),
+            "S" => Ok(Item::Stop { span: Span::from(&command_token) }),
+            "R" | "R#" => {
+                let duration: Option<Duration> = self.parse_duration()?;
+                Ok(Item::Rest { duration, span: self.span_from(&command_token) })
+            },
+            _ => match Pitch::from_name(command_name) {
+                Some(pitch) => {
+                    let duration: Option<Duration> = self.parse_duration()?;
+                    Ok(Item::Note { pitch, duration, span: self.span_from(&command_token) })
+                },
+                None => Err(Diagnostic::error(
+                    DiagnosticCode::UnknownCommand,
+                    format!("Unexpected command called {}", command_name),
+                    Span::from(&command_token)
+                ))
+            }
+        }
+    }
+
+    /// Error for a `[` or `{` still open at the end of a channel or of the file.
+    fn unclosed_block_error(&self, start_token: &Token) -> Diagnostic {
+        let at_channel_end: bool = self.current_token.token_type == TokenType::Arobase;
+        let (code, message): (DiagnosticCode, &str) = match (start_token.token_type, at_channel_end) {
+            (TokenType::LeftParen, true) => (DiagnosticCode::UnclosedLoopAtChannelEnd, "Loop didn't close at the end of channel"),
+            (TokenType::LeftParen, false) => (DiagnosticCode::UnclosedLoopAtEndOfFile, "Loop didn't close at the end of file"),
+            (_, true) => (DiagnosticCode::UnclosedTransposition, "Transposition didn't close at the end of channel"),
+            (_, false) => (DiagnosticCode::UnclosedTransposition, "Transposition didn't close at the end of file")
+        };
+        let label: &str = match start_token.token_type {
+            TokenType::LeftParen => "loop starts here",
+            _ => "transposition starts here"
+        };
+        Diagnostic::error(code, message.to_string(), Span::from(&self.current_token))
+            .with_label(Span::from(start_token), label.to_string())
+    }
+
+    /// Items up to the `closing` token, which is consumed.
+    fn parse_block(&mut self, start_token: &Token, closing: TokenType) -> Result<Vec<Item>, Diagnostic> {
+        let mut items: Vec<Item> = Vec::new();
+        while self.current_token.token_type != closing {
+            if self.current_token.token_type == TokenType::Arobase {
+                return Err(self.unclosed_block_error(start_token));
+            }
+            let start_index: usize = self.current_index;
+            match self.parse_item() {
+                Ok(item) => items.push(item),
+                Err(diagnostic) => self.recover(diagnostic, start_index)
+            }
+            if self.is_end_of_file() {
+                return Err(self.unclosed_block_error(start_token));
+            }
+        }
+        self.advance();
+        Ok(items)
+    }
+
+    fn parse_loop(&mut self) -> Result<Item, Diagnostic> {
+        let start_token: Token = self.current_token.clone();
+        self.advance();
+        let times: u8 = self.parse_number().map(|number| number.value).unwrap_or_else(|diagnostic| {
+            self.errors.push(diagnostic);
+            1
+        });
+        let span: Span = self.span_from(&start_token);
+        let items: Vec<Item> = self.parse_block(&start_token, TokenType::RightParen)?;
+        Ok(Item::Loop { times, items, span })
+    }
+
+    fn parse_transposition(&mut self) -> Result<Item, Diagnostic> {
+        let start_token: Token = self.current_token.clone();
+        self.advance();
+        let span: Span = Span::from(&start_token);
+        let semitones: i8 = start_token.value[1..].parse::<i8>().unwrap_or_else(|_| {
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::InvalidNumber,
+                format!("Failed to convert number of semitones '{}'", &start_token.value[1..]),
+                span
+            ).with_help("Expected a number between -128 and 127.".to_string()));
+            0
+        });
+        let items: Vec<Item> = self.parse_block(&start_token, TokenType::TransposeEnd)?;
+        Ok(Item::Transposition { semitones: Spanned::new(semitones, span), items, span })
+    }
+
+    fn parse_item(&mut self) -> Result<Item, Diagnostic> {
+        match self.current_token.token_type {
+            TokenType::LessThan => {
+                let span: Span = Span::from(&self.current_token);
+                self.advance();
+                Ok(Item::OctaveDown { span })
+            },
+            TokenType::GreaterThan => {
+                let span: Span = Span::from(&self.current_token);
+                self.advance();
+                Ok(Item::OctaveUp { span })
+            },
+            TokenType::LeftParen => self.parse_loop(),
+            TokenType::TransposeStart => self.parse_transposition(),
+            TokenType::TimeSignature => self.parse_time_signature(),
+            TokenType::BarLine => {
+                let span: Span = Span::from(&self.current_token);
+                self.advance();
+                Ok(Item::BarLine { span })
+            },
+            TokenType::Command => self.parse_command(),
+            TokenType::Include => {
+                Err(Diagnostic::error(
+                    DiagnosticCode::UnexpectedToken,
+                    format!("Unexpanded include of {}", self.current_token.include_path()),
+                    Span::from(&self.current_token)
+                ).with_help("Includes are expanded when loading the file with an Includer.".to_string()))
+            },
+            TokenType::EndOfFile => {
+                Err(Diagnostic::error(
+                    DiagnosticCode::UnexpectedEndOfFile,
+                    "Unexpected end of file".to_string(),
+                    Span::from(&self.current_token)
+                ).with_help("Expected @, <, >, [, ], {, }, | or a command.".to_string()))
+            }
+            _ => {
+                Err(Diagnostic::error(
+                    DiagnosticCode::UnexpectedToken,
+                    format!("Unexpected token {}", self.current_token.value),
+                    Span::from(&self.current_token)
+                ).with_help("Expected @, <, >, [, ], {, }, | or a command.".to_string()))
+            }
+        }
+    }
+
+    /// Parses the whole song, recovering from errors to report as many as possible.
+    /// The song is returned along the errors so the compiler can check it too.
+    pub(crate) fn parse_recovering(&mut self) -> (Song, Vec<Diagnostic>) {
+        let mut sections: Vec<Section> = Vec::new();
+        if self.current_token.token_type != TokenType::Arobase {
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::MissingFirstHeader,
+                "The file do not start with an '@'".to_string(),
+                Span::from(&self.current_token)
+            ));
+            sections.push(Section { name: None, items: Vec::new(), span: Span::from(&self.current_token) });
+        }
+
+        while !self.is_end_of_file() {
+            if self.current_token.token_type == TokenType::Arobase {
+                let span: Span = Span::from(&self.current_token);
+                self.advance();
+                let name: Option<Spanned<String>> = match self.current_token.token_type {
+                    TokenType::Label => Some(self.parse_name()),
+                    _ => None
+                };
+                sections.push(Section { name, items: Vec::new(), span });
+                continue;
+            }
+            let start_index: usize = self.current_index;
+            match self.parse_item() {
+                Ok(item) => {
+                    if let Some(section) = sections.last_mut() {
+                        section.items.push(item);
+                    }
+                },
+                Err(diagnostic) => self.recover(diagnostic, start_index)
+            }
+        }
+
+        let macros: Vec<Section> = sections.split_off(self.channels.min(sections.len()));
+        let song: Song = Song {
+            channels: sections,
+            macros
+        };
+        (song, std::mem::take(&mut self.errors))
+    }
+
+    pub fn parse(&mut self) -> Result<Song, Vec<Diagnostic>> {
+        let (song, mut errors): (Song, Vec<Diagnostic>) = self.parse_recovering();
+        if errors.is_empty() {
+            return Ok(song);
+        }
+        errors.truncate(self.max_errors.unwrap_or(errors.len()));
+        Err(errors)
+    }
+}
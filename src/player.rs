@@ -0,0 +1,93 @@
+use std::io::{Error, Write};
+
+use crate::driver::{ChannelState, Driver, CHANNELS};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+/// Approximate rate of the reference driver synthesis loop on an 8MHz AVR.
+/// A driver tick lasts `tempo << 4` iterations of that loop.
+pub const SYNTHESIS_LOOP_RATE: f64 = 52000.0;
+const HIGH_LEVEL: u8 = 0xC0;
+const LOW_LEVEL: u8 = 0x40;
+
+fn frequency(channel: &ChannelState) -> f64 {
+    let midi_note: f64 = (12 * (channel.octave as i32 + 2) + channel.note as i32 - 1) as f64;
+    440.0 * 2f64.powf((midi_note - 69.0) / 12.0)
+}
+
+fn duty_cycle(channel: &ChannelState) -> f64 {
+    if channel.note == 0 || channel.stopped || channel.volume == 0 || channel.volume > 8 {
+        return 0.0;
+    }
+    0.5 / (1 << (channel.volume - 1)) as f64
+}
+
+/// Software synthesizer playing compiled µMML data like the reference AVR driver.
+pub struct Player {
+    driver: Driver,
+    phases: [f64; CHANNELS],
+    sample_rate: u32
+}
+
+impl Player {
+    pub fn new(data: Vec<u8>, sample_rate: u32) -> Result<Self, Error> {
+        Ok(Self {
+            driver: Driver::new(data)?,
+            phases: [0.0; CHANNELS],
+            sample_rate
+        })
+    }
+
+    fn samples_per_tick(&self) -> f64 {
+        ((self.driver.tempo() as u32) << 4) as f64 * self.sample_rate as f64 / SYNTHESIS_LOOP_RATE
+    }
+
+    /// Renders the song until every channel reached its end once, as 8-bit unsigned 1-bit samples.
+    pub fn render(&mut self, max_seconds: Option<u32>) -> Result<Vec<u8>, Error> {
+        let max_samples: Option<usize> = max_seconds.map(|seconds| seconds as usize * self.sample_rate as usize);
+        let mut samples: Vec<u8> = Vec::new();
+        let mut tick_samples: f64 = 0.0;
+        loop {
+            self.driver.tick()?;
+            if self.driver.is_finished() {
+                break;
+            }
+            tick_samples += self.samples_per_tick();
+            let channels: &[ChannelState] = self.driver.channels();
+            let phase_steps: Vec<f64> = channels.iter()
+                .map(|channel| frequency(channel) / self.sample_rate as f64)
+                .collect();
+            let duty_cycles: Vec<f64> = channels.iter().map(duty_cycle).collect();
+            while tick_samples >= 1.0 {
+                let index: usize = samples.len() % CHANNELS;
+                let high: bool = self.phases[index] < duty_cycles[index];
+                for (phase, phase_step) in self.phases.iter_mut().zip(&phase_steps) {
+                    *phase = (*phase + phase_step).fract();
+                }
+                samples.push(if high { HIGH_LEVEL } else { LOW_LEVEL });
+                tick_samples -= 1.0;
+                if max_samples.is_some_and(|max_samples| samples.len() >= max_samples) {
+                    return Ok(samples);
+                }
+            }
+        }
+        Ok(samples)
+    }
+}
+
+/// Writes 8-bit unsigned mono samples as a WAV file.
+pub fn write_wav<W: Write>(writer: &mut W, samples: &[u8], sample_rate: u32) -> Result<(), Error> {
+    let data_size: u32 = samples.len() as u32;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&8u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    writer.write_all(samples)
+}
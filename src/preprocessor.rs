@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCode, Span},
+    token::{Token, TokenType}
+};
+
+/// Most tokens the expansions of a song can produce, definitions using other definitions several times growing exponentially.
+pub const MAX_EXPANDED_TOKENS: usize = 1 << 18;
+
+/// A `$NAME = ...` constant, or a `$NAME($a, $b) = ...` snippet when it has parameters.
+#[derive(Debug, Clone)]
+struct Definition {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+    span: Span
+}
+
+/// Expands `$NAME` constants and snippets before parsing.
+/// Expanded tokens keep the position of the definition or argument they come from.
+#[derive(Debug, Default)]
+pub struct Preprocessor {
+    definitions: HashMap<String, Definition>,
+    /// Names being expanded, to detect recursive definitions.
+    stack: Vec<String>,
+    /// Use of the outermost definition being expanded, pointed at by the errors found inside definitions.
+    use_site: Option<Span>,
+    expanded_tokens: usize,
+    errors: Vec<Diagnostic>
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an error, pointing at the use expanding the definition it is found in.
+    fn error(&mut self, diagnostic: Diagnostic) {
+        let diagnostic: Diagnostic = match self.use_site {
+            Some(use_site) if !self.stack.is_empty() => diagnostic.with_label(use_site, "expanded from here".to_string()),
+            _ => diagnostic
+        };
+        self.errors.push(diagnostic);
+    }
+
+    /// Splits the `(...)` group starting at `start` into its comma separated arguments.
+    /// Returns the arguments and the index following the `)`.
+    fn parse_arguments(&mut self, tokens: &[Token], start: usize) -> (Vec<Vec<Token>>, usize) {
+        let mut arguments: Vec<Vec<Token>> = vec![Vec::new()];
+        let mut depth: usize = 0;
+        let mut index: usize = start + 1;
+        while let Some(token) = tokens.get(index) {
+            index += 1;
+            match token.token_type {
+                TokenType::ArgumentsStart => depth += 1,
+                TokenType::ArgumentsEnd if depth == 0 => return (arguments, index),
+                TokenType::ArgumentsEnd => depth -= 1,
+                TokenType::Comma if depth == 0 => {
+                    arguments.push(Vec::new());
+                    continue;
+                },
+                TokenType::EndOfFile => break,
+                _ => {}
+            }
+            if let Some(argument) = arguments.last_mut() {
+                argument.push(token.clone());
+            }
+        }
+        self.error(Diagnostic::error(
+            DiagnosticCode::UnexpectedEndOfFile,
+            "Arguments are not closed".to_string(),
+            Span::from(&tokens[start])
+        ).with_help("Expected a ')'.".to_string()));
+        (arguments, index.min(tokens.len()))
+    }
+
+    /// Records the definition whose `=` is at `equals`. Returns the index following its body.
+    fn define(&mut self, tokens: &[Token], name_token: &Token, arguments: Vec<Vec<Token>>, equals: usize) -> usize {
+        let equals_token: &Token = &tokens[equals];
+        let body: Vec<Token> = tokens[equals + 1..].iter()
+            .take_while(|token| token.line == equals_token.line && token.file == equals_token.file && !token.is_end_of_file())
+            .cloned()
+            .collect();
+        let end: usize = equals + 1 + body.len();
+        let mut parameters: Vec<String> = Vec::new();
+        for argument in arguments.iter().filter(|argument| !argument.is_empty()) {
+            match argument.as_slice() {
+                [parameter] if parameter.token_type == TokenType::Variable => parameters.push(parameter.value.clone()),
+                _ => self.errors.push(Diagnostic::error(
+                    DiagnosticCode::InvalidDefinition,
+                    format!("Invalid parameter of {}", name_token.value),
+                    Span::from(&argument[0])
+                ).with_help("Parameters are names like $note.".to_string()))
+            }
+        }
+        let span: Span = Span::from(name_token);
+        if let Some(definition) = self.definitions.get(&name_token.value) {
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::InvalidDefinition,
+                format!("{} is defined more than once", name_token.value),
+                span
+            ).with_label(definition.span, "first defined here".to_string()));
+            return end;
+        }
+        self.definitions.insert(name_token.value.clone(), Definition { parameters, body, span });
+        end
+    }
+
+    /// Expands a use of a definition, its arguments replacing its parameters.
+    fn expand(&mut self, name_token: &Token, arguments: Vec<Vec<Token>>) -> Vec<Token> {
+        if self.expanded_tokens > MAX_EXPANDED_TOKENS {
+            return Vec::new();
+        }
+        if self.stack.is_empty() {
+            self.use_site = Some(Span::from(name_token));
+        }
+        let Some(definition) = self.definitions.get(&name_token.value).cloned() else {
+            self.error(Diagnostic::error(
+                DiagnosticCode::UnknownDefinition,
+                format!("Unknown name {}", name_token.value),
+                Span::from(name_token)
+            ).with_help(format!("Define it before using it with '{} = ...'.", name_token.value)));
+            return Vec::new();
+        };
+        if self.stack.contains(&name_token.value) {
+            self.error(Diagnostic::error(
+                DiagnosticCode::InvalidDefinition,
+                format!("{} expands to itself", name_token.value),
+                Span::from(name_token)
+            ).with_label(definition.span, "defined here".to_string()));
+            return Vec::new();
+        }
+        let arguments: Vec<Vec<Token>> = arguments.into_iter().filter(|argument| !argument.is_empty()).collect();
+        if arguments.len() != definition.parameters.len() {
+            self.error(Diagnostic::error(
+                DiagnosticCode::InvalidDefinition,
+                format!("{} takes {} arguments but {} were given", name_token.value, definition.parameters.len(), arguments.len()),
+                Span::from(name_token)
+            ).with_label(definition.span, "defined here".to_string()));
+            return Vec::new();
+        }
+        let mut tokens: Vec<Token> = Vec::new();
+        for token in definition.body {
+            match definition.parameters.iter().position(|parameter| *parameter == token.value) {
+                Some(index) => tokens.extend(arguments[index].iter().cloned()),
+                None => tokens.push(token)
+            }
+        }
+        self.expanded_tokens += tokens.len();
+        if self.expanded_tokens > MAX_EXPANDED_TOKENS {
+            self.error(Diagnostic::error(
+                DiagnosticCode::InvalidDefinition,
+                format!("Expanding {} goes over the limit of {} expanded tokens", name_token.value, MAX_EXPANDED_TOKENS),
+                Span::from(name_token)
+            ).with_help("Definitions using other definitions several times grow exponentially.".to_string()));
+            return Vec::new();
+        }
+        self.stack.push(name_token.value.clone());
+        let tokens: Vec<Token> = self.process(&tokens, false);
+        self.stack.pop();
+        tokens
+    }
+
+    fn process(&mut self, tokens: &[Token], allow_definitions: bool) -> Vec<Token> {
+        let mut result: Vec<Token> = Vec::new();
+        let mut index: usize = 0;
+        while let Some(token) = tokens.get(index) {
+            if token.token_type != TokenType::Variable {
+                result.push(token.clone());
+                index += 1;
+                continue;
+            }
+            let (arguments, next_index): (Vec<Vec<Token>>, usize) = match tokens.get(index + 1) {
+                Some(next_token) if next_token.token_type == TokenType::ArgumentsStart => self.parse_arguments(tokens, index + 1),
+                _ => (Vec::new(), index + 1)
+            };
+            index = match tokens.get(next_index) {
+                Some(next_token) if allow_definitions && next_token.token_type == TokenType::Equals =>
+                    self.define(tokens, token, arguments, next_index),
+                _ => {
+                    result.append(&mut self.expand(token, arguments));
+                    next_index
+                }
+            };
+        }
+        result
+    }
+
+    /// Removes the definitions and expands their uses.
+    /// The tokens are returned along the errors so the parser can check them too.
+    pub fn preprocess(&mut self, tokens: &[Token]) -> (Vec<Token>, Vec<Diagnostic>) {
+        self.definitions.clear();
+        self.expanded_tokens = 0;
+        let tokens: Vec<Token> = self.process(tokens, true);
+        (tokens, std::mem::take(&mut self.errors))
+    }
+}
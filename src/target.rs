@@ -0,0 +1,131 @@
+use std::{fmt::{Display, Formatter}, io::{Error, ErrorKind}, path::Path};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtendedCommand {
+    Transpose,
+    Instrument,
+    Tie,
+    Panning,
+    Stop
+}
+
+impl Display for ExtendedCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transpose => write!(f, "Transpose"),
+            Self::Instrument => write!(f, "Instrument"),
+            Self::Tie => write!(f, "Tie"),
+            Self::Panning => write!(f, "Panning"),
+            Self::Stop => write!(f, "Stop")
+        }
+    }
+}
+
+pub const EXTENDED_COMMANDS: [ExtendedCommand; 5] = [
+    ExtendedCommand::Transpose,
+    ExtendedCommand::Instrument,
+    ExtendedCommand::Tie,
+    ExtendedCommand::Panning,
+    ExtendedCommand::Stop
+];
+
+/// Most channels a driver can have, every channel needing one of the 254 headers of a file.
+pub const MAX_CHANNELS: u8 = 254;
+
+/// Profile of a µMML driver: the commands it supports and its limits.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Target {
+    pub name: String,
+    /// Extended commands the driver supports. `None` when unknown, they are then compiled with a warning.
+    pub extended_commands: Option<Vec<ExtendedCommand>>,
+    pub min_octave: u8,
+    pub max_octave: u8,
+    pub channels: u8,
+    /// Macros a channel can be inside at once, 1 when macros can't call macros.
+    pub max_macro_depth: u8,
+    pub max_data_size: usize
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self {
+            name: String::from("generic"),
+            extended_commands: None,
+            min_octave: 1,
+            max_octave: 5,
+            channels: 4,
+            max_macro_depth: 1,
+            max_data_size: u16::MAX as usize
+        }
+    }
+}
+
+impl Target {
+    /// The stock protodome driver, without any extended command.
+    pub fn protodome_avr() -> Self {
+        Self {
+            name: String::from("protodome-avr"),
+            extended_commands: Some(Vec::new()),
+            ..Self::default()
+        }
+    }
+
+    /// A driver supporting every extended command, with a stack of return addresses for macros calling macros.
+    pub fn extended() -> Self {
+        Self {
+            name: String::from("extended"),
+            extended_commands: Some(EXTENDED_COMMANDS.to_vec()),
+            max_macro_depth: 4,
+            ..Self::default()
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "generic" => Some(Self::default()),
+            "protodome-avr" => Some(Self::protodome_avr()),
+            "extended" => Some(Self::extended()),
+            _ => None
+        }
+    }
+
+    /// Parses a custom profile, missing fields take the generic profile values.
+    pub fn from_toml(source: &str) -> Result<Self, Error> {
+        let target: Target = toml::from_str(source).map_err(|err| Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid target profile: {}", err.message())
+        ))?;
+        if target.min_octave == 0 || target.min_octave > target.max_octave || target.max_octave > 16 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid target profile octave range {}-{}. Expected octaves between 1 and 16.", target.min_octave, target.max_octave)
+            ));
+        }
+        if target.max_data_size > u16::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid target profile maximum data size {}. Header offsets are 16-bit.", target.max_data_size)
+            ));
+        }
+        if target.channels == 0 || target.channels > MAX_CHANNELS {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid target profile channel count {}. Expected 1 to {} channels.", target.channels, MAX_CHANNELS)
+            ));
+        }
+        Ok(target)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Whether the driver supports the command, `None` if unknown.
+    pub fn supports(&self, command: ExtendedCommand) -> Option<bool> {
+        self.extended_commands.as_ref().map(|commands| commands.contains(&command))
+    }
+}
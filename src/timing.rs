@@ -0,0 +1,355 @@
+use std::{fmt::{Display, Formatter}, io::{Error, ErrorKind}};
+
+use crate::{
+    ast::{Duration, Song},
+    diagnostic::{Diagnostic, DiagnosticCode, Span},
+    target::Target
+};
+
+/// Driver ticks of a whole note.
+pub const TICKS_PER_WHOLE_NOTE: u64 = 128;
+
+/// Name of a channel, from `A` to `Z` then numbered from 27.
+pub fn channel_name(index: usize) -> String {
+    match index < 26 {
+        true => ((b'A' + index as u8) as char).to_string(),
+        false => (index + 1).to_string()
+    }
+}
+
+/// Time signature giving the bars of a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub beats: u8,
+    /// Duration of a beat, 4 for quarter notes.
+    pub unit: u8
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self { beats: 4, unit: 4 }
+    }
+}
+
+impl TimeSignature {
+    fn ticks_per_beat(&self) -> u64 {
+        (TICKS_PER_WHOLE_NOTE / self.unit as u64).max(1)
+    }
+
+    fn ticks_per_bar(&self) -> u64 {
+        self.ticks_per_beat() * self.beats.max(1) as u64
+    }
+}
+
+/// Position in a song, starting at bar 1, beat 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarPosition {
+    pub bar: u64,
+    pub beat: u64,
+    /// Ticks after the beat.
+    pub tick: u64
+}
+
+impl BarPosition {
+    pub fn from_ticks(ticks: u64, time_signature: TimeSignature) -> Self {
+        let ticks_per_beat: u64 = time_signature.ticks_per_beat();
+        let ticks_per_bar: u64 = time_signature.ticks_per_bar();
+        Self {
+            bar: ticks / ticks_per_bar + 1,
+            beat: ticks % ticks_per_bar / ticks_per_beat + 1,
+            tick: ticks % ticks_per_beat
+        }
+    }
+
+    /// Position in a channel changing time signature, `changes` giving each signature with the tick it starts at.
+    /// The channel starts in 4/4 and a change in the middle of a bar starts a new bar.
+    pub fn from_ticks_with_changes(ticks: u64, changes: &[(u64, TimeSignature)]) -> Self {
+        let mut bars: u64 = 0;
+        let mut start: u64 = 0;
+        let mut time_signature: TimeSignature = TimeSignature::default();
+        for &(tick, next_time_signature) in changes.iter().take_while(|(tick, _)| *tick <= ticks) {
+            bars += (tick - start).div_ceil(time_signature.ticks_per_bar());
+            start = tick;
+            time_signature = next_time_signature;
+        }
+        let position: BarPosition = Self::from_ticks(ticks - start, time_signature);
+        Self { bar: position.bar + bars, ..position }
+    }
+}
+
+impl Display for BarPosition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bar {}, beat {}", self.bar, self.beat)?;
+        if self.tick > 0 {
+            write!(f, " (+{} ticks)", self.tick)?;
+        }
+        Ok(())
+    }
+}
+
+/// Length of a channel, loops and macro calls expanded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelTiming {
+    pub ticks: u64,
+    /// Whether the channel ends with an `S` command instead of starting again.
+    pub stopped: bool
+}
+
+impl ChannelTiming {
+    /// Position right after the last note of the channel, given its time signature changes.
+    pub fn end(&self, time_signatures: &[(u64, TimeSignature)]) -> BarPosition {
+        BarPosition::from_ticks_with_changes(self.ticks, time_signatures)
+    }
+}
+
+struct TimingReader<'a> {
+    data: &'a [u8],
+    channels: usize,
+    max_macro_depth: usize
+}
+
+impl TimingReader<'_> {
+    fn read_byte(&self, address: usize) -> Result<u8, Error> {
+        self.data.get(address).copied().ok_or_else(|| Error::new(
+            ErrorKind::InvalidData,
+            format!("Address 0x{:04X} is out of the data bounds.", address)
+        ))
+    }
+
+    fn read_header(&self, index: usize) -> Result<usize, Error> {
+        Ok(((self.read_byte(index * 2)? as usize) << 8) | self.read_byte(index * 2 + 1)? as usize)
+    }
+
+    /// Ticks played from `address` to the end of its section or loop body, `depth` macros deep, and whether a stop was reached.
+    fn read_ticks(&self, address: &mut usize, depth: usize) -> Result<(u64, bool), Error> {
+        let mut ticks: u64 = 0;
+        loop {
+            let byte: u8 = self.read_byte(*address)?;
+            match byte {
+                0x00..=0xCF => {
+                    ticks += Duration::nibble_ticks(byte & 0x0F) as u64;
+                    *address += 1;
+                },
+                0xD0..=0xEF | 0xF6 => *address += 1,
+                0xF3 | 0xF4 | 0xF5 | 0xF7 => *address += 2,
+                0xF0 => {
+                    let times: u8 = self.read_byte(*address + 1)?;
+                    *address += 2;
+                    let (body_ticks, stopped): (u64, bool) = self.read_ticks(address, depth)?;
+                    if stopped {
+                        return Ok((ticks + body_ticks, true));
+                    }
+                    ticks += body_ticks * loop_times(times);
+                },
+                0xF1 => {
+                    *address += 1;
+                    return Ok((ticks, false));
+                },
+                0xF2 if depth >= self.max_macro_depth => return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Macro call at 0x{:04X} goes past the {} macros a channel can be inside.", *address, self.max_macro_depth)
+                )),
+                0xF2 => {
+                    let macro_id: usize = self.read_byte(*address + 1)? as usize;
+                    let mut macro_address: usize = self.read_header(self.channels + macro_id)?;
+                    *address += 2;
+                    let (macro_ticks, stopped): (u64, bool) = self.read_ticks(&mut macro_address, depth + 1)?;
+                    ticks += macro_ticks;
+                    if stopped {
+                        return Ok((ticks, true));
+                    }
+                },
+                0xF8 => return Ok((ticks, true)),
+                0xFF => return Ok((ticks, false)),
+                _ => return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unknown command 0x{:02X} at 0x{:04X}.", byte, *address)
+                ))
+            }
+        }
+    }
+}
+
+/// Computes the length of each channel of data compiled for the target.
+pub fn channel_timings(data: &[u8], target: &Target) -> Result<Vec<ChannelTiming>, Error> {
+    let reader: TimingReader = TimingReader {
+        data,
+        channels: target.channels as usize,
+        max_macro_depth: target.max_macro_depth as usize
+    };
+    (0..reader.channels)
+        .map(|channel| {
+            let mut address: usize = reader.read_header(channel)?;
+            let (ticks, stopped): (u64, bool) = reader.read_ticks(&mut address, 0)?;
+            Ok(ChannelTiming { ticks, stopped })
+        })
+        .collect()
+}
+
+/// Warns about the channels ending before the longest one. Silent channels are not checked.
+pub fn check_timing(song: &Song, timings: &[ChannelTiming], time_signatures: &[Vec<(u64, TimeSignature)>]) -> Vec<Diagnostic> {
+    let Some((longest, longest_timing)) = timings.iter().enumerate().max_by_key(|(_, timing)| timing.ticks) else {
+        return Vec::new();
+    };
+    let span = |channel: usize| song.channels.get(channel).map(|section| section.span).unwrap_or_default();
+    let end = |channel: usize, timing: &ChannelTiming| timing.end(time_signatures.get(channel).map_or(&[], Vec::as_slice));
+    let longest_span: Span = span(longest);
+    timings.iter()
+        .enumerate()
+        .filter(|(_, timing)| timing.ticks > 0 && timing.ticks < longest_timing.ticks)
+        .map(|(channel, timing)| Diagnostic::warning(
+            DiagnosticCode::UnsynchronizedChannels,
+            format!(
+                "Channel {} ends at {} but channel {} ends at {}",
+                channel_name(channel), end(channel, timing),
+                channel_name(longest), end(longest, longest_timing)
+            ),
+            span(channel)
+        ).with_label(longest_span, "longest channel".to_string()).with_help(format!(
+            "Channel {} is {} ticks shorter, a whole note lasting {} ticks.",
+            channel_name(channel), longest_timing.ticks - timing.ticks, TICKS_PER_WHOLE_NOTE
+        )))
+        .collect()
+}
+
+/// What the compiler records of a section to check its bars.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Timed {
+    Ticks(u64),
+    BarLine(Span),
+    TimeSignature { beats: u8, unit: u8 },
+    Loop { times: u8, body: Vec<Timed> },
+    MacroCall(u8)
+}
+
+fn has_bar_lines(timeline: &[Timed]) -> bool {
+    timeline.iter().any(|timed| match timed {
+        Timed::BarLine(_) => true,
+        Timed::Loop { body, .. } => has_bar_lines(body),
+        _ => false
+    })
+}
+
+/// The driver plays a loop of 0 as a loop of 256.
+fn loop_times(times: u8) -> u64 {
+    if times == 0 { 256 } else { times as u64 }
+}
+
+/// Ticks of a timeline, bar lines ignored.
+fn timeline_ticks(timeline: &[Timed], macro_ticks: &[u64]) -> u64 {
+    timeline.iter()
+        .map(|timed| match timed {
+            Timed::Ticks(ticks) => *ticks,
+            Timed::Loop { times, body } => timeline_ticks(body, macro_ticks).saturating_mul(loop_times(*times)),
+            Timed::MacroCall(macro_id) => macro_ticks.get(*macro_id as usize).copied().unwrap_or(0),
+            _ => 0
+        })
+        .fold(0, u64::saturating_add)
+}
+
+/// Checks the length of each bar, in the order of the source code like the compiler.
+pub(crate) struct BarChecker<'a> {
+    macro_ticks: &'a [u64],
+    section_name: String,
+    beats: u8,
+    unit: u8,
+    bar: u64,
+    ticks: u64,
+    errors: Vec<Diagnostic>
+}
+
+impl<'a> BarChecker<'a> {
+    pub(crate) fn new(macro_ticks: &'a [u64]) -> Self {
+        Self {
+            macro_ticks,
+            section_name: String::new(),
+            beats: 4,
+            unit: 4,
+            bar: 1,
+            ticks: 0,
+            errors: Vec::new()
+        }
+    }
+
+    fn ticks_per_bar(&self) -> u64 {
+        TICKS_PER_WHOLE_NOTE * self.beats as u64 / self.unit as u64
+    }
+
+    fn check_bar_line(&mut self, span: Span) {
+        let ticks_per_bar: u64 = self.ticks_per_bar();
+        if self.ticks != ticks_per_bar && !self.errors.iter().any(|error| error.span == span) {
+            self.errors.push(Diagnostic::error(
+                DiagnosticCode::InvalidBarLength,
+                format!("Bar {} of {} lasts {} ticks instead of {}", self.bar, self.section_name, self.ticks, ticks_per_bar),
+                span
+            ).with_help(format!(
+                "Bars of {}/{} last {} ticks, a whole note lasting {} ticks.",
+                self.beats, self.unit, ticks_per_bar, TICKS_PER_WHOLE_NOTE
+            )));
+        }
+        self.bar += 1;
+        self.ticks = 0;
+    }
+
+    fn check_timeline(&mut self, timeline: &[Timed]) {
+        for timed in timeline {
+            match timed {
+                Timed::Ticks(ticks) => self.ticks += ticks,
+                Timed::BarLine(span) => self.check_bar_line(*span),
+                Timed::TimeSignature { beats, unit } => {
+                    self.beats = *beats;
+                    self.unit = *unit;
+                },
+                Timed::MacroCall(macro_id) => self.ticks += self.macro_ticks.get(*macro_id as usize).copied().unwrap_or(0),
+                Timed::Loop { times, body } if !has_bar_lines(body) =>
+                    self.ticks += timeline_ticks(body, self.macro_ticks) * loop_times(*times),
+                Timed::Loop { times, body } => {
+                    // From the second repetition, each one starts and ends like the previous one.
+                    let repetitions: u64 = loop_times(*times);
+                    self.check_timeline(body);
+                    if repetitions > 1 {
+                        let first_bar: u64 = self.bar;
+                        self.check_timeline(body);
+                        self.bar += (self.bar - first_bar) * (repetitions - 2);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks the bars of a section, starting a new bar in 4/4.
+    pub(crate) fn check_section(&mut self, section_name: String, timeline: &[Timed]) {
+        self.section_name = section_name;
+        self.beats = 4;
+        self.unit = 4;
+        self.bar = 1;
+        self.ticks = 0;
+        self.check_timeline(timeline);
+    }
+
+    pub(crate) fn finish(self) -> Vec<Diagnostic> {
+        self.errors
+    }
+}
+
+/// Time signature changes of the top level of a timeline, with the tick they start at.
+pub(crate) fn time_signature_changes(timeline: &[Timed], macro_ticks: &[u64]) -> Vec<(u64, TimeSignature)> {
+    let mut changes: Vec<(u64, TimeSignature)> = Vec::new();
+    let mut ticks: u64 = 0;
+    for timed in timeline {
+        match timed {
+            Timed::TimeSignature { beats, unit } => changes.push((ticks, TimeSignature { beats: *beats, unit: *unit })),
+            _ => ticks = ticks.saturating_add(timeline_ticks(std::slice::from_ref(timed), macro_ticks))
+        }
+    }
+    changes
+}
+
+/// Ticks of each macro, to count macro calls. Each pass adds the macros called one level deeper, up to `max_depth`.
+pub(crate) fn macro_ticks(macro_timelines: &[Vec<Timed>], max_depth: u8) -> Vec<u64> {
+    let mut ticks: Vec<u64> = Vec::new();
+    for _ in 0..max_depth.max(1) {
+        ticks = macro_timelines.iter().map(|timeline| timeline_ticks(timeline, &ticks)).collect();
+    }
+    ticks
+}
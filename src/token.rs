@@ -1,3 +1,5 @@
+use std::fmt::{Display, Formatter};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenType {
     Command,
@@ -8,6 +10,29 @@ pub enum TokenType {
     Dot,
     Arobase,
     Number,
+    /// Name of a section, written `name:` right after its `@`.
+    Label,
+    /// Name of a macro called by `M`, written `(name)`.
+    Name,
+    /// `#include "path"` directive, expanded by the includer.
+    Include,
+    /// `$NAME` constant or snippet, expanded by the preprocessor.
+    Variable,
+    Equals,
+    Comma,
+    /// `(` right after a `$NAME`, starting its parameters or arguments.
+    ArgumentsStart,
+    ArgumentsEnd,
+    /// `{` with its number of semitones, like `{-3`.
+    TransposeStart,
+    TransposeEnd,
+    BarLine,
+    /// `#time 3/4` directive.
+    TimeSignature,
+    /// `%` comment, only kept when the lexer keeps trivia.
+    Comment,
+    /// Spaces and line breaks, only kept when the lexer keeps trivia.
+    Whitespace,
     EndOfFile
 }
 
@@ -16,35 +41,52 @@ pub struct Token {
     pub value: String,
     pub token_type: TokenType,
     pub line: usize,
-    pub column: usize
+    pub column: usize,
+    pub offset: usize,
+    /// Index of the source file, 0 being the compiled file.
+    pub file: usize
 }
 
 impl Token {
-    pub fn new(value: String, token_type: TokenType, line: usize, column: usize) -> Self {
+    pub fn new(value: String, token_type: TokenType, line: usize, column: usize, offset: usize) -> Self {
         Self{
             value,
             token_type,
             line,
-            column
+            column,
+            offset,
+            file: 0
         } 
     }
 
-    pub fn empty(line: usize, column: usize) -> Self {
+    pub fn empty(line: usize, column: usize, offset: usize) -> Self {
         Self {
             value: String::new(),
             token_type: TokenType::EndOfFile,
             line,
-            column
+            column,
+            offset,
+            file: 0
         }
     }
 
+    /// Path written in an include directive.
+    pub fn include_path(&self) -> &str {
+        self.value.trim_start_matches("#include").trim().trim_matches('"')
+    }
+
+    /// Whether the token is a comment or whitespace, ignored by the compiler.
+    pub fn is_trivia(&self) -> bool {
+        matches!(self.token_type, TokenType::Comment | TokenType::Whitespace)
+    }
+
     pub fn is_end_of_file(&self) -> bool {
         self.token_type == TokenType::EndOfFile
     }
 }
 
-impl ToString for Token {
-    fn to_string(&self) -> String {
-        format!("Token \"{}\" of type {:#?} at line {}, column {}", self.value, self.token_type, self.line, self.column)
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Token \"{}\" of type {:#?} at line {}, column {}", self.value, self.token_type, self.line, self.column)
     }
 }
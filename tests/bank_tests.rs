@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+use mmml_compiler::{
+    bank::{build_bank, identifier, song_name, Bank, BankSong, Manifest},
+    compiler::Compiler,
+    driver::{ChannelState, Driver},
+    lexer::Lexer
+};
+
+fn compile(source: &str) -> Vec<u8> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile().unwrap()
+}
+
+/// Notes played by each channel, tick by tick.
+fn play(data: Vec<u8>) -> Vec<Vec<(u8, u8, u8, u16)>> {
+    let mut driver: Driver = Driver::new(data).unwrap();
+    let mut ticks: Vec<Vec<(u8, u8, u8, u16)>> = Vec::new();
+    while !driver.is_finished() {
+        driver.tick().unwrap();
+        ticks.push(driver.channels().iter()
+            .map(|channel: &ChannelState| (channel.note, channel.octave, channel.volume, channel.remaining_ticks))
+            .collect());
+    }
+    ticks
+}
+
+#[test]
+fn test_bank() {
+    let songs: Vec<(String, Vec<u8>)> = vec![
+        (String::from("TITLE"), compile("@ o4 c4 m1 @ r1 @ r1 @ r1 @ c8 d8 e8 f8")),
+        (String::from("LEVEL"), compile("@ e4 m2 m1 @ r1 @ r1 @ r1 @ g4 @ c8 d8 e8 f8")),
+        (String::from("BOSS"), compile("@ [2 m1 ] @ r1 @ r1 @ r1 @ g4"))
+    ];
+    let bank: Bank = build_bank(&songs, 4).unwrap();
+    assert_eq!(bank.shared_macros, 2);
+    assert_eq!(bank.bytes_saved, 5 + 2);
+    assert_eq!(&bank.data[..5], &[3, 0x00, 0x1E, 0x00, 0x16]);
+    assert_eq!(&bank.data[13..30], b"TITLE\0LEVEL\0BOSS\0");
+    assert_eq!(bank.songs[0], BankSong { name: String::from("TITLE"), offset: 0x1E, length: 22 });
+    let mut previous_end: usize = 0x1E;
+    for (song, (_, data)) in bank.songs.iter().zip(songs) {
+        assert_eq!(song.offset, previous_end);
+        previous_end = song.offset + song.length;
+        assert_eq!(play(bank.data[song.offset..].to_vec()), play(data));
+    }
+    assert_eq!(bank.shared_bytes, 5 + 2);
+    assert_eq!(bank.data.len(), previous_end + bank.shared_bytes);
+}
+
+#[test]
+fn test_bank_errors() {
+    let data: Vec<u8> = compile("@ c4 @ r1 @ r1 @ r1");
+    assert!(build_bank(&[], 4).is_err());
+    assert!(build_bank(&[(String::from("SONG"), data.clone()), (String::from("SONG"), data.clone())], 4).is_err());
+    assert!(build_bank(&[(String::from("boss-1"), data.clone()), (String::from("Boss 1"), data)], 4).is_err());
+}
+
+#[test]
+fn test_manifest() {
+    let manifest: Manifest = Manifest::from_toml(
+        "name = \"MUSIC\"\n[[songs]]\npath = \"title.mmml\"\n[[songs]]\nname = \"BOSS\"\npath = \"songs/boss-1.mmml\"\n"
+    ).unwrap();
+    assert_eq!(manifest.name.as_deref(), Some("MUSIC"));
+    assert_eq!(manifest.songs[1].name.as_deref(), Some("BOSS"));
+    assert_eq!(manifest.songs[1].path, PathBuf::from("songs/boss-1.mmml"));
+    assert!(Manifest::from_toml("[[songs]]\nfile = \"title.mmml\"\n").is_err());
+    assert_eq!(song_name(Path::new("songs/boss-1.mmml")), "BOSS_1");
+    assert_eq!(song_name(Path::new("1up.mmml")), "_1UP");
+    assert_eq!(identifier("Title screen"), "TITLE_SCREEN");
+    assert_eq!(identifier(""), "_");
+}
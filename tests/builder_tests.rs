@@ -0,0 +1,99 @@
+use mmml_compiler::{
+    ast::{Duration, Pitch},
+    builder::{BuildError, MacroRef, SequenceBuilder, SongBuilder},
+    compiler::Compiler,
+    lexer::Lexer,
+    target::Target
+};
+
+#[test]
+fn test_build() {
+    let mut song: SongBuilder = SongBuilder::default();
+    let arpeggio: MacroRef = song.add_macro(|m| {
+        m.note(Pitch::C, Duration::SIXTEENTH).note(Pitch::E, Duration::SIXTEENTH).note(Pitch::G, Duration::SIXTEENTH);
+    });
+    song.channel(|c| {
+        c.tempo(120).octave(3).volume(8)
+            .note(Pitch::FSharp, Duration::EIGHTH.dotted())
+            .loop_n(4, |c| {
+                c.call(arpeggio).octave_up();
+            })
+            .rest(Duration::QUARTER);
+    });
+    song.channel(|c| {
+        c.octave(2).note(Pitch::A, Duration::WHOLE);
+    });
+
+    let source: String = song.to_source().unwrap();
+    assert_eq!(source, "@ t120 o3 v8 f#8. [4 m1 > ] r4\n@ o2 a1\n@ r1\n@ r1\n@ c16 e16 g16\n");
+
+    let mut lexer: Lexer = Lexer::new(source);
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    assert_eq!(song.compile().unwrap(), compiler.compile().unwrap());
+}
+
+#[test]
+fn test_invalid_states() {
+    let mut song: SongBuilder = SongBuilder::default();
+    song.channel(|c| {
+        c.octave(6);
+    });
+    assert_eq!(song.build().unwrap_err(), BuildError::InvalidOctave { octave: 6, min_octave: 1, max_octave: 5 });
+
+    let mut song: SongBuilder = SongBuilder::default();
+    song.channel(|c| {
+        c.note(Pitch::C, Duration::WHOLE.dotted());
+    });
+    assert_eq!(song.build().unwrap_err(), BuildError::InvalidDuration(Duration::WHOLE.dotted()));
+
+    let mut song: SongBuilder = SongBuilder::default();
+    song.channel(|c| {
+        c.volume(9).loop_n(0, |c| {
+            c.rest(Duration::HALF);
+        });
+    });
+    assert_eq!(song.build().unwrap_err(), BuildError::InvalidVolume(9));
+
+    let mut song: SongBuilder = SongBuilder::default();
+    song.channel(|c| {
+        c.octave(5).octave_up();
+    });
+    assert_eq!(song.build().unwrap_err(), BuildError::InvalidOctave { octave: 6, min_octave: 1, max_octave: 5 });
+
+    let mut song: SongBuilder = SongBuilder::default();
+    song.channel(|c| {
+        c.octave_up().octave_up();
+    });
+    assert_eq!(song.build().unwrap_err(), BuildError::InvalidOctave { octave: 6, min_octave: 1, max_octave: 5 });
+
+    let mut song: SongBuilder = SongBuilder::default();
+    song.channel(|c| {
+        c.octave(2).loop_n(2, |c| {
+            c.octave_down();
+        }).octave_down();
+    });
+    assert_eq!(song.build().unwrap_err(), BuildError::InvalidOctave { octave: 0, min_octave: 1, max_octave: 5 });
+
+    let mut song: SongBuilder = SongBuilder::new(Target::protodome_avr());
+    song.channel(|c| {
+        c.octave(5).tie().stop();
+    });
+    assert!(song.build().is_ok());
+    match song.compile().unwrap_err() {
+        BuildError::Compile(diagnostics) => assert_eq!(diagnostics.len(), 2),
+        error => panic!("Unexpected error {}", error)
+    }
+
+    let mut other_song: SongBuilder = SongBuilder::default();
+    let other_macro: MacroRef = other_song.add_macro(|m| {
+        m.rest(Duration::QUARTER);
+    });
+    let mut song: SongBuilder = SongBuilder::default();
+    song.add_macro(|m| {
+        m.rest(Duration::HALF);
+    });
+    song.channel(|c| {
+        c.call(other_macro);
+    });
+    assert_eq!(song.build().unwrap_err(), BuildError::UnknownMacro(1));
+}
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use mmml_compiler::{compiler::Compiler, lexer::Lexer};
+use mmml_compiler::{compiler::Compiler, diagnostic::{Diagnostic, DiagnosticCode}, lexer::Lexer};
 
 #[test]
 fn protodome_test() {
@@ -391,3 +391,18 @@ fn protodome_test() {
     ];
     assert_eq!(bytes, expected_bytes);
 }
+
+#[test]
+fn test_macro_numbers() {
+    let compile = |source: &str| -> Result<Vec<u8>, Vec<Diagnostic>> {
+        let mut lexer: Lexer = Lexer::new(source.into());
+        let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+        compiler.compile()
+    };
+    assert!(compile("@ m1 c4 @ r1 @ r1 @ r1 @ c4").is_ok());
+    let errors: Vec<Diagnostic> = compile("@ m2 c4 @ r1 @ r1 @ r1 @ c4").unwrap_err();
+    assert_eq!(errors[0].code, DiagnosticCode::InvalidMacro);
+    assert_eq!(errors[0].help.as_deref(), Some("Number of macros: 1."));
+    let errors: Vec<Diagnostic> = compile("@ m0 c4 @ r1 @ r1 @ r1 @ c4").unwrap_err();
+    assert_eq!(errors[0].code, DiagnosticCode::InvalidMacro);
+}
@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use mmml_compiler::{compiler::Compiler, decompiler::{parse_c_array, Decompiler}, lexer::Lexer};
+
+fn compile(source: String) -> Vec<u8> {
+    let mut lexer: Lexer = Lexer::new(source);
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile().unwrap()
+}
+
+#[test]
+fn protodome_round_trip_test() {
+    let manifest_dir: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let path: PathBuf = manifest_dir.join("test_data").join("4000ad.mmml");
+    let bytes: Vec<u8> = compile(std::fs::read_to_string(path).unwrap());
+    let mut decompiler: Decompiler = Decompiler::new(bytes.clone());
+    let source: String = decompiler.decompile().unwrap();
+    assert_eq!(compile(source), bytes);
+}
+
+#[test]
+fn test_commands() {
+    let bytes: Vec<u8> = compile("@ o3 v8 t40 c4 d#8. [2 r16 ] m1 @ @ @ @ e128 < f+2 &".into());
+    let mut decompiler: Decompiler = Decompiler::new(bytes);
+    let source: String = decompiler.decompile().unwrap();
+    assert_eq!(
+        source,
+        "% Channel A\n@ o3 v8 t40 c4 d#8. [2 r16 ] m1\n\n% Channel B\n@\n\n% Channel C\n@\n\n\
+        % Channel D\n@\n\n% Macro 1\n@ e128 o2 f#2 &\n"
+    );
+}
+
+#[test]
+fn test_invalid_data() {
+    let mut decompiler: Decompiler = Decompiler::new(vec![0x00, 0x08, 0x00, 0x09, 0x00, 0x0A, 0x00, 0x0B, 0xF1, 0xFF, 0xFF, 0xFF, 0x00]);
+    assert!(decompiler.decompile().is_err());
+}
+
+#[test]
+fn test_c_array() {
+    let source: &str = "const unsigned char MUSIC[4] = {\n\t0x00,0x1F,\n\t0xff,12\n};";
+    assert_eq!(parse_c_array(source).unwrap(), vec![0x00, 0x1F, 0xFF, 12]);
+}
@@ -0,0 +1,123 @@
+use mmml_compiler::{
+    compiler::Compiler,
+    diagnostic::{Diagnostic, DiagnosticCode, Severity, Span, WarningLevel, WarningLevels},
+    lexer::Lexer
+};
+
+fn compile(source: &str) -> Result<Vec<u8>, Diagnostic> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().map_err(|diagnostics| diagnostics[0].clone())?);
+    compiler.compile().map_err(|diagnostics| diagnostics[0].clone())
+}
+
+#[test]
+fn test_codes() {
+    let cases: [(&str, DiagnosticCode); 11] = [
+        ("@ c4 $ @ @ @", DiagnosticCode::UnexpectedCharacter),
+        ("@ o6 @ @ @", DiagnosticCode::InvalidOctave),
+        ("@ v9 @ @ @", DiagnosticCode::InvalidVolume),
+        ("@ c3 @ @ @", DiagnosticCode::InvalidDuration),
+        ("@ c1. @ @ @", DiagnosticCode::InvalidDuration),
+        ("@ o1 < @ @ @", DiagnosticCode::OctaveUnderflow),
+        ("@ x @ @ @", DiagnosticCode::UnknownCommand),
+        ("@ @ @", DiagnosticCode::NotEnoughHeaders),
+        ("@ m(lead) @ @ @ @bass: c", DiagnosticCode::UnknownMacroName),
+        ("@ m(drums) @drums: @ @ @ c", DiagnosticCode::InvalidMacro),
+        ("@ m1 @ @ @ @lead: c @lead: d", DiagnosticCode::DuplicateSectionName)
+    ];
+    for (source, code) in cases {
+        let diagnostic: Diagnostic = compile(source).unwrap_err();
+        assert_eq!(diagnostic.code, code, "{}", source);
+        assert_eq!(diagnostic.severity, Severity::Error);
+    }
+}
+
+#[test]
+fn test_span() {
+    let diagnostic: Diagnostic = compile("@ c4\n@ o12 @ @").unwrap_err();
+    assert_eq!(diagnostic.span, Span::new(2, 3, 8, 2));
+    assert_eq!(diagnostic.help.as_deref(), Some("Expected octave number 1-5."));
+}
+
+#[test]
+fn test_render() {
+    let source: &str = "@ c4 [2 d8\n@ e4 @ @";
+    let diagnostic: Diagnostic = compile(source).unwrap_err();
+    assert_eq!(
+        diagnostic.render(source, "song.mmml"),
+        "error[E0010]: Loop didn't close at the end of channel\n \
+        --> song.mmml:2:1\n  \
+        |\n\
+        1 | @ c4 [2 d8\n  \
+        |      - loop starts here\n\
+        2 | @ e4 @ @\n  \
+        | ^\n"
+    );
+}
+
+#[test]
+fn test_multiple_errors() {
+    let source: &str = "@ o6 c3 [2 x d4 ] q > @ [300 e4 ] @ @";
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    let codes: Vec<DiagnosticCode> = compiler.compile().unwrap_err().iter().map(|diagnostic| diagnostic.code).collect();
+    assert_eq!(codes, vec![
+        DiagnosticCode::InvalidOctave,
+        DiagnosticCode::InvalidDuration,
+        DiagnosticCode::UnknownCommand,
+        DiagnosticCode::UnknownCommand,
+        DiagnosticCode::InvalidNumber
+    ]);
+
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.set_max_errors(2);
+    assert_eq!(compiler.compile().unwrap_err().len(), 2);
+
+    let mut lexer: Lexer = Lexer::new("@ ^c4 ~ @ @ @".into());
+    assert_eq!(lexer.tokenize().unwrap_err().len(), 2);
+}
+
+#[test]
+fn test_warnings() {
+    let mut lexer: Lexer = Lexer::new("@ c4 & c4 k2 @ i1 p3 @ s @".into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile().unwrap();
+    let warnings: &[Diagnostic] = compiler.warnings();
+    let codes: Vec<DiagnosticCode> = warnings.iter().map(|warning| warning.code).collect();
+    assert_eq!(codes, vec![
+        DiagnosticCode::TieCommand,
+        DiagnosticCode::TransposeCommand,
+        DiagnosticCode::InstrumentCommand,
+        DiagnosticCode::PanningCommand,
+        DiagnosticCode::StopCommand
+    ]);
+    assert!(warnings.iter().all(|warning| warning.severity == Severity::Warning));
+
+    let mut warning_levels: WarningLevels = WarningLevels::default();
+    warning_levels.set_default_level(WarningLevel::Deny);
+    warning_levels.set_level(DiagnosticCode::from_warning_name("tie").unwrap(), WarningLevel::Allow);
+    warning_levels.set_level(DiagnosticCode::from_warning_name("W0004").unwrap(), WarningLevel::Warn);
+    let severities: Vec<(DiagnosticCode, Severity)> = warning_levels.apply(warnings)
+        .iter()
+        .map(|warning| (warning.code, warning.severity))
+        .collect();
+    assert_eq!(severities, vec![
+        (DiagnosticCode::TransposeCommand, Severity::Error),
+        (DiagnosticCode::InstrumentCommand, Severity::Error),
+        (DiagnosticCode::PanningCommand, Severity::Warning),
+        (DiagnosticCode::StopCommand, Severity::Error)
+    ]);
+}
+
+#[test]
+fn test_unused_macro() {
+    let mut lexer: Lexer = Lexer::new("@ m1 @ @ @ @lead: c @bass: d @drums: e".into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile().unwrap();
+    let warnings: &[Diagnostic] = compiler.warnings();
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings.iter().all(|warning| warning.code == DiagnosticCode::UnusedMacro));
+    assert_eq!(warnings[0].message, "Macro bass is never called");
+    assert_eq!(DiagnosticCode::from_warning_name("unused-macro"), Some(DiagnosticCode::UnusedMacro));
+}
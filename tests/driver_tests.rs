@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use mmml_compiler::{compiler::Compiler, driver::{ChannelState, Driver, DriverFault, LoopFrame}, lexer::Lexer};
+
+fn compile(source: String) -> Vec<u8> {
+    let mut lexer: Lexer = Lexer::new(source);
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile().unwrap()
+}
+
+fn run(data: Vec<u8>, ticks: usize) -> Result<Driver, DriverFault> {
+    let mut driver: Driver = Driver::new(data)?;
+    for _ in 0..ticks {
+        driver.tick()?;
+    }
+    Ok(driver)
+}
+
+#[test]
+fn protodome_test() {
+    let manifest_dir: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let path: PathBuf = manifest_dir.join("test_data").join("4000ad.mmml");
+    let mut driver: Driver = Driver::new(compile(std::fs::read_to_string(path).unwrap())).unwrap();
+    while !driver.is_finished() {
+        driver.tick().unwrap();
+    }
+    assert!(driver.channels().iter().all(|channel| channel.macro_stack.is_empty() && channel.loop_stack.is_empty()));
+}
+
+#[test]
+fn test_channel_state() {
+    let driver: Driver = run(compile("@ o2 v6 [3 c8. ] m1 @ @ @ @ d16".into()), 50).unwrap();
+    let channel: &ChannelState = &driver.channels()[0];
+    assert_eq!(channel.octave, 1);
+    assert_eq!(channel.volume, 3);
+    assert_eq!(channel.note, 1);
+    assert_eq!(channel.remaining_ticks, 22);
+    assert_eq!(channel.loop_stack, vec![LoopFrame { start: channel.start + 4, remaining: 0 }]);
+    assert!(channel.macro_stack.is_empty());
+
+    let driver: Driver = run(compile("@ o2 v6 [3 c8. ] m1 @ @ @ @ d16".into()), 76).unwrap();
+    let channel: &ChannelState = &driver.channels()[0];
+    assert_eq!(channel.note, 3);
+    assert_eq!(channel.remaining_ticks, 4);
+    assert!(channel.loop_stack.is_empty());
+    assert_eq!(channel.macro_stack, vec![channel.start + 8]);
+    assert_eq!(driver.ticks(), 76);
+}
+
+#[test]
+fn test_faults() {
+    let loops: Vec<u8> = compile("@ [2 [2 [2 [2 [2 [2 c4 ] ] ] ] ] ] @ @ @".into());
+    assert!(matches!(run(loops, 1), Err(DriverFault::LoopStackOverflow { channel: 0, .. })));
+
+    // The compiler rejects macro calls inside macros, the call is patched in.
+    let mut macros: Vec<u8> = compile("@ m1 @ @ @ @ r4 r4 @ c4".into());
+    let macro_start: usize = macros[9] as usize;
+    macros[macro_start..macro_start + 2].copy_from_slice(&[0xF2, 0x01]);
+    assert!(matches!(run(macros, 1), Err(DriverFault::MacroRecursion { channel: 0, .. })));
+
+    // The compiler rejects calls past the last macro, the macro id is patched in.
+    let mut header_table: Vec<u8> = compile("@ @ m1 @ @ @ c4".into());
+    let channel_start: usize = header_table[3] as usize;
+    header_table[channel_start + 1] = 0x01;
+    assert!(matches!(run(header_table, 1), Err(DriverFault::InvalidMacro { channel: 1, macro_id: 1, .. })));
+
+    assert!(matches!(Driver::new(vec![0x00, 0x02, 0xFF]), Err(DriverFault::InvalidHeaderTable)));
+}
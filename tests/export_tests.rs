@@ -0,0 +1,143 @@
+use std::io::Error;
+
+use mmml_compiler::{
+    compiler::Compiler,
+    decompiler::parse_c_array,
+    export::{AssemblyExporter, CExporter, Exporter, IntelHexExporter, JsonExporter, PythonExporter, RustExporter, SRecordExporter},
+    lexer::Lexer
+};
+use serde_json::Value;
+
+fn compile(source: &str) -> Vec<u8> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile().unwrap()
+}
+
+fn export(exporter: &dyn Exporter, data: &[u8], name: &str) -> String {
+    String::from_utf8(exporter.export(data, name).unwrap()).unwrap()
+}
+
+#[test]
+fn test_source_exports() {
+    let data: Vec<u8> = (0..18).collect();
+    let c_code: String = export(&CExporter::new(), &data, "SONG");
+    assert_eq!(
+        c_code,
+        "const unsigned char SONG[18] = {\n\t0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0A,0x0B,0x0C,0x0D,0x0E,0x0F,0x10,\n\t0x11\n};"
+    );
+    assert_eq!(parse_c_array(&c_code).unwrap(), data);
+    assert_eq!(
+        CExporter::new().companion_files(&data, "SONG").unwrap(),
+        [(String::from("h"), b"#ifndef SONG_H\n#define SONG_H\n\nextern const unsigned char SONG[];\n\n#endif".to_vec())]
+    );
+    assert_eq!(
+        export(&RustExporter, &data[..3], "SONG"),
+        "pub static SONG: [u8; 3] = [\n    0x00, 0x01, 0x02\n];\n"
+    );
+    assert_eq!(export(&PythonExporter, &data[..3], "SONG"), "SONG = bytes([\n    0x00, 0x01, 0x02\n])\n");
+    let assembly: String = export(&AssemblyExporter, &data, "SONG");
+    assert!(assembly.contains("\t.global SONG\n"));
+    assert!(assembly.ends_with("SONG:\n\t.byte 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F\n\t.byte 0x10, 0x11\n"));
+}
+
+#[test]
+fn test_c_options() {
+    let data: Vec<u8> = compile("@ c4 @ r1 @ r1 @ r1 @ d4");
+    let mut exporter: CExporter = CExporter::new();
+    exporter.set_progmem(true);
+    exporter.set_section(Some(String::from(".music")));
+    exporter.set_stdint(true);
+    exporter.set_size_define(true);
+    exporter.set_offset_defines(true);
+    exporter.set_bytes_per_line(8);
+    let c_code: String = export(&exporter, &data, "SONG");
+    assert!(c_code.starts_with(
+        "#include <stdint.h>\n#include <avr/pgmspace.h>\n\n\
+        const uint8_t SONG[21] __attribute__((section(\".music\"))) PROGMEM = {\n\t0x00,0x0A,0x00,0x0C,0x00,0x0E,0x00,0x10,\n\t"
+    ));
+    assert_eq!(parse_c_array(&c_code).unwrap(), data);
+    let header: Vec<u8> = exporter.companion_files(&data, "SONG").unwrap().remove(0).1;
+    assert_eq!(
+        String::from_utf8(header).unwrap(),
+        "#ifndef SONG_H\n#define SONG_H\n\n\
+        #include <stdint.h>\n#include <avr/pgmspace.h>\n\n\
+        #define SONG_SIZE 21\n#define SONG_CHANNEL_A_OFFSET 0x000A\n#define SONG_CHANNEL_B_OFFSET 0x000C\n\
+        #define SONG_CHANNEL_C_OFFSET 0x000E\n#define SONG_CHANNEL_D_OFFSET 0x0010\n#define SONG_MACRO_1_OFFSET 0x0012\n\n\
+        extern const uint8_t SONG[] PROGMEM;\n\n#endif"
+    );
+    exporter.set_static(true);
+    assert!(export(&exporter, &data, "SONG").contains("\nstatic const uint8_t SONG[21]"));
+    let header: Vec<u8> = exporter.companion_files(&data, "SONG").unwrap().remove(0).1;
+    assert!(!String::from_utf8(header).unwrap().contains("extern"));
+}
+
+#[test]
+fn test_many_channel_defines() {
+    let mut data: Vec<u8> = Vec::new();
+    for channel in 0..28u16 {
+        data.extend((56 + channel).to_be_bytes());
+    }
+    data.extend([0xFF; 28]);
+    data.push(0x00);
+    let mut exporter: CExporter = CExporter::new();
+    exporter.set_offset_defines(true);
+    exporter.set_channels(28);
+    let header: String = String::from_utf8(exporter.companion_files(&data, "SONG").unwrap().remove(0).1).unwrap();
+    assert!(header.contains("#define SONG_CHANNEL_Z_OFFSET 0x0051\n#define SONG_CHANNEL_27_OFFSET 0x0052\n#define SONG_CHANNEL_28_OFFSET 0x0053\n"));
+}
+
+#[test]
+fn test_json_export() {
+    let data: Vec<u8> = compile("@ c4 @ r1 @ r1 @ r1 @ d4");
+    let document: Value = serde_json::from_str(&export(&JsonExporter, &data, "SONG")).unwrap();
+    assert_eq!(document["name"], "SONG");
+    assert_eq!(document["size"], data.len());
+    assert_eq!(document["headers"], serde_json::json!([10, 12, 14, 16, 18]));
+    assert_eq!(document["data"], serde_json::json!(data));
+}
+
+#[test]
+fn test_intel_hex_export() {
+    assert_eq!(export(&IntelHexExporter::default(), &[0x00, 0x0A], "SONG"), ":02000000000AF4\n:00000001FF\n");
+    let data: Vec<u8> = vec![0xAA; 12];
+    let records: String = export(&IntelHexExporter { base_address: 0xFFF8 }, &data, "SONG");
+    assert_eq!(
+        records.lines().collect::<Vec<&str>>(),
+        [":08FFF800AAAAAAAAAAAAAAAAB1", ":020000040001F9", ":04000000AAAAAAAA54", ":00000001FF"]
+    );
+}
+
+#[test]
+fn test_s_record_export() {
+    let records: String = export(&SRecordExporter { base_address: 0x1000 }, &[0x00, 0x0A], "SONG");
+    assert_eq!(
+        records.lines().collect::<Vec<&str>>(),
+        ["S0070000534F4E47C1", "S1051000000AE0", "S5030001FB", "S9031000EC"]
+    );
+    let records: String = export(&SRecordExporter { base_address: 0x10000 }, &[0x00], "SONG");
+    assert!(records.contains("\nS20501000000F9\n"));
+    assert!(records.ends_with("S804010000FA\n"));
+    assert!(SRecordExporter { base_address: u32::MAX }.export(&[0x00, 0x0A], "SONG").is_err());
+}
+
+/// A format added outside of the library.
+struct HexDumpExporter;
+
+impl Exporter for HexDumpExporter {
+    fn extension(&self) -> &str {
+        "txt"
+    }
+
+    fn export(&self, data: &[u8], _name: &str) -> Result<Vec<u8>, Error> {
+        Ok(data.iter().map(|byte| format!("{:02x}", byte)).collect::<String>().into_bytes())
+    }
+}
+
+#[test]
+fn test_custom_exporter() {
+    let exporter: Box<dyn Exporter> = Box::new(HexDumpExporter);
+    assert_eq!(exporter.extension(), "txt");
+    assert_eq!(export(exporter.as_ref(), &[0x00, 0x0A, 0xFF], "SONG"), "000aff");
+    assert!(exporter.companion_files(&[0x00], "SONG").unwrap().is_empty());
+}
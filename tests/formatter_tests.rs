@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use mmml_compiler::{compiler::Compiler, formatter::Formatter, lexer::Lexer, token::Token};
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    lexer.set_keep_trivia(true);
+    lexer.tokenize().unwrap()
+}
+
+#[test]
+fn test_format() {
+    let source: &str = "% Song\n\n\n$RIFF($n) = $n C+8 $n @ o3 C4 d E+ f   % first\n[2 > c <b ]   r 4 .\n\n@lead: m (lead) $RIFF(e) @ T120 @\n";
+    assert_eq!(
+        Formatter::new().format(&tokenize(source)),
+        "% Song\n\n$RIFF($n) = $n c#8 $n @ o3 c4 d e# f % first\n[2 >c <b ] r4.\n\n@lead: m(lead) $RIFF(e)\n@ t120\n@\n"
+    );
+    let source: &str = "@ o3 C4 d E+ f   % first\n@ c @ d @ e";
+    assert_eq!(Formatter::new().format(&tokenize(source)), "@ o3 c4 d e# f % first\n@ c\n@ d\n@ e\n");
+}
+
+#[test]
+fn test_wrap_and_align() {
+    let mut formatter: Formatter = Formatter::new();
+    formatter.set_width(12);
+    assert_eq!(formatter.format(&tokenize("@ c4 d e f g a b >c % up\n$A = c d e f g a b")), "@ c4 d e f g\na b >c % up\n$A = c d e f g a b\n");
+
+    let mut formatter: Formatter = Formatter::new();
+    formatter.set_align(true);
+    let source: &str = "@ o3 c4 d e f | g1 |\n@ c1 | e8 f g a b >c d e |\n@ r1 | r1 |\n@ [4 c4 ] | r |\n@ c d e";
+    let formatted: String = formatter.format(&tokenize(source));
+    assert_eq!(
+        formatted,
+        "@ o3 c4 d e f | g1 |\n@ c1 |          e8 f g a b >c d e |\n@ r1 |          r1 |\n@ [4 c4 ] |     r |\n@ c d e\n"
+    );
+    assert_eq!(formatter.format(&tokenize(&formatted)), formatted);
+}
+
+#[test]
+fn test_song() {
+    let manifest_dir: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let source: String = std::fs::read_to_string(manifest_dir.join("test_data").join("4000ad.mmml")).unwrap();
+    let formatted: String = Formatter::new().format(&tokenize(&source));
+    assert_ne!(formatted, source);
+    assert_eq!(Formatter::new().format(&tokenize(&formatted)), formatted);
+    assert!(formatted.lines().all(|line| line.len() <= 80 || line.trim_start().starts_with('%')));
+    let compile = |source: &str| Compiler::new(tokenize(source)).compile().unwrap();
+    assert_eq!(compile(&formatted), compile(&source));
+}
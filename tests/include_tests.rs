@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+
+use mmml_compiler::{
+    compiler::Compiler,
+    diagnostic::{Diagnostic, DiagnosticCode, Span},
+    include::Includer,
+    token::Token
+};
+
+fn include_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_data").join("include")
+}
+
+fn tokenize(includer: &mut Includer, name: &str) -> Result<Vec<Token>, Vec<Diagnostic>> {
+    let path: PathBuf = include_dir().join(name);
+    let source: String = std::fs::read_to_string(&path).unwrap();
+    includer.tokenize(path, source)
+}
+
+#[test]
+fn test_include_paths() {
+    let mut includer: Includer = Includer::new();
+    let errors: Vec<Diagnostic> = tokenize(&mut includer, "song.mmml").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, DiagnosticCode::IncludeNotFound);
+    assert_eq!(errors[0].span, Span::new(6, 0, 89, 21));
+
+    includer.add_include_path(include_dir().join("library"));
+    let tokens: Vec<Token> = tokenize(&mut includer, "song.mmml").unwrap();
+    assert_eq!(includer.files().len(), 3);
+    assert!(includer.files()[2].path.ends_with("library/arpeggios.mmml"));
+    let mut compiler: Compiler = Compiler::new(tokens);
+    let bytes: Vec<u8> = compiler.compile().unwrap();
+    assert_eq!(&bytes[0..12], &[0x00, 0x0C, 0x00, 0x17, 0x00, 0x19, 0x00, 0x1B, 0x00, 0x1D, 0x00, 0x21]);
+    assert_eq!(&bytes[29..35], &[0x14, 0x04, 0x03, 0xFF, 0x14, 0x54]);
+}
+
+#[test]
+fn test_include_cycle() {
+    let mut includer: Includer = Includer::new();
+    let errors: Vec<Diagnostic> = tokenize(&mut includer, "cycle.mmml").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, DiagnosticCode::IncludeCycle);
+    assert_eq!(errors[0].span.file, 1);
+    assert_eq!(errors[0].help.as_deref(), Some("Include cycle: cycle.mmml -> cycle_included.mmml -> cycle.mmml."));
+}
+
+#[test]
+fn test_included_file_diagnostics() {
+    let mut includer: Includer = Includer::new();
+    let mut compiler: Compiler = Compiler::new(tokenize(&mut includer, "error.mmml").unwrap());
+    let errors: Vec<Diagnostic> = compiler.compile().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, DiagnosticCode::InvalidOctave);
+    assert_eq!(errors[0].span.file, 2);
+    assert_eq!((errors[0].span.line, errors[0].span.column), (2, 3));
+
+    let sources: Vec<(String, &str)> = includer.sources();
+    let files: Vec<(&str, &str)> = sources.iter().map(|(name, source)| (name.as_str(), *source)).collect();
+    let rendered: String = errors[0].render_files(&files);
+    assert!(rendered.contains("library/bad.mmml:2:4"), "{}", rendered);
+    assert!(rendered.contains("2 |   o9 d4"), "{}", rendered);
+}
+
+#[test]
+fn test_include_once() {
+    let mut includer: Includer = Includer::new();
+    includer.add_include_path(include_dir().join("library"));
+    let tokens: Vec<Token> = tokenize(&mut includer, "diamond.mmml").unwrap();
+    assert_eq!(includer.files().len(), 3);
+    let mut compiler: Compiler = Compiler::new(tokens);
+    assert!(compiler.compile().is_ok());
+}
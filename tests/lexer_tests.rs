@@ -1,4 +1,10 @@
-use mmml_compiler::{lexer::Lexer, token::Token, token::TokenType};
+use mmml_compiler::{
+    compiler::Compiler,
+    diagnostic::{Diagnostic, DiagnosticCode},
+    lexer::Lexer,
+    token::Token,
+    token::TokenType
+};
 
 #[test]
 fn test_commands() {
@@ -6,34 +12,34 @@ fn test_commands() {
     let mut lexer: Lexer = Lexer::new(source);
     let tokens: Vec<Token> = lexer.tokenize().unwrap();
     let expected_tokens: Vec<Token> = vec![
-        Token::new("c".into(), TokenType::Command, 1, 0),
-        Token::new("1".into(), TokenType::Number, 1, 1),
-        Token::new("c#".into(), TokenType::Command, 1, 2),
-        Token::new("2".into(), TokenType::Number, 1, 4),
-        Token::new("d".into(), TokenType::Command, 1, 5),
-        Token::new("4".into(), TokenType::Number, 1, 6),
-        Token::new("d#".into(), TokenType::Command, 1, 7),
-        Token::new("8".into(), TokenType::Number, 1, 9),
-        Token::new("e".into(), TokenType::Command, 1, 10),
-        Token::new("16".into(), TokenType::Number, 1, 11),
-        Token::new("f".into(), TokenType::Command, 1, 13),
-        Token::new("32".into(), TokenType::Number, 1, 14),
-        Token::new("f#".into(), TokenType::Command, 1, 16),
-        Token::new("64".into(), TokenType::Number, 1, 18),
-        Token::new("g".into(), TokenType::Command, 1, 20),
-        Token::new("128".into(), TokenType::Number, 1, 21),
-        Token::new("g#".into(), TokenType::Command, 1, 24),
-        Token::new("1".into(), TokenType::Number, 1, 26),
-        Token::new("a".into(), TokenType::Command, 1, 27),
-        Token::new("2".into(), TokenType::Number, 1, 28),
-        Token::new("a#".into(), TokenType::Command, 1, 29),
-        Token::new("4".into(), TokenType::Number, 1, 31),
-        Token::new("b".into(), TokenType::Command, 1, 32),
-        Token::new("8".into(), TokenType::Number, 1, 33),
-        Token::new("r".into(), TokenType::Command, 1, 34),
-        Token::new("16".into(), TokenType::Number, 1, 35),
-        Token::new("&".into(), TokenType::Command, 1, 37),
-        Token::empty(1, 37)
+        Token::new("c".into(), TokenType::Command, 1, 0, 0),
+        Token::new("1".into(), TokenType::Number, 1, 1, 1),
+        Token::new("c#".into(), TokenType::Command, 1, 2, 2),
+        Token::new("2".into(), TokenType::Number, 1, 4, 4),
+        Token::new("d".into(), TokenType::Command, 1, 5, 5),
+        Token::new("4".into(), TokenType::Number, 1, 6, 6),
+        Token::new("d#".into(), TokenType::Command, 1, 7, 7),
+        Token::new("8".into(), TokenType::Number, 1, 9, 9),
+        Token::new("e".into(), TokenType::Command, 1, 10, 10),
+        Token::new("16".into(), TokenType::Number, 1, 11, 11),
+        Token::new("f".into(), TokenType::Command, 1, 13, 13),
+        Token::new("32".into(), TokenType::Number, 1, 14, 14),
+        Token::new("f#".into(), TokenType::Command, 1, 16, 16),
+        Token::new("64".into(), TokenType::Number, 1, 18, 18),
+        Token::new("g".into(), TokenType::Command, 1, 20, 20),
+        Token::new("128".into(), TokenType::Number, 1, 21, 21),
+        Token::new("g#".into(), TokenType::Command, 1, 24, 24),
+        Token::new("1".into(), TokenType::Number, 1, 26, 26),
+        Token::new("a".into(), TokenType::Command, 1, 27, 27),
+        Token::new("2".into(), TokenType::Number, 1, 28, 28),
+        Token::new("a#".into(), TokenType::Command, 1, 29, 29),
+        Token::new("4".into(), TokenType::Number, 1, 31, 31),
+        Token::new("b".into(), TokenType::Command, 1, 32, 32),
+        Token::new("8".into(), TokenType::Number, 1, 33, 33),
+        Token::new("r".into(), TokenType::Command, 1, 34, 34),
+        Token::new("16".into(), TokenType::Number, 1, 35, 35),
+        Token::new("&".into(), TokenType::Command, 1, 37, 37),
+        Token::empty(1, 37, 37)
     ];
     assert_eq!(tokens, expected_tokens);
 }
@@ -44,34 +50,34 @@ fn test_comment() {
     let mut lexer: Lexer = Lexer::new(source);
     let tokens: Vec<Token> = lexer.tokenize().unwrap();
     let expected_tokens: Vec<Token> = vec![
-        Token::new("c".into(), TokenType::Command, 2, 0),
-        Token::new("1".into(), TokenType::Number, 2, 1),
-        Token::new("c#".into(), TokenType::Command, 2, 2),
-        Token::new("2".into(), TokenType::Number, 2, 4),
-        Token::new("d".into(), TokenType::Command, 2, 5),
-        Token::new("4".into(), TokenType::Number, 2, 6),
-        Token::new("d#".into(), TokenType::Command, 2, 7),
-        Token::new("8".into(), TokenType::Number, 2, 9),
-        Token::new("e".into(), TokenType::Command, 2, 10),
-        Token::new("16".into(), TokenType::Number, 2, 11),
-        Token::new("f".into(), TokenType::Command, 2, 13),
-        Token::new("32".into(), TokenType::Number, 2, 14),
-        Token::new("f#".into(), TokenType::Command, 2, 16),
-        Token::new("64".into(), TokenType::Number, 2, 18),
-        Token::new("g".into(), TokenType::Command, 2, 20),
-        Token::new("128".into(), TokenType::Number, 2, 21),
-        Token::new("g#".into(), TokenType::Command, 2, 24),
-        Token::new("1".into(), TokenType::Number, 2, 26),
-        Token::new("a".into(), TokenType::Command, 2, 27),
-        Token::new("2".into(), TokenType::Number, 2, 28),
-        Token::new("a#".into(), TokenType::Command, 2, 29),
-        Token::new("4".into(), TokenType::Number, 2, 31),
-        Token::new("b".into(), TokenType::Command, 2, 32),
-        Token::new("8".into(), TokenType::Number, 2, 33),
-        Token::new("r".into(), TokenType::Command, 4, 0),
-        Token::new("16".into(), TokenType::Number, 4, 1),
-        Token::new("&".into(), TokenType::Command, 4, 3),
-        Token::empty(4, 3)
+        Token::new("c".into(), TokenType::Command, 2, 0, 25),
+        Token::new("1".into(), TokenType::Number, 2, 1, 26),
+        Token::new("c#".into(), TokenType::Command, 2, 2, 27),
+        Token::new("2".into(), TokenType::Number, 2, 4, 29),
+        Token::new("d".into(), TokenType::Command, 2, 5, 30),
+        Token::new("4".into(), TokenType::Number, 2, 6, 31),
+        Token::new("d#".into(), TokenType::Command, 2, 7, 32),
+        Token::new("8".into(), TokenType::Number, 2, 9, 34),
+        Token::new("e".into(), TokenType::Command, 2, 10, 35),
+        Token::new("16".into(), TokenType::Number, 2, 11, 36),
+        Token::new("f".into(), TokenType::Command, 2, 13, 38),
+        Token::new("32".into(), TokenType::Number, 2, 14, 39),
+        Token::new("f#".into(), TokenType::Command, 2, 16, 41),
+        Token::new("64".into(), TokenType::Number, 2, 18, 43),
+        Token::new("g".into(), TokenType::Command, 2, 20, 45),
+        Token::new("128".into(), TokenType::Number, 2, 21, 46),
+        Token::new("g#".into(), TokenType::Command, 2, 24, 49),
+        Token::new("1".into(), TokenType::Number, 2, 26, 51),
+        Token::new("a".into(), TokenType::Command, 2, 27, 52),
+        Token::new("2".into(), TokenType::Number, 2, 28, 53),
+        Token::new("a#".into(), TokenType::Command, 2, 29, 54),
+        Token::new("4".into(), TokenType::Number, 2, 31, 56),
+        Token::new("b".into(), TokenType::Command, 2, 32, 57),
+        Token::new("8".into(), TokenType::Number, 2, 33, 58),
+        Token::new("r".into(), TokenType::Command, 4, 0, 79),
+        Token::new("16".into(), TokenType::Number, 4, 1, 80),
+        Token::new("&".into(), TokenType::Command, 4, 3, 82),
+        Token::empty(4, 3, 82)
     ];
     assert_eq!(tokens, expected_tokens);
 }
@@ -82,16 +88,66 @@ fn test_symbols() {
     let mut lexer: Lexer = Lexer::new(source);
     let tokens: Vec<Token> = lexer.tokenize().unwrap();
     let expected_tokens: Vec<Token> = vec![
-        Token::new("@".into(), TokenType::Arobase, 1, 0),
-        Token::new("[".into(), TokenType::LeftParen, 1, 1),
-        Token::new("2".into(), TokenType::Number, 1, 2),
-        Token::new("]".into(), TokenType::RightParen, 1, 3),
-        Token::new("<".into(), TokenType::LessThan, 1, 4),
-        Token::new(">".into(), TokenType::GreaterThan, 1, 5),
-        Token::new("r".into(), TokenType::Command, 1, 6),
-        Token::new("2".into(), TokenType::Number, 1, 7),
-        Token::new(".".into(), TokenType::Dot, 1, 8),
-        Token::empty(1, 8)
+        Token::new("@".into(), TokenType::Arobase, 1, 0, 0),
+        Token::new("[".into(), TokenType::LeftParen, 1, 1, 1),
+        Token::new("2".into(), TokenType::Number, 1, 2, 2),
+        Token::new("]".into(), TokenType::RightParen, 1, 3, 3),
+        Token::new("<".into(), TokenType::LessThan, 1, 4, 4),
+        Token::new(">".into(), TokenType::GreaterThan, 1, 5, 5),
+        Token::new("r".into(), TokenType::Command, 1, 6, 6),
+        Token::new("2".into(), TokenType::Number, 1, 7, 7),
+        Token::new(".".into(), TokenType::Dot, 1, 8, 8),
+        Token::empty(1, 8, 8)
     ];
     assert_eq!(tokens, expected_tokens);
 }
+
+#[test]
+fn test_names() {
+    let source: String = "@bass_1: c m(bass_1) @c4".into();
+    let mut lexer: Lexer = Lexer::new(source);
+    let tokens: Vec<Token> = lexer.tokenize().unwrap();
+    let expected_tokens: Vec<Token> = vec![
+        Token::new("@".into(), TokenType::Arobase, 1, 0, 0),
+        Token::new("bass_1:".into(), TokenType::Label, 1, 1, 1),
+        Token::new("c".into(), TokenType::Command, 1, 9, 9),
+        Token::new("m".into(), TokenType::Command, 1, 11, 11),
+        Token::new("(bass_1)".into(), TokenType::Name, 1, 12, 12),
+        Token::new("@".into(), TokenType::Arobase, 1, 21, 21),
+        Token::new("c".into(), TokenType::Command, 1, 22, 22),
+        Token::new("4".into(), TokenType::Number, 1, 23, 23),
+        Token::empty(1, 23, 23)
+    ];
+    assert_eq!(tokens, expected_tokens);
+    assert!(Lexer::new("@ m(bass c".into()).tokenize().is_err());
+}
+
+#[test]
+fn test_trivia() {
+    let mut lexer: Lexer = Lexer::new("c % tie\n\n&".into());
+    lexer.set_keep_trivia(true);
+    let tokens: Vec<Token> = lexer.tokenize().unwrap();
+    let expected_tokens: Vec<Token> = vec![
+        Token::new("c".into(), TokenType::Command, 1, 0, 0),
+        Token::new(" ".into(), TokenType::Whitespace, 1, 1, 1),
+        Token::new("% tie".into(), TokenType::Comment, 1, 2, 2),
+        Token::new("\n\n".into(), TokenType::Whitespace, 1, 7, 7),
+        Token::new("&".into(), TokenType::Command, 3, 0, 9),
+        Token::empty(3, 0, 9)
+    ];
+    assert_eq!(tokens, expected_tokens);
+}
+
+#[test]
+fn test_recovery() {
+    let mut lexer: Lexer = Lexer::new("@ ^ c4 @ o9 @ @".into());
+    let (tokens, lexer_errors): (Vec<Token>, Vec<Diagnostic>) = lexer.tokenize_recovering();
+    assert_eq!(lexer_errors.len(), 1);
+    assert_eq!(tokens.len(), 9);
+
+    let mut compiler: Compiler = Compiler::new(tokens);
+    compiler.set_lexer_errors(lexer_errors);
+    let errors: Vec<Diagnostic> = compiler.compile().unwrap_err();
+    let codes: Vec<DiagnosticCode> = errors.iter().map(|error| error.code).collect();
+    assert_eq!(codes, [DiagnosticCode::UnexpectedCharacter, DiagnosticCode::InvalidOctave]);
+}
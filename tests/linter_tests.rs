@@ -0,0 +1,74 @@
+use mmml_compiler::{
+    ast::Song,
+    compiler::Compiler,
+    diagnostic::{Diagnostic, DiagnosticCode, Span},
+    lexer::Lexer,
+    linter::lint
+};
+
+fn lint_source(source: &str) -> Vec<Diagnostic> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    let (song, _): (Song, Vec<Diagnostic>) = compiler.parse();
+    compiler.compile().unwrap();
+    lint(&song, compiler.compiled_items())
+}
+
+fn codes(source: &str) -> Vec<DiagnosticCode> {
+    lint_source(source).iter().map(|warning| warning.code).collect()
+}
+
+#[test]
+fn test_rules() {
+    assert_eq!(codes("@ o3 c o3 d >> e [1 f ] [0 g ] v0 a s c @ @ @"), vec![
+        DiagnosticCode::RedundantCommand,
+        DiagnosticCode::OctaveShiftRun,
+        DiagnosticCode::SingleLoop,
+        DiagnosticCode::ZeroLoop,
+        DiagnosticCode::SilentNote,
+        DiagnosticCode::UnreachableCommand
+    ]);
+    assert!(codes("@ o3 c > d < e v4 f v5 [2 g ] @ @ @").is_empty());
+
+    let warnings: Vec<Diagnostic> = lint_source("@ o5 c <<< d @ @ @");
+    assert_eq!(warnings[0].message, "3 octave shifts in a row could be a single octave command");
+    assert_eq!(warnings[0].span, Span::new(1, 7, 7, 3));
+    assert_eq!(warnings[0].help.as_deref(), Some("Write o2 instead."));
+}
+
+#[test]
+fn test_state() {
+    // Loops start again with the state they ended with, macros may change it.
+    assert!(codes("@ o4 [2 c o3 d o4 ] @ o4 m1 o4 c @ o4 {+3 c } [2 d ] @ @ o4").is_empty());
+    assert_eq!(codes("@ o4 [2 c ] o4 @ v2 m1 v2 @ o4 {+3 o5 c } o5 @ @ c"), vec![
+        DiagnosticCode::RedundantCommand,
+        DiagnosticCode::RedundantCommand,
+        DiagnosticCode::RedundantCommand
+    ]);
+
+    let warnings: Vec<Diagnostic> = lint_source("@ v0 c v0 d [2 e ] @ @ @");
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(warnings[0].code, DiagnosticCode::SilentNote);
+    assert_eq!(warnings[0].labels[0].span, Span::new(1, 2, 2, 2));
+    assert_eq!(warnings[1].message, "v0 doesn't change the volume");
+}
+
+#[test]
+fn test_unreachable() {
+    let warnings: Vec<Diagnostic> = lint_source("@ [2 c2 s ] | d @ {+2 e s } @ c1 s | @");
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].code, DiagnosticCode::UnreachableCommand);
+    assert_eq!(warnings[0].span, Span::new(1, 14, 14, 1));
+    assert_eq!(DiagnosticCode::from_warning_name("unreachable-command"), Some(DiagnosticCode::UnreachableCommand));
+}
+
+#[test]
+fn test_unused_macros() {
+    let warnings: Vec<Diagnostic> = lint_source("@ c4 @ r1 @ r1 @ r1 @ d4");
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].code, DiagnosticCode::UnusedMacro);
+    assert_eq!(warnings[0].message, "Macro 1 is never called");
+    assert_eq!(warnings[0].span, Span::new(1, 20, 20, 1));
+    // Named macros are reported by the compiler.
+    assert!(codes("@ [2 m1 ] @ r1 @ r1 @ r1 @ d4 @named: e4").is_empty());
+}
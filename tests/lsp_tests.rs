@@ -0,0 +1,123 @@
+use std::{io::{BufReader, Write}, process::{Child, Command, Stdio}};
+
+use mmml_compiler::lsp::{read_message, write_message, Server};
+use serde_json::{json, Value};
+
+const URI: &str = "file:///tmp/song.mmml";
+
+fn open(source: &str) -> (Server, Vec<Value>) {
+    let mut server: Server = Server::new();
+    server.handle(&json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }));
+    let notifications: Vec<Value> = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": { "textDocument": { "uri": URI, "languageId": "mmml", "version": 1, "text": source } }
+    }));
+    (server, notifications)
+}
+
+fn request(server: &mut Server, method: &str, line: usize, character: usize) -> Value {
+    let responses: Vec<Value> = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": method,
+        "params": {
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+            "context": { "includeDeclaration": true }
+        }
+    }));
+    responses[0]["result"].clone()
+}
+
+#[test]
+fn test_diagnostics() {
+    let (mut server, notifications): (Server, Vec<Value>) = open("@ c4\n@ o9 @ @ m1 @ c");
+    assert_eq!(notifications[0]["method"], "textDocument/publishDiagnostics");
+    let diagnostics: &Value = &notifications[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["code"], "E0005");
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"], json!({ "start": { "line": 1, "character": 3 }, "end": { "line": 1, "character": 4 } }));
+
+    let notifications: Vec<Value> = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": { "textDocument": { "uri": URI, "version": 2 }, "contentChanges": [{ "text": "@ c4 & c4 @ @ @" }] }
+    }));
+    let diagnostics: &Value = &notifications[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["code"], "W0003");
+    assert_eq!(diagnostics[0]["severity"], 2);
+}
+
+#[test]
+fn test_hover() {
+    let (mut server, _): (Server, Vec<Value>) = open("@ o3 c4. > d\n@ m(lead) @ @ @lead: r8 |");
+    let hover: Value = request(&mut server, "textDocument/hover", 0, 6);
+    assert_eq!(hover["contents"]["value"], "`c4.`\n\nOctave 3, duration 4. (48 ticks)\n\nCompiles to `19`");
+    assert_eq!(hover["range"], json!({ "start": { "line": 0, "character": 5 }, "end": { "line": 0, "character": 8 } }));
+    let hover: Value = request(&mut server, "textDocument/hover", 0, 11);
+    assert_eq!(hover["contents"]["value"], "`d`\n\nOctave 4, duration 4. (48 ticks)\n\nCompiles to `39`");
+    let hover: Value = request(&mut server, "textDocument/hover", 1, 3);
+    assert_eq!(hover["contents"]["value"], "`m(lead)`\n\nCalls Macro 1 `lead`\n\nCompiles to `F2 00`");
+    let hover: Value = request(&mut server, "textDocument/hover", 1, 24);
+    assert_eq!(hover["contents"]["value"], "`|`\n\nCompiles to no bytes");
+    assert_eq!(request(&mut server, "textDocument/hover", 0, 1), Value::Null);
+}
+
+#[test]
+fn test_macros() {
+    let (mut server, _): (Server, Vec<Value>) = open("@ m2 [2 m1 ] @ m(bass) @ @\n@ c @bass: d");
+    let definition: Value = request(&mut server, "textDocument/definition", 0, 3);
+    assert_eq!(definition["uri"], URI);
+    assert_eq!(definition["range"]["start"], json!({ "line": 1, "character": 4 }));
+
+    let references: Value = request(&mut server, "textDocument/references", 1, 6);
+    let starts: Vec<Value> = references.as_array().unwrap().iter().map(|reference| reference["range"]["start"].clone()).collect();
+    assert_eq!(starts, vec![
+        json!({ "line": 1, "character": 4 }),
+        json!({ "line": 0, "character": 2 }),
+        json!({ "line": 0, "character": 15 })
+    ]);
+
+    let symbols: Value = request(&mut server, "textDocument/documentSymbol", 0, 0);
+    let names: Vec<&str> = symbols.as_array().unwrap().iter().map(|symbol| symbol["name"].as_str().unwrap()).collect();
+    assert_eq!(names, vec!["Channel A", "Channel B", "Channel C", "Channel D", "Macro 1", "Macro 2 bass"]);
+    assert_eq!(symbols[0]["range"]["end"], json!({ "line": 0, "character": 13 }));
+    assert_eq!(symbols[5]["range"]["end"], json!({ "line": 1, "character": 12 }));
+}
+
+#[test]
+fn test_stdio() {
+    let mut child: Child = Command::new(env!("CARGO_BIN_EXE_mmml-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for message in [
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "capabilities": {} } }),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": { "uri": URI, "text": "@ c4 @ @ @" } } }),
+        json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": { "textDocument": { "uri": URI }, "position": { "line": 0, "character": 2 } } }),
+        json!({ "jsonrpc": "2.0", "id": 3, "method": "unknown", "params": {} }),
+        json!({ "jsonrpc": "2.0", "id": 4, "method": "shutdown" }),
+        json!({ "jsonrpc": "2.0", "method": "exit" })
+    ] {
+        write_message(&mut stdin, &message).unwrap();
+    }
+    stdin.flush().unwrap();
+    let mut stdout: BufReader<_> = BufReader::new(child.stdout.take().unwrap());
+    let mut messages: Vec<Value> = Vec::new();
+    while let Some(message) = read_message(&mut stdout).unwrap() {
+        messages.push(message);
+    }
+    assert!(child.wait().unwrap().success());
+    assert_eq!(messages.len(), 5);
+    assert_eq!(messages[0]["result"]["capabilities"]["hoverProvider"], true);
+    assert_eq!(messages[1]["params"]["diagnostics"], json!([]));
+    assert_eq!(messages[2]["result"]["contents"]["value"], "`c4`\n\nOctave 4, duration 4 (32 ticks)\n\nCompiles to `12`");
+    assert_eq!(messages[3]["error"]["code"], -32601);
+    assert_eq!(messages[4], json!({ "jsonrpc": "2.0", "id": 4, "result": null }));
+}
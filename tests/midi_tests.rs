@@ -0,0 +1,118 @@
+use mmml_compiler::{
+    compiler::Compiler,
+    lexer::Lexer,
+    midi::{export_midi, import_midi, ImportWarning, MidiImport},
+    target::Target
+};
+
+fn compile(source: &str) -> Vec<u8> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile().unwrap()
+}
+
+fn count(bytes: &[u8], pattern: &[u8]) -> usize {
+    bytes.windows(pattern.len()).filter(|window| *window == pattern).count()
+}
+
+#[test]
+fn test_header() {
+    let midi: Vec<u8> = export_midi(compile("@ c4 @ r1 @ r1 @ r1"), 4).unwrap();
+    assert_eq!(&midi[0..14], b"MThd\x00\x00\x00\x06\x00\x01\x00\x05\x00\x20");
+    assert_eq!(count(&midi, b"MTrk"), 5);
+    assert_eq!(count(&midi, b"Channel D"), 1);
+    assert_eq!(&midi[midi.len() - 3..], &[0xFF, 0x2F, 0x00]);
+}
+
+#[test]
+fn test_target_channels() {
+    let compile_for = |source: &str, channels: u8| -> Vec<u8> {
+        let mut lexer: Lexer = Lexer::new(source.into());
+        let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+        compiler.set_target(Target { channels, ..Target::default() });
+        compiler.compile().unwrap()
+    };
+    let midi: Vec<u8> = export_midi(compile_for("@ c4 @ m1 @ e4", 2), 2).unwrap();
+    assert_eq!(&midi[10..12], &[0x00, 0x03]);
+    assert_eq!(count(&midi, b"MTrk"), 3);
+    assert_eq!(count(&midi, &[0x91, 64, 127]), 1);
+
+    let mut source: String = String::from("@ r1");
+    source += &" @ r1".repeat(9);
+    source += " @ c4";
+    let midi: Vec<u8> = export_midi(compile_for(&source, 11), 11).unwrap();
+    assert_eq!(count(&midi, b"Channel K"), 1);
+    assert_eq!(count(&midi, &[0x9B, 60, 127]), 1);
+    source += &" @ r1".repeat(5);
+    assert!(export_midi(compile_for(&source, 16), 16).is_err());
+}
+
+#[test]
+fn test_expansion() {
+    let midi: Vec<u8> = export_midi(compile("@ o4 v8 c4 [2 m1 ] @ r1 @ r1 @ r1 @ v4 d8"), 4).unwrap();
+    assert_eq!(count(&midi, &[0x90, 60, 127]), 1);
+    assert_eq!(count(&midi, &[0x90, 62, 71]), 2);
+    // Note off of the first D, 16 ticks after it started.
+    assert_eq!(count(&midi, &[16, 0x80, 62, 0]), 2);
+}
+
+#[test]
+fn test_key_range() {
+    let mut lexer: Lexer = Lexer::new("@ o9 g4 a4 @ r1 @ r1 @ r1".into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.set_target(Target { max_octave: 16, ..Target::default() });
+    let error: String = export_midi(compiler.compile().unwrap(), 4).unwrap_err().to_string();
+    assert_eq!(error, "Channel A plays MIDI key 129 at tick 32, above the highest key 127.");
+}
+
+#[test]
+fn test_tempo() {
+    let midi: Vec<u8> = export_midi(compile("@ c4 t128 c4 @ r1 @ r1 @ r1"), 4).unwrap();
+    assert_eq!(count(&midi, &[0x00, 0xFF, 0x51, 0x03, 0x09, 0x9D, 0x89]), 1);
+    assert_eq!(count(&midi, &[0x20, 0xFF, 0x51, 0x03, 0x13, 0x3B, 0x13]), 1);
+}
+
+fn midi_file(events: &[u8]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = b"MThd\x00\x00\x00\x06\x00\x00\x00\x01\x00\x60MTrk".to_vec();
+    bytes.extend((events.len() as u32 + 4).to_be_bytes());
+    bytes.extend(events);
+    bytes.extend([0x00, 0xFF, 0x2F, 0x00]);
+    bytes
+}
+
+#[test]
+fn test_round_trip() {
+    let source: &str = "@ t64 o4 v8 c4 d8 e8\n@ r2\n@ r2\n@ r2\n";
+    let midi_import: MidiImport = import_midi(&export_midi(compile(source), 4).unwrap()).unwrap();
+    assert_eq!(midi_import.song.to_string(), source);
+    assert!(midi_import.warnings.is_empty());
+}
+
+#[test]
+fn test_import() {
+    let midi: Vec<u8> = midi_file(&[
+        // C4 quarter note, then a 5 notes chord and a C8 (running status).
+        0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0,
+        0x00, 0x90, 60, 64, 0x00, 64, 64, 0x00, 67, 64, 0x00, 71, 64, 0x00, 74, 64,
+        0x30, 0x80, 60, 0, 0x00, 64, 0, 0x00, 67, 0, 0x00, 71, 0, 0x00, 74, 0,
+        0x00, 0x90, 108, 127, 0x18, 108, 0
+    ]);
+    let midi_import: MidiImport = import_midi(&midi).unwrap();
+    assert_eq!(
+        midi_import.song.to_string(),
+        "@ t51 o4 v6 c4 o5 v4 d8 v8 c16\n@ r4 o4 v4 b8 r16\n@ r4 o4 v4 g8 r16\n@ r4 o4 v4 e8 r16\n"
+    );
+    assert_eq!(midi_import.warnings, vec![
+        ImportWarning::Dropped { track: 1, tick: 96, key: 60 },
+        ImportWarning::Transposed { track: 1, tick: 144, key: 108, octaves: -3 }
+    ]);
+
+    let mut lexer: Lexer = Lexer::new(midi_import.song.to_string());
+    assert!(Compiler::new(lexer.tokenize().unwrap()).compile().is_ok());
+    assert!(import_midi(b"MThd").is_err());
+
+    // A note lasting 5 128th notes, played as a 32nd note.
+    let midi_import: MidiImport = import_midi(&midi_file(&[0x00, 0x90, 60, 100, 0x0F, 0x80, 60, 0])).unwrap();
+    assert_eq!(midi_import.song.to_string(), "@ t51 o4 v6 c32 r128\n@ r32 r128\n@ r32 r128\n@ r32 r128\n");
+    assert_eq!(midi_import.warnings, vec![ImportWarning::Truncated { track: 1, tick: 0, lost_ticks: 1 }]);
+}
@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use mmml_compiler::{
+    compiler::Compiler,
+    driver::{ChannelState, Driver},
+    lexer::Lexer,
+    optimizer::{compress, optimize, CompressionReport, OptimizationReport}
+};
+
+fn compile(source: &str) -> Vec<u8> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile().unwrap()
+}
+
+/// Notes played by each channel, tick by tick.
+fn play(data: Vec<u8>) -> Vec<Vec<(u8, u8, u8, u16)>> {
+    let mut driver: Driver = Driver::new(data).unwrap();
+    let mut ticks: Vec<Vec<(u8, u8, u8, u16)>> = Vec::new();
+    while !driver.is_finished() {
+        driver.tick().unwrap();
+        ticks.push(driver.channels().iter()
+            .map(|channel: &ChannelState| (channel.note, channel.octave, channel.volume, channel.remaining_ticks))
+            .collect());
+    }
+    ticks
+}
+
+/// Notes, octaves and volumes heard on each channel, tick by tick, as merged rests don't count their ticks the same way.
+fn sound(data: Vec<u8>) -> Vec<Vec<(u8, u8, u8)>> {
+    play(data).into_iter()
+        .map(|channels| channels.into_iter().map(|(note, octave, volume, _)| (note, octave, volume)).collect())
+        .collect()
+}
+
+#[test]
+fn test_compress() {
+    let data: Vec<u8> = compile(
+        "@ o3 c8 d8 e8 f8 g4 a4 c8 d8 e8 f8 g4 a4 r2 c8 d8 e8 f8 g4 a4 \
+        @ c16 c16 c16 c16 c16 c16 c16 c16 @ o2 e8 d8 e8 d8 e8 d8 e8 d8 c8 d8 e8 f8 g4 a4 @ r1"
+    );
+    let (compressed, report): (Vec<u8>, CompressionReport) = compress(&data, 4).unwrap();
+    assert_eq!(report.original_size, data.len());
+    assert_eq!(report.compressed_size, compressed.len());
+    assert!(report.bytes_saved() > 0);
+    assert_eq!(report.macros, 1);
+    assert_eq!(report.loops, 3);
+    assert_eq!(&compressed[0..2], &[0x00, 0x0A]);
+    assert_eq!(play(compressed), play(data));
+}
+
+#[test]
+fn test_protodome() {
+    let manifest_dir: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let source: String = std::fs::read_to_string(manifest_dir.join("test_data").join("4000ad.mmml")).unwrap();
+    let data: Vec<u8> = compile(&source);
+    let (compressed, report): (Vec<u8>, CompressionReport) = compress(&data, 4).unwrap();
+    assert!(compressed.len() <= data.len());
+    assert_eq!(report.compressed_size, compressed.len());
+    assert_eq!(play(compressed), play(data));
+}
+
+#[test]
+fn test_optimize() {
+    let data: Vec<u8> = compile(
+        "@ o4 c4 o4 d4 v6 v6 e4 r8 r8 r16 r16 [3 ] f4 < > g4 \
+        @ o3 [2 c4 o4 d4 ] o4 e4 m1 o4 c4 @ v5 m2 v5 c4 @ r1 \
+        @ e4 @ o2 c4"
+    );
+    let (optimized, report): (Vec<u8>, OptimizationReport) = optimize(&data, 4).unwrap();
+    assert_eq!(report.original_size, data.len());
+    assert_eq!(report.optimized_size, optimized.len());
+    assert_eq!(report.state_commands, 7);
+    assert_eq!(report.merged_rests, 3);
+    assert_eq!(report.empty_loops, 1);
+    assert_eq!(report.bytes_saved(), 7 + 3 + 3);
+    assert_eq!(&optimized[12..21], &[0xD3, 0x12, 0x32, 0xE3, 0x52, 0x09, 0x62, 0x82, 0xFF]);
+    assert_eq!(sound(optimized), sound(data));
+}
+
+#[test]
+fn test_optimize_protodome() {
+    let manifest_dir: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let source: String = std::fs::read_to_string(manifest_dir.join("test_data").join("4000ad.mmml")).unwrap();
+    let data: Vec<u8> = compile(&source);
+    let (optimized, report): (Vec<u8>, OptimizationReport) = optimize(&data, 4).unwrap();
+    assert_eq!(report.optimized_size, optimized.len());
+    assert!(optimized.len() <= data.len());
+    assert_eq!(sound(optimized.clone()), sound(data));
+    let (compressed, _): (Vec<u8>, CompressionReport) = compress(&optimized, 4).unwrap();
+    assert_eq!(sound(compressed), sound(optimized));
+}
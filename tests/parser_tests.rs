@@ -0,0 +1,89 @@
+use mmml_compiler::{
+    ast::{Duration, Item, Pitch, Song, Spanned},
+    compiler::Compiler,
+    diagnostic::{DiagnosticCode, Span},
+    lexer::Lexer,
+    parser::Parser
+};
+
+fn parse(source: &str) -> Song {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut parser: Parser = Parser::new(lexer.tokenize().unwrap());
+    parser.parse().unwrap()
+}
+
+#[test]
+fn test_song() {
+    let song: Song = parse("@ t120 o3 c4. d# [2 r8 > ] @ v5 m1 @ @ @ e16");
+    assert_eq!(song.channels.len(), 4);
+    assert_eq!(song.macros.len(), 1);
+    assert_eq!(song.channels[0].items, vec![
+        Item::Tempo { tempo: 120, span: Span::new(1, 2, 2, 4) },
+        Item::Octave { octave: Spanned::new(3, Span::new(1, 8, 8, 1)), span: Span::new(1, 7, 7, 2) },
+        Item::Note { pitch: Pitch::C, duration: Some(Duration::QUARTER.dotted()), span: Span::new(1, 10, 10, 3) },
+        Item::Note { pitch: Pitch::DSharp, duration: None, span: Span::new(1, 14, 14, 2) },
+        Item::Loop {
+            times: 2,
+            items: vec![
+                Item::Rest { duration: Some(Duration::EIGHTH), span: Span::new(1, 20, 20, 2) },
+                Item::OctaveUp { span: Span::new(1, 23, 23, 1) }
+            ],
+            span: Span::new(1, 17, 17, 2)
+        }
+    ]);
+    assert_eq!(song.macros[0].items, vec![
+        Item::Note { pitch: Pitch::E, duration: Some(Duration::SIXTEENTH), span: Span::new(1, 41, 41, 3) }
+    ]);
+}
+
+#[test]
+fn test_channels() {
+    let mut lexer: Lexer = Lexer::new("@ c @ d @ e".into());
+    let mut parser: Parser = Parser::new(lexer.tokenize().unwrap());
+    parser.set_channels(2);
+    let song: Song = parser.parse().unwrap();
+    assert_eq!(song.channels.len(), 2);
+    assert_eq!(song.macros.len(), 1);
+    assert_eq!(song.sections().count(), 3);
+}
+
+#[test]
+fn test_errors() {
+    let mut lexer: Lexer = Lexer::new("@ c3 x [2 d @ o9 @ @".into());
+    let mut parser: Parser = Parser::new(lexer.tokenize().unwrap());
+    let codes: Vec<DiagnosticCode> = parser.parse().unwrap_err().iter().map(|error| error.code).collect();
+    assert_eq!(codes, vec![
+        DiagnosticCode::InvalidDuration,
+        DiagnosticCode::UnknownCommand,
+        DiagnosticCode::UnclosedLoopAtChannelEnd
+    ]);
+}
+
+#[test]
+fn test_compile_song() {
+    let source: &str = "@ o4 c4 < c8 < < [3 d ] @ r @ @ @ a";
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    let expected: Vec<u8> = compiler.compile().unwrap();
+    let mut compiler: Compiler = Compiler::new(Vec::new());
+    assert_eq!(compiler.compile_song(&parse(source)).unwrap(), expected);
+    assert_eq!(&expected[10..20], &[0xD3, 0x12, 0xD2, 0x13, 0xD0, 0xF0, 0x03, 0x33, 0xF1, 0xFF]);
+}
+
+#[test]
+fn test_named_macros() {
+    let source: &str = "@ m(lead) m2 @ r @ r @ r\n@lead: c8 e8\n@bass: < c\n";
+    let song: Song = parse(source);
+    assert_eq!(song.macros[0].name, Some(Spanned::new("lead".to_string(), Span::new(2, 1, 26, 5))));
+    assert_eq!(song.channels[0].items[0], Item::NamedMacroCall {
+        name: Spanned::new("lead".to_string(), Span::new(1, 3, 3, 6)),
+        span: Span::new(1, 2, 2, 7)
+    });
+    assert_eq!(song.to_string(), "@ m(lead) m2\n@ r\n@ r\n@ r\n@lead: c8 e8\n@bass: < c\n");
+
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    let bytes: Vec<u8> = compiler.compile().unwrap();
+    assert_eq!(&bytes[12..17], &[0xF2, 0x00, 0xF2, 0x01, 0xFF]);
+    assert!(compiler.warnings().is_empty());
+}
@@ -0,0 +1,34 @@
+use mmml_compiler::{compiler::Compiler, lexer::Lexer, player::{write_wav, Player}};
+
+fn compile(source: &str) -> Vec<u8> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile().unwrap()
+}
+
+#[test]
+fn test_song_length() {
+    // 256 samples per tick at tempo 16, a quarter note lasts 32 ticks.
+    let mut player: Player = Player::new(compile("@ t16 c4 [2 r8 ] @ @ @"), 52000).unwrap();
+    let samples: Vec<u8> = player.render(None).unwrap();
+    assert_eq!(samples.len(), 64 * 256);
+    assert!(samples[..32 * 256].iter().any(|&sample| sample != samples[0]));
+    assert!(samples[32 * 256..].iter().all(|&sample| sample == samples[32 * 256]));
+}
+
+#[test]
+fn test_max_seconds() {
+    let mut player: Player = Player::new(compile("@ t255 c1 @ @ @"), 8000).unwrap();
+    assert_eq!(player.render(Some(1)).unwrap().len(), 8000);
+}
+
+#[test]
+fn test_wav_header() {
+    let mut wav: Vec<u8> = Vec::new();
+    write_wav(&mut wav, &[0x40, 0xC0], 44100).unwrap();
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 44100);
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(wav.len(), 46);
+}
@@ -0,0 +1,71 @@
+use mmml_compiler::{
+    compiler::Compiler,
+    diagnostic::{Diagnostic, DiagnosticCode, Span},
+    lexer::Lexer,
+    preprocessor::{Preprocessor, MAX_EXPANDED_TOKENS},
+    token::Token
+};
+
+fn compile(source: &str) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile()
+}
+
+#[test]
+fn test_constants() {
+    let source: &str = "$KICK = o1 v8 c64\n$PREAMBLE = o4 v5\n@ $PREAMBLE c4 $KICK [2 $KICK ] @ r @ r @ r";
+    let expected: Vec<u8> = compile("@ o4 v5 c4 o1 v8 c64 [2 o1 v8 c64 ] @ r @ r @ r").unwrap();
+    assert_eq!(compile(source).unwrap(), expected);
+
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let (tokens, errors): (Vec<Token>, Vec<Diagnostic>) = Preprocessor::new().preprocess(&lexer.tokenize().unwrap());
+    assert!(errors.is_empty());
+    assert_eq!(tokens[1].value, "o");
+    assert_eq!((tokens[1].line, tokens[1].column), (2, 12));
+    assert_eq!((tokens[7].line, tokens[7].column), (1, 8));
+}
+
+#[test]
+fn test_snippets() {
+    let source: &str = "$ARP($root, $third) = $root 16 $third 16 $root 16\n\
+        $CHORD($note) = $ARP($note, e) > $ARP($note, g) <\n\
+        @ o3 $CHORD(c) $ARP(d, f#) @ r @ r @ r";
+    let expected: Vec<u8> = compile("@ o3 c16 e16 c16 > c16 g16 c16 < d16 f#16 d16 @ r @ r @ r").unwrap();
+    assert_eq!(compile(source).unwrap(), expected);
+}
+
+#[test]
+fn test_errors() {
+    let codes = |source: &str| -> Vec<DiagnosticCode> {
+        compile(source).unwrap_err().iter().map(|error| error.code).collect()
+    };
+    assert_eq!(codes("@ $KICK @ @ @"), vec![DiagnosticCode::UnknownDefinition]);
+    assert_eq!(codes("$A = c $B\n$B = $A\n@ $A @ @ @"), vec![DiagnosticCode::InvalidDefinition]);
+    assert_eq!(codes("$A($n) = $n\n@ $A(c, d) @ @ @"), vec![DiagnosticCode::InvalidDefinition]);
+    assert_eq!(codes("$A = c\n$A = d\n@ $A @ @ @"), vec![DiagnosticCode::InvalidDefinition]);
+
+    let errors: Vec<Diagnostic> = compile("$LOW = o9 c\n@ $LOW @ @ @").unwrap_err();
+    assert_eq!(errors[0].code, DiagnosticCode::InvalidOctave);
+    assert_eq!(errors[0].span, Span::new(1, 8, 8, 1));
+}
+
+#[test]
+fn test_expansion_limit() {
+    let mut source: String = String::from("$D0 = c\n");
+    for depth in 1..=20 {
+        source += &format!("$D{} = $D{} $D{}\n", depth, depth - 1, depth - 1);
+    }
+    source += "@ $D20 @ @ @";
+    let tokens: Vec<Token> = Lexer::new(source.clone()).tokenize().unwrap();
+    let (_, errors): (Vec<Token>, Vec<Diagnostic>) = Preprocessor::new().preprocess(&tokens);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, DiagnosticCode::InvalidDefinition);
+    assert!(errors[0].message.contains(&MAX_EXPANDED_TOKENS.to_string()));
+    assert_eq!(errors[0].labels[0].message, "expanded from here");
+    assert_eq!(errors[0].labels[0].span, Span::new(22, 2, source.len() - 10, 4));
+
+    let errors: Vec<Diagnostic> = compile("$A = $B\n@ $A @ @ @").unwrap_err();
+    assert_eq!(errors[0].code, DiagnosticCode::UnknownDefinition);
+    assert_eq!(errors[0].labels[0].span, Span::new(2, 2, 10, 2));
+}
@@ -0,0 +1,50 @@
+use mmml_compiler::{compiler::Compiler, diagnostic::{Diagnostic, DiagnosticCode}, lexer::Lexer, target::{ExtendedCommand, Target}};
+
+fn compile(source: &str, target: Target) -> (Result<Vec<u8>, Vec<Diagnostic>>, Vec<Diagnostic>) {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.set_target(target);
+    let result: Result<Vec<u8>, Vec<Diagnostic>> = compiler.compile();
+    (result, compiler.warnings().to_vec())
+}
+
+#[test]
+fn test_builtin_targets() {
+    let source: &str = "@ c4 & c4 k2 @ s @ @";
+    let (result, warnings) = compile(source, Target::protodome_avr());
+    let codes: Vec<DiagnosticCode> = result.unwrap_err().iter().map(|error| error.code).collect();
+    assert_eq!(codes, vec![DiagnosticCode::UnsupportedCommand; 3]);
+    assert!(warnings.is_empty());
+
+    let (result, warnings) = compile(source, Target::extended());
+    assert!(result.is_ok());
+    assert!(warnings.is_empty());
+
+    let (result, warnings) = compile(source, Target::default());
+    assert!(result.is_ok());
+    assert_eq!(warnings.len(), 3);
+}
+
+#[test]
+fn test_custom_target() {
+    let target: Target = Target::from_toml(
+        "name = \"fork\"\nextended_commands = [\"tie\"]\nmax_octave = 6\nchannels = 2\nmax_data_size = 18"
+    ).unwrap();
+    assert_eq!(target.supports(ExtendedCommand::Tie), Some(true));
+    assert_eq!(target.supports(ExtendedCommand::Stop), Some(false));
+    assert_eq!(target.min_octave, 1);
+
+    let (result, _) = compile("@ o6 c4 & c4 @ m1 @ c4", target.clone());
+    assert_eq!(result.unwrap()[6], 0xD5);
+
+    let (result, _) = compile("@ o6 c4 c4 c4 c4 c4 c4 c4 c4 @ @", target);
+    assert_eq!(result.unwrap_err()[0].code, DiagnosticCode::ProgramTooLarge);
+
+    assert!(Target::from_toml("max_octave = 17").is_err());
+    assert!(Target::from_toml("octaves = 3").is_err());
+    assert!(Target::from_toml("channels = 255").is_err());
+    assert!(Target::from_toml("channels = 254").is_ok());
+
+    let (result, _) = compile("@ c4", Target { channels: 255, ..Target::default() });
+    assert_eq!(result.unwrap_err()[0].code, DiagnosticCode::NotEnoughHeaders);
+}
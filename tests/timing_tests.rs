@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use mmml_compiler::{
+    ast::Song,
+    compiler::Compiler,
+    diagnostic::{Diagnostic, DiagnosticCode, Span},
+    driver::Driver,
+    lexer::Lexer,
+    target::Target,
+    timing::{channel_name, channel_timings, check_timing, BarPosition, ChannelTiming, TimeSignature}
+};
+
+type TimeSignatures = Vec<Vec<(u64, TimeSignature)>>;
+
+fn compile(source: &str) -> (Song, Vec<u8>, TimeSignatures) {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    let (song, _): (Song, Vec<Diagnostic>) = compiler.parse();
+    let data: Vec<u8> = compiler.compile().unwrap();
+    (song, data, compiler.time_signatures().to_vec())
+}
+
+#[test]
+fn test_bar_position() {
+    let common_time: TimeSignature = TimeSignature::default();
+    assert_eq!(BarPosition::from_ticks(0, common_time), BarPosition { bar: 1, beat: 1, tick: 0 });
+    assert_eq!(BarPosition::from_ticks(128 * 4 + 32 * 2 + 5, common_time), BarPosition { bar: 5, beat: 3, tick: 5 });
+    assert_eq!(BarPosition::from_ticks(128 * 4 + 32 * 2 + 5, common_time).to_string(), "bar 5, beat 3 (+5 ticks)");
+
+    let waltz: TimeSignature = TimeSignature { beats: 3, unit: 4 };
+    assert_eq!(BarPosition::from_ticks(96 * 4 + 32 * 2 + 5, waltz), BarPosition { bar: 5, beat: 3, tick: 5 });
+    assert_eq!(BarPosition::from_ticks(96, waltz), BarPosition { bar: 2, beat: 1, tick: 0 });
+    let six_eight: TimeSignature = TimeSignature { beats: 6, unit: 8 };
+    assert_eq!(BarPosition::from_ticks(96 + 16 * 5, six_eight), BarPosition { bar: 2, beat: 6, tick: 0 });
+
+    let changes: [(u64, TimeSignature); 2] = [(0, waltz), (96 * 2, common_time)];
+    assert_eq!(BarPosition::from_ticks_with_changes(96, &changes), BarPosition { bar: 2, beat: 1, tick: 0 });
+    assert_eq!(BarPosition::from_ticks_with_changes(96 * 2 + 128 + 32, &changes), BarPosition { bar: 4, beat: 2, tick: 0 });
+    assert_eq!(BarPosition::from_ticks_with_changes(64 + 96, &[(64, waltz)]), BarPosition { bar: 3, beat: 1, tick: 0 });
+    assert_eq!(BarPosition::from_ticks_with_changes(128, &[]), BarPosition::from_ticks(128, common_time));
+}
+
+#[test]
+fn test_channel_names() {
+    assert_eq!(channel_name(0), "A");
+    assert_eq!(channel_name(25), "Z");
+    assert_eq!(channel_name(26), "27");
+}
+
+#[test]
+fn test_timing() {
+    let (song, data, time_signatures): (Song, Vec<u8>, TimeSignatures) = compile("@ [4 c4 d ] m1 @ c1 [2 r2. ] @ c2 s @ @ [0 c4 ] e8.");
+    let timings: Vec<ChannelTiming> = channel_timings(&data, &Target::default()).unwrap();
+    assert_eq!(timings, vec![
+        ChannelTiming { ticks: 4 * 64 + 256 * 32 + 24, stopped: false },
+        ChannelTiming { ticks: 128 + 96 * 2, stopped: false },
+        ChannelTiming { ticks: 64, stopped: true },
+        ChannelTiming { ticks: 0, stopped: false }
+    ]);
+    let warnings: Vec<Diagnostic> = check_timing(&song, &timings, &time_signatures);
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings.iter().all(|warning| warning.code == DiagnosticCode::UnsynchronizedChannels));
+    assert_eq!(warnings[0].message, "Channel B ends at bar 3, beat 3 but channel A ends at bar 67, beat 1 (+24 ticks)");
+    assert_eq!(warnings[0].span, Span::new(1, 15, 15, 1));
+
+    let (song, data, time_signatures): (Song, Vec<u8>, TimeSignatures) = compile("@ #time 3/4 c2. | c4 @ #time 3/4 c2. | @ @");
+    let warnings: Vec<Diagnostic> = check_timing(&song, &channel_timings(&data, &Target::default()).unwrap(), &time_signatures);
+    assert_eq!(warnings[0].message, "Channel B ends at bar 2, beat 1 but channel A ends at bar 2, beat 2");
+
+    let (song, data, time_signatures): (Song, Vec<u8>, TimeSignatures) = compile("@ c1 | #time 3/4 c2. | c4 @ c1 | c2. @ @");
+    assert_eq!(time_signatures[0], vec![(128, TimeSignature { beats: 3, unit: 4 })]);
+    let warnings: Vec<Diagnostic> = check_timing(&song, &channel_timings(&data, &Target::default()).unwrap(), &time_signatures);
+    assert_eq!(warnings[0].message, "Channel B ends at bar 2, beat 4 but channel A ends at bar 3, beat 2");
+}
+
+#[test]
+fn test_driver() {
+    let manifest_dir: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let source: String = std::fs::read_to_string(manifest_dir.join("test_data").join("4000ad.mmml")).unwrap();
+    let (song, data, time_signatures): (Song, Vec<u8>, TimeSignatures) = compile(&source);
+    let timings: Vec<ChannelTiming> = channel_timings(&data, &Target::default()).unwrap();
+    let mut driver: Driver = Driver::new(data).unwrap();
+    let mut ends: [Option<u64>; 4] = [None; 4];
+    while !driver.is_finished() {
+        driver.tick().unwrap();
+        for (channel, end) in ends.iter_mut().enumerate() {
+            if end.is_none() && driver.channels()[channel].is_finished() {
+                *end = Some(driver.ticks() - 1);
+            }
+        }
+    }
+    let expected: Vec<u64> = timings.iter().map(|timing| timing.ticks).collect();
+    assert_eq!(ends.map(Option::unwrap).to_vec(), expected);
+    assert!(check_timing(&song, &timings, &time_signatures).is_empty());
+}
+
+fn compile_bars(source: &str) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile()
+}
+
+#[test]
+fn test_bar_lines() {
+    assert_eq!(compile_bars("@ c4 d e f | g1 | @ @ @"), compile_bars("@ c4 d e f g1 @ @ @"));
+    assert!(compile_bars("@ #time 3/4 c4 d e | f2. | g4. a8 b4 | @ #time 6/8 c4. d8 e f | @ @").is_ok());
+    assert!(compile_bars("@ [2 c2 d | ] [2 e8 f ] [2 g4 ] | @ m1 c2 | @ @ @ d2").is_ok());
+
+    let errors: Vec<Diagnostic> = compile_bars("@ c4 d | e1 | @ c1 | #time 3/4 d1 | @ @").unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().all(|error| error.code == DiagnosticCode::InvalidBarLength));
+    assert_eq!(errors[0].message, "Bar 1 of channel A lasts 64 ticks instead of 128");
+    assert_eq!(errors[0].span, Span::new(1, 7, 7, 1));
+    assert_eq!(errors[1].message, "Bar 2 of channel B lasts 128 ticks instead of 96");
+    assert_eq!(errors[1].help.as_deref(), Some("Bars of 3/4 last 96 ticks, a whole note lasting 128 ticks."));
+
+    let errors: Vec<Diagnostic> = compile_bars("@ [3 c1 | ] d2 | @ @ @ m(riff) @riff: c4 | d8").unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].message, "Bar 4 of channel A lasts 64 ticks instead of 128");
+    assert_eq!(errors[1].message, "Bar 1 of macro riff lasts 32 ticks instead of 128");
+}
+
+#[test]
+fn test_time_signature() {
+    let errors: Vec<Diagnostic> = compile_bars("@ #time 0/4 c4 @ #time 3/5 @ @").unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().all(|error| error.code == DiagnosticCode::InvalidTimeSignature));
+    assert_eq!(errors[1].message, "Invalid time signature 3/5");
+}
+
+#[test]
+fn test_nested_macro_call() {
+    let errors: Vec<Diagnostic> = compile_bars("@ m1 @ r1 @ r1 @ r1 @ m2 c4 @ d4").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, DiagnosticCode::NestedMacroCall);
+    assert_eq!(errors[0].span, Span::new(1, 22, 22, 2));
+    assert!(compile_bars("@ m1 @ r1 @ r1 @ r1 @named: m(other) @other: d4").is_err());
+
+    let compile_for = |source: &str, target: Target| -> Result<Vec<u8>, Vec<Diagnostic>> {
+        let mut lexer: Lexer = Lexer::new(source.into());
+        let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+        compiler.set_target(target);
+        compiler.compile()
+    };
+    let data: Vec<u8> = compile_for("@ m1 @ r1 @ r1 @ r1 @ m2 c4 @ d4", Target::extended()).unwrap();
+    assert_eq!(channel_timings(&data, &Target::extended()).unwrap()[0].ticks, 64);
+    assert!(channel_timings(&data, &Target::default()).is_err());
+    assert!(compile_for("@ m1 | @ r1 @ r1 @ r1 @ m2 c4 @ d4 c2", Target::extended()).is_ok());
+    let errors: Vec<Diagnostic> = compile_for("@ m1 @ r1 @ r1 @ r1 @ c4 m1", Target::extended()).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, DiagnosticCode::NestedMacroCall);
+    let flat: Target = Target { max_macro_depth: 0, ..Target::default() };
+    assert!(compile_for("@ m1 @ r1 @ r1 @ r1 @ c4", flat).is_err());
+}
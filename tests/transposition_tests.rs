@@ -0,0 +1,55 @@
+use mmml_compiler::{
+    ast::{Item, Song},
+    compiler::Compiler,
+    diagnostic::{Diagnostic, DiagnosticCode},
+    lexer::Lexer,
+    parser::Parser
+};
+
+fn compile(source: &str) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let mut lexer: Lexer = Lexer::new(source.into());
+    let mut compiler: Compiler = Compiler::new(lexer.tokenize().unwrap());
+    compiler.compile()
+}
+
+fn channel_bytes(source: &str) -> Vec<u8> {
+    let bytes: Vec<u8> = compile(source).unwrap();
+    let end: usize = bytes.iter().skip(8).position(|&byte| byte == 0xFF).unwrap() + 8;
+    bytes[8..end].to_vec()
+}
+
+#[test]
+fn test_parse() {
+    let mut lexer: Lexer = Lexer::new("@ {-3 c [2 d ] {+12 e } } @ @ @".into());
+    let mut parser: Parser = Parser::new(lexer.tokenize().unwrap());
+    let song: Song = parser.parse().unwrap();
+    assert!(matches!(&song.channels[0].items[0], Item::Transposition { semitones, items, .. } if semitones.value == -3 && items.len() == 3));
+    assert_eq!(song.channels[0].to_string(), "@ {-3 c [2 d ] {+12 e } }");
+}
+
+#[test]
+fn test_transposition() {
+    assert_eq!(channel_bytes("@ o4 c4 {+2 c d e } f @ @ @"), vec![
+        0xD3, 0x12, 0xD3, 0x32, 0x52, 0x72, 0x62
+    ]);
+    assert_eq!(channel_bytes("@ o4 {+1 b4 c } c @ @ @"), vec![
+        0xD3, 0xD4, 0x12, 0xD3, 0x22, 0x12
+    ]);
+    assert_eq!(channel_bytes("@ o4 {-12 c4 > c < [2 c d ] } c @ @ @"), vec![
+        0xD3, 0xD2, 0x12, 0xD3, 0x12, 0xF0, 0x02, 0xD2, 0x12, 0x32, 0xF1, 0xD3, 0x12
+    ]);
+    assert_eq!(channel_bytes("@ o4 {+12 c4 [2 r ] } c @ @ @"), vec![
+        0xD3, 0xD4, 0x12, 0xF0, 0x02, 0x02, 0xF1, 0xD3, 0x12
+    ]);
+}
+
+#[test]
+fn test_errors() {
+    let codes = |source: &str| -> Vec<DiagnosticCode> {
+        compile(source).unwrap_err().iter().map(|error| error.code).collect()
+    };
+    assert_eq!(codes("@ o5 {+12 c } @ @ @"), vec![DiagnosticCode::TransposedNoteOutOfRange]);
+    assert_eq!(codes("@ o1 {-1 c } @ @ @"), vec![DiagnosticCode::TransposedNoteOutOfRange]);
+    assert_eq!(codes("@ {+1 m1 } @ @ @ @ c"), vec![DiagnosticCode::UntransposableCommand]);
+    assert_eq!(codes("@ {+1 c @ @ @"), vec![DiagnosticCode::UnclosedTransposition]);
+}